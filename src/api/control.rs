@@ -0,0 +1,308 @@
+use hyper::{Body, Response};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::api::{ApiError, HttpApi};
+use crate::client::dispatcher::{DeviceAction, DeviceActionMessage};
+use crate::config::bindings::RoomAction;
+use crate::config::caseta_remote::{ButtonId, RemoteId};
+
+impl HttpApi {
+    /// `POST /rooms/{room_name}/actions/{action}`. `action` is a room action's `type`, and an
+    /// optional json body fills in the rest of it, like `{"scene": "bright"}` for `recall_scene`
+    pub(super) async fn room_action(
+        &self,
+        room_name: &str,
+        action: &str,
+        body: &[u8],
+    ) -> Result<Response<Body>, ApiError> {
+        let mut fields = match body.iter().all(u8::is_ascii_whitespace) {
+            true => Map::new(),
+            false => serde_json::from_slice(body).map_err(|e| {
+                ApiError::BadRequest(format!("the body should be a json object: {}", e))
+            })?,
+        };
+        fields.insert("type".to_string(), Value::from(action));
+        let room_action: RoomAction = serde_json::from_value(Value::Object(fields))
+            .map_err(|e| ApiError::BadRequest(format!("{} isn't a room action: {}", action, e)))?;
+
+        let room = self
+            .topology
+            .room_named(room_name)
+            .ok_or_else(|| ApiError::NotFound(format!("there's no room named {}", room_name)))?;
+        if let RoomAction::RecallScene { scene } = &room_action {
+            if !room
+                .scenes
+                .iter()
+                .any(|room_scene| &room_scene.name == scene)
+            {
+                return Err(ApiError::NotFound(format!(
+                    "room {} has no scene named {}",
+                    room.name, scene
+                )));
+            }
+        }
+        self.send(DeviceActionMessage::room_action(&room.name, room_action))
+            .await
+    }
+
+    /// `POST /remotes/{remote_id}/buttons/{button_id}/{device_action}`, exactly as if the button
+    /// had been pressed
+    pub(super) async fn button_press(
+        &self,
+        remote_id: &str,
+        button_id: &str,
+        device_action: &str,
+    ) -> Result<Response<Body>, ApiError> {
+        let remote_id: RemoteId = remote_id
+            .parse()
+            .map_err(|_| ApiError::BadRequest(format!("{} isn't a remote id", remote_id)))?;
+        let button_id: ButtonId = parse_name(button_id, "button")?;
+        let device_action: DeviceAction = parse_name(device_action, "device action")?;
+
+        let topology = self.topology.current();
+        let (remote, _room) = topology
+            .get(&remote_id)
+            .ok_or_else(|| ApiError::NotFound(format!("there's no remote {}", remote_id)))?;
+        if !remote.has_button(&button_id) {
+            return Err(ApiError::NotFound(format!(
+                "remote {} doesn't have a {} button",
+                remote_id, button_id
+            )));
+        }
+        self.send(DeviceActionMessage::new(
+            device_action,
+            remote_id,
+            button_id,
+        ))
+        .await
+    }
+}
+
+// the snake_case names from the configuration files
+fn parse_name<T: DeserializeOwned>(name: &str, kind: &str) -> Result<T, ApiError> {
+    serde_json::from_value(Value::from(name))
+        .map_err(|_| ApiError::BadRequest(format!("{} isn't a {}", name, kind)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use hyper::{header, Body, Method, Request, StatusCode};
+    use spectral::prelude::*;
+    use tokio::sync::mpsc;
+
+    use crate::api::HttpApi;
+    use crate::caseta::remote::RemoteWatchers;
+    use crate::client::dispatcher::{DeviceAction, DeviceActionMessage};
+    use crate::client::room_state::new_cache;
+    use crate::config::bindings::RoomAction;
+    use crate::config::caseta_remote::{ButtonId, CasetaRemote};
+    use crate::config::scene::{Room, Scene, SharedTopology};
+
+    fn api() -> (HttpApi, mpsc::Receiver<DeviceActionMessage>) {
+        let room = Room {
+            name: "Living Room".to_string(),
+            room_id: uuid::Uuid::new_v4(),
+            grouped_light_room_id: uuid::Uuid::new_v4(),
+            scenes: vec![Scene {
+                name: "bright".to_string(),
+                devices: vec![],
+            }],
+            remotes: vec![2],
+            bindings: vec![],
+        };
+        let remote = CasetaRemote::TwoButtonPico {
+            id: 2,
+            name: "living room pico".to_string(),
+        };
+        let topology = SharedTopology::new(HashMap::from([(2, (remote, room))]));
+        let (action_sender, action_receiver) = mpsc::channel(4);
+        (
+            HttpApi::new(
+                topology,
+                action_sender,
+                Arc::new(new_cache()),
+                RemoteWatchers::default(),
+            ),
+            action_receiver,
+        )
+    }
+
+    fn post(path: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::POST)
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_sends_button_presses_to_the_dispatcher() {
+        let (api, mut action_receiver) = api();
+
+        let response = api
+            .handle(post(
+                "/remotes/2/buttons/power_on/single_press_complete",
+                "",
+            ))
+            .await;
+
+        assert_that(&response.status()).is_equal_to(StatusCode::ACCEPTED);
+        assert_that(&action_receiver.try_recv().unwrap()).is_equal_to(DeviceActionMessage::new(
+            DeviceAction::SinglePressComplete,
+            2,
+            ButtonId::PowerOn,
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_sends_room_actions_to_the_dispatcher() {
+        let (api, mut action_receiver) = api();
+
+        let response = api
+            .handle(post(
+                "/rooms/living%20room/actions/recall_scene",
+                r#"{"scene": "bright"}"#,
+            ))
+            .await;
+
+        assert_that(&response.status()).is_equal_to(StatusCode::ACCEPTED);
+        assert_that(&action_receiver.try_recv().unwrap()).is_equal_to(
+            DeviceActionMessage::room_action(
+                "Living Room",
+                RoomAction::RecallScene {
+                    scene: "bright".to_string(),
+                },
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_requests_it_cant_act_on() {
+        let (api, mut action_receiver) = api();
+        let mut statuses = vec![];
+        for (method, path, body) in [
+            (Method::POST, "/rooms/Office/actions/turn_on", ""),
+            (Method::POST, "/rooms/Living%20Room/actions/explode", ""),
+            (
+                Method::POST,
+                "/rooms/Living%20Room/actions/recall_scene",
+                r#"{"scene": "dim"}"#,
+            ),
+            (
+                Method::POST,
+                "/remotes/2/buttons/favorite/single_press_complete",
+                "",
+            ),
+            (
+                Method::POST,
+                "/remotes/pico/buttons/power_on/single_press_complete",
+                "",
+            ),
+            (
+                Method::GET,
+                "/remotes/2/buttons/power_on/single_press_complete",
+                "",
+            ),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            statuses.push(api.handle(request).await.status());
+        }
+
+        assert_that(&statuses).is_equal_to(vec![
+            StatusCode::NOT_FOUND,
+            StatusCode::BAD_REQUEST,
+            StatusCode::NOT_FOUND,
+            StatusCode::NOT_FOUND,
+            StatusCode::BAD_REQUEST,
+            StatusCode::METHOD_NOT_ALLOWED,
+        ]);
+        assert_that(&action_receiver.try_recv().is_err()).is_true();
+    }
+
+    #[tokio::test]
+    async fn it_rejects_bodies_that_are_too_large() {
+        let (api, mut action_receiver) = api();
+        let oversized = format!(r#"{{"scene": "{}"}}"#, "a".repeat(8 * 1024));
+
+        let with_length = api
+            .handle(post(
+                "/rooms/Living%20Room/actions/recall_scene",
+                &oversized,
+            ))
+            .await;
+        // a chunked body doesn't say how long it is up front
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..8 {
+                if sender.send_data("a".repeat(1024).into()).await.is_err() {
+                    return;
+                }
+            }
+        });
+        let chunked = api
+            .handle(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/rooms/Living%20Room/actions/recall_scene")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await;
+
+        assert_that(&with_length.status()).is_equal_to(StatusCode::PAYLOAD_TOO_LARGE);
+        assert_that(&chunked.status()).is_equal_to(StatusCode::PAYLOAD_TOO_LARGE);
+        assert_that(&action_receiver.try_recv().is_err()).is_true();
+    }
+
+    #[tokio::test]
+    async fn it_rejects_posts_a_browser_could_send_from_another_site() {
+        let (api, mut action_receiver) = api();
+        let path = "/remotes/2/buttons/power_on/single_press_complete";
+        let mut statuses = vec![];
+        for headers in [
+            vec![],
+            vec![(header::CONTENT_TYPE, "text/plain")],
+            vec![(header::CONTENT_TYPE, "application/x-www-form-urlencoded")],
+            vec![
+                (header::CONTENT_TYPE, "application/json"),
+                (header::ORIGIN, "https://example.com"),
+            ],
+        ] {
+            let mut request = Request::builder().method(Method::POST).uri(path);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            statuses.push(
+                api.handle(request.body(Body::empty()).unwrap())
+                    .await
+                    .status(),
+            );
+        }
+        let with_charset = Request::builder()
+            .method(Method::POST)
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(Body::empty())
+            .unwrap();
+
+        assert_that(&statuses).is_equal_to(vec![
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            StatusCode::FORBIDDEN,
+        ]);
+        assert_that(&action_receiver.try_recv().is_err()).is_true();
+        assert_that(&api.handle(with_charset).await.status()).is_equal_to(StatusCode::ACCEPTED);
+    }
+}
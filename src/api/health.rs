@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_derive::Serialize;
+
+use crate::caseta::connection::ConnectionStats;
+use crate::client::hue::HueClient;
+use crate::config::auth_configuration::HealthCheckConfiguration;
+
+/// What `/healthz` and `/readyz` look at.
+///
+/// The caseta connection manager only wakes up when the main loop asks it for the next message,
+/// so its last activity doubles as a heartbeat for the main loop.
+#[derive(Debug, Clone)]
+pub struct HealthChecks {
+    connection_stats: Arc<ConnectionStats>,
+    hue_client: HueClient,
+    configuration: HealthCheckConfiguration,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+    pub healthy: bool,
+    checks: BTreeMap<&'static str, CheckResult>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    healthy: bool,
+    detail: String,
+}
+
+impl CheckResult {
+    fn healthy(detail: String) -> Self {
+        CheckResult {
+            healthy: true,
+            detail,
+        }
+    }
+
+    fn unhealthy(detail: String) -> Self {
+        CheckResult {
+            healthy: false,
+            detail,
+        }
+    }
+}
+
+impl HealthReport {
+    fn new(checks: BTreeMap<&'static str, CheckResult>) -> Self {
+        HealthReport {
+            healthy: checks.values().all(|check| check.healthy),
+            checks,
+        }
+    }
+}
+
+impl HealthChecks {
+    pub fn new(
+        connection_stats: Arc<ConnectionStats>,
+        hue_client: HueClient,
+        configuration: HealthCheckConfiguration,
+    ) -> Self {
+        HealthChecks {
+            connection_stats,
+            hue_client,
+            configuration,
+        }
+    }
+
+    /// `/healthz`: is the main loop still turning over
+    pub fn liveness(&self) -> HealthReport {
+        HealthReport::new(BTreeMap::from([("caseta_loop", self.caseta_loop())]))
+    }
+
+    /// `/readyz`: can a button press actually turn into light right now
+    pub async fn readiness(&self) -> HealthReport {
+        HealthReport::new(BTreeMap::from([
+            ("caseta_loop", self.caseta_loop()),
+            ("caseta_connection", self.caseta_connection()),
+            ("hue_bridge", self.hue_bridge().await),
+        ]))
+    }
+
+    fn caseta_loop(&self) -> CheckResult {
+        let timeout = self.configuration.liveness_timeout();
+        match self.connection_stats.since_last_activity() {
+            Some(since) if since <= timeout => {
+                CheckResult::healthy(format!("last active {} ago", seconds(since)))
+            }
+            Some(since) => CheckResult::unhealthy(format!(
+                "hasn't done anything for {}, which is longer than {}",
+                seconds(since),
+                seconds(timeout)
+            )),
+            None => CheckResult::unhealthy("hasn't started yet".to_string()),
+        }
+    }
+
+    fn caseta_connection(&self) -> CheckResult {
+        let max_age = self.configuration.keep_alive_max_age();
+        if !self.connection_stats.is_logged_in() {
+            return CheckResult::unhealthy("not logged in to the caseta hub".to_string());
+        }
+        match self.connection_stats.since_last_message() {
+            Some(since) if since <= max_age => CheckResult::healthy(format!(
+                "logged in. the hub last sent a message {} ago",
+                seconds(since)
+            )),
+            Some(since) => CheckResult::unhealthy(format!(
+                "the hub last sent a message {} ago, which is longer than {}",
+                seconds(since),
+                seconds(max_age)
+            )),
+            None => CheckResult::unhealthy("the hub hasn't sent anything".to_string()),
+        }
+    }
+
+    async fn hue_bridge(&self) -> CheckResult {
+        match self
+            .hue_client
+            .check_reachable(self.configuration.hue_timeout())
+            .await
+        {
+            Ok(()) => CheckResult::healthy("reachable".to_string()),
+            Err(e) => CheckResult::unhealthy(format!("unreachable: {}", e)),
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use reqwest::Url;
+    use spectral::prelude::*;
+
+    use crate::api::health::HealthChecks;
+    use crate::caseta::connection::ConnectionStats;
+    use crate::client::hue::HueClient;
+    use crate::client::hue_tls::HueTlsVerification;
+    use crate::client::test_server::TestServer;
+    use crate::config::auth_configuration::HealthCheckConfiguration;
+
+    #[tokio::test]
+    async fn it_reports_each_check_and_only_passes_when_they_all_do() {
+        let server = TestServer::start(200, r#"{"errors":[],"data":[]}"#).await;
+        let hue_client = HueClient::from_bridge_url(
+            Url::parse(&server.base_url).unwrap(),
+            "application-key".to_string(),
+            &HueTlsVerification::bridge_id("001788fffe6a1b2c"),
+        );
+        let health_checks = HealthChecks::new(
+            Arc::new(ConnectionStats::default()),
+            hue_client,
+            HealthCheckConfiguration::default(),
+        );
+
+        let liveness = health_checks.liveness();
+        let readiness = health_checks.readiness().await;
+
+        assert_that(&liveness.healthy).is_false();
+        assert_that(&readiness.healthy).is_false();
+        let checks = &readiness.checks;
+        assert_that(&checks["caseta_loop"].healthy).is_false();
+        assert_that(&checks["caseta_connection"].detail)
+            .is_equal_to("not logged in to the caseta hub".to_string());
+        assert_that(&checks["hue_bridge"].healthy).is_true();
+        assert_that(&server.requests()[0].path).is_equal_to("/clip/v2/resource/bridge".to_string());
+    }
+}
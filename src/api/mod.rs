@@ -0,0 +1,279 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use hyper::body::{Bytes, HttpBody};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use serde_json::json;
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error, info};
+
+use crate::api::health::{HealthChecks, HealthReport};
+use crate::caseta::remote::RemoteWatchers;
+use crate::client::dispatcher::DeviceActionMessage;
+use crate::client::driver::DeviceDrivers;
+use crate::client::room_state::CurrentRoomStateCache;
+use crate::config::scene::SharedTopology;
+use crate::metrics::{self, metrics};
+
+pub mod control;
+pub mod health;
+pub mod status;
+
+// room action bodies are a field or two of json, so anything much bigger isn't one
+const MAXIMUM_BODY_BYTES: usize = 4 * 1024;
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{method} isn't allowed for {path}")]
+    MethodNotAllowed { method: Method, path: String },
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    #[error("{0}")]
+    Unavailable(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Everything the http api needs to answer requests.
+///
+/// Actions go into the same channel the remote watchers use, so a request takes exactly the same
+/// path through the dispatcher as a button press.
+#[derive(Clone)]
+pub struct HttpApi {
+    topology: SharedTopology,
+    action_sender: Sender<DeviceActionMessage>,
+    room_states: Arc<CurrentRoomStateCache>,
+    remote_watchers: RemoteWatchers,
+    health_checks: Option<HealthChecks>,
+    device_drivers: Option<DeviceDrivers>,
+}
+
+impl HttpApi {
+    pub fn new(
+        topology: SharedTopology,
+        action_sender: Sender<DeviceActionMessage>,
+        room_states: Arc<CurrentRoomStateCache>,
+        remote_watchers: RemoteWatchers,
+    ) -> Self {
+        HttpApi {
+            topology,
+            action_sender,
+            room_states,
+            remote_watchers,
+            health_checks: None,
+            device_drivers: None,
+        }
+    }
+
+    pub fn with_health_checks(mut self, health_checks: HealthChecks) -> Self {
+        self.health_checks = Some(health_checks);
+        self
+    }
+
+    /// lets `GET /rooms/{room}` report what each of the room's devices is doing
+    pub fn with_device_drivers(mut self, device_drivers: DeviceDrivers) -> Self {
+        self.device_drivers = Some(device_drivers);
+        self
+    }
+
+    pub async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        let response = match self.route(request).await {
+            Ok(response) => response,
+            Err(e) => json_response(e.status(), &json!({ "error": e.to_string() })),
+        };
+        debug!(method=%method, path=%path, status=%response.status(), "answered an http api request");
+        response
+    }
+
+    async fn route(&self, request: Request<Body>) -> Result<Response<Body>, ApiError> {
+        let segments = path_segments(request.uri().path())?;
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        if request.method() == Method::POST {
+            check_post_headers(&request)?;
+        }
+        match (request.method(), segments.as_slice()) {
+            (&Method::GET, ["healthz"]) => Ok(health_response(&self.health_checks()?.liveness())),
+            (&Method::GET, ["readyz"]) => {
+                Ok(health_response(&self.health_checks()?.readiness().await))
+            }
+            (&Method::GET, ["metrics"]) => Ok(Response::builder()
+                .header(header::CONTENT_TYPE, metrics::CONTENT_TYPE)
+                .body(Body::from(metrics().encode()))
+                .unwrap()),
+            (&Method::GET, ["rooms"]) => Ok(json_response(StatusCode::OK, &self.rooms())),
+            (&Method::GET, ["rooms", room]) => {
+                Ok(json_response(StatusCode::OK, &self.room(room).await?))
+            }
+            (&Method::GET, ["remotes"]) => Ok(json_response(StatusCode::OK, &self.remotes())),
+            (&Method::POST, ["rooms", room_name, "actions", action]) => {
+                let body = read_body(request.into_body()).await?;
+                self.room_action(room_name, action, &body).await
+            }
+            (&Method::POST, ["remotes", remote_id, "buttons", button_id, device_action]) => {
+                self.button_press(remote_id, button_id, device_action).await
+            }
+            (_, ["healthz"])
+            | (_, ["readyz"])
+            | (_, ["metrics"])
+            | (_, ["rooms"])
+            | (_, ["rooms", _])
+            | (_, ["remotes"])
+            | (_, ["rooms", _, "actions", _])
+            | (_, ["remotes", _, "buttons", _, _]) => Err(ApiError::MethodNotAllowed {
+                method: request.method().clone(),
+                path: request.uri().path().to_string(),
+            }),
+            _ => Err(ApiError::NotFound(format!(
+                "there's nothing at {}",
+                request.uri().path()
+            ))),
+        }
+    }
+
+    fn health_checks(&self) -> Result<&HealthChecks, ApiError> {
+        self.health_checks
+            .as_ref()
+            .ok_or_else(|| ApiError::NotFound("health checks aren't set up".to_string()))
+    }
+
+    async fn send(&self, message: DeviceActionMessage) -> Result<Response<Body>, ApiError> {
+        self.action_sender
+            .send(message)
+            .await
+            .map_err(|_| ApiError::Unavailable("the dispatcher isn't running".to_string()))?;
+        Ok(Response::builder()
+            .status(StatusCode::ACCEPTED)
+            .body(Body::empty())
+            .unwrap())
+    }
+}
+
+/// binds `address` and serves the http api in the background, returning the address it's bound to
+pub fn serve(address: SocketAddr, api: HttpApi) -> Result<SocketAddr> {
+    let api = Arc::new(api);
+    let make_service = make_service_fn(move |_connection| {
+        let api = api.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let api = api.clone();
+                async move { Ok::<_, Infallible>(api.handle(request).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&address)?.serve(make_service);
+    let local_address = server.local_addr();
+    info!(address=%local_address, "serving the http api");
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(error=%e, "the http api stopped");
+        }
+    });
+    Ok(local_address)
+}
+
+// there's no authentication, so a web page the user happens to visit mustn't be able to post
+// actions. browsers send an `Origin` with cross-site posts, and can't send a json content type
+// without asking first (which we never answer)
+fn check_post_headers(request: &Request<Body>) -> Result<(), ApiError> {
+    if let Some(origin) = request.headers().get(header::ORIGIN) {
+        return Err(ApiError::Forbidden(format!(
+            "requests from {} aren't allowed",
+            String::from_utf8_lossy(origin.as_bytes())
+        )));
+    }
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    if !media_type.eq_ignore_ascii_case(JSON_CONTENT_TYPE) {
+        return Err(ApiError::UnsupportedMediaType(format!(
+            "the content type should be {}, not {:?}",
+            JSON_CONTENT_TYPE, content_type
+        )));
+    }
+    Ok(())
+}
+
+fn path_segments(path: &str) -> Result<Vec<String>, ApiError> {
+    path.trim_matches('/')
+        .split('/')
+        .map(|segment| {
+            percent_decode_str(segment)
+                .decode_utf8()
+                .map(|segment| segment.into_owned())
+                .map_err(|_| ApiError::BadRequest(format!("{} isn't valid utf-8", segment)))
+        })
+        .collect()
+}
+
+/// reads the whole body, giving up as soon as it's clearly more than [`MAXIMUM_BODY_BYTES`]
+async fn read_body(mut body: Body) -> Result<Bytes, ApiError> {
+    let too_large = || {
+        ApiError::PayloadTooLarge(format!(
+            "request bodies can't be bigger than {} bytes",
+            MAXIMUM_BODY_BYTES
+        ))
+    };
+    if body.size_hint().lower() > MAXIMUM_BODY_BYTES as u64 {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk =
+            chunk.map_err(|e| ApiError::BadRequest(format!("unable to read the body: {}", e)))?;
+        if bytes.len() + chunk.len() > MAXIMUM_BODY_BYTES {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(bytes))
+}
+
+fn health_response(report: &HealthReport) -> Response<Body> {
+    let status = match report.healthy {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    json_response(status, report)
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .body(Body::from(
+            serde_json::to_vec(body).expect("api responses should always serialize"),
+        ))
+        .unwrap()
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use serde_derive::Serialize;
+use uuid::Uuid;
+
+use crate::api::{ApiError, HttpApi};
+use crate::client::driver::DeviceState;
+use crate::config::caseta_remote::{ButtonId, CasetaRemote, RemoteId};
+use crate::config::scene::{Device, Room};
+
+#[derive(Serialize, Debug)]
+pub struct RoomStatus {
+    name: String,
+    room_id: Uuid,
+    grouped_light_room_id: Uuid,
+    remotes: Vec<RemoteId>,
+    scenes: Vec<String>,
+    /// what the room state cache thinks the room is doing, if it has an opinion
+    state: Option<RoomStateStatus>,
+    /// what each device says it's doing right now. only a single room's status asks the devices
+    #[serde(skip_serializing_if = "Option::is_none")]
+    devices: Option<Vec<DeviceStatus>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RoomStateStatus {
+    scene: Option<String>,
+    brightness: Option<f32>,
+    on: bool,
+    age_secs: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeviceStatus {
+    name: String,
+    on: Option<bool>,
+    brightness: Option<f32>,
+    /// why the device couldn't be read
+    error: Option<String>,
+}
+
+impl DeviceStatus {
+    fn new(device: &Device, state: anyhow::Result<DeviceState>) -> Self {
+        let name = device.name().to_string();
+        match state {
+            Ok(state) => DeviceStatus {
+                name,
+                on: Some(state.on),
+                brightness: state.brightness,
+                error: None,
+            },
+            Err(e) => DeviceStatus {
+                name,
+                on: None,
+                brightness: None,
+                error: Some(format!("{:#}", e)),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RemoteStatus {
+    #[serde(flatten)]
+    remote: CasetaRemote,
+    room: String,
+    /// the latest press we've tracked for this remote
+    watcher: Option<RemoteWatcherStatus>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RemoteWatcherStatus {
+    button_id: ButtonId,
+    button_state: Option<String>,
+    finished: bool,
+    tracking_secs: f64,
+}
+
+impl HttpApi {
+    /// `GET /rooms`, every room with a remote
+    pub(super) fn rooms(&self) -> Vec<RoomStatus> {
+        let topology = self.topology.current();
+        let mut rooms: HashMap<Uuid, &Room> = HashMap::new();
+        for (_remote, room) in topology.values() {
+            rooms.insert(room.room_id, room);
+        }
+        let mut rooms: Vec<RoomStatus> = rooms
+            .into_values()
+            .map(|room| self.room_status(room))
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        rooms
+    }
+
+    /// `GET /rooms/{room}`, where `room` is a room id or name. along with the cached state, this
+    /// asks each of the room's devices what it's doing
+    pub(super) async fn room(&self, room: &str) -> Result<RoomStatus, ApiError> {
+        let room_id = Uuid::parse_str(room).ok();
+        let configured_room = self
+            .topology
+            .current()
+            .values()
+            .map(|(_remote, configured_room)| configured_room)
+            .find(|configured_room| match room_id {
+                Some(room_id) => configured_room.room_id == room_id,
+                None => configured_room.name.eq_ignore_ascii_case(room),
+            })
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound(format!("there's no room {}", room)))?;
+
+        let mut room_status = self.room_status(&configured_room);
+        if let Some(device_drivers) = &self.device_drivers {
+            let device_states = device_drivers.read_state(&configured_room).await;
+            room_status.devices = Some(
+                device_states
+                    .into_iter()
+                    .map(|(device, state)| DeviceStatus::new(device, state))
+                    .collect(),
+            );
+        }
+        Ok(room_status)
+    }
+
+    /// `GET /remotes`, every configured remote and what its watcher is up to
+    pub(super) fn remotes(&self) -> Vec<RemoteStatus> {
+        let topology = self.topology.current();
+        let remote_watchers = self.remote_watchers.lock().unwrap();
+        let mut remotes: Vec<RemoteStatus> = topology
+            .iter()
+            .map(|(remote_id, (remote, room))| {
+                let watcher = remote_watchers.get(remote_id).map(|watcher| {
+                    let history = watcher.remote_history.lock().unwrap();
+                    RemoteWatcherStatus {
+                        button_id: watcher.button_id,
+                        button_state: history.button_state.map(|state| state.to_string()),
+                        finished: history.is_finished(),
+                        tracking_secs: history.tracking_duration().as_secs_f64(),
+                    }
+                });
+                RemoteStatus {
+                    remote: remote.clone(),
+                    room: room.name.clone(),
+                    watcher,
+                }
+            })
+            .collect();
+        remotes.sort_by_key(|status| status.remote.id());
+        remotes
+    }
+
+    fn room_status(&self, room: &Room) -> RoomStatus {
+        let state = self
+            .room_states
+            .get_with_age(&room.room_id)
+            .map(|(state, age)| RoomStateStatus {
+                scene: state.scene.map(|scene| scene.name),
+                brightness: state.brightness,
+                on: state.on,
+                age_secs: age.as_secs_f64(),
+            });
+        RoomStatus {
+            name: room.name.clone(),
+            room_id: room.room_id,
+            grouped_light_room_id: room.grouped_light_room_id,
+            remotes: room.remotes.clone(),
+            scenes: room.scenes.iter().map(|scene| scene.name.clone()).collect(),
+            state,
+            devices: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use hyper::{Body, Request, StatusCode};
+    use serde_json::{json, Value};
+    use spectral::prelude::*;
+    use tokio::sync::mpsc;
+
+    use crate::api::HttpApi;
+    use crate::caseta::remote::{RemoteWatcher, RemoteWatchers};
+    use crate::client::driver::{DeviceDriver, DeviceDrivers, DeviceState};
+    use crate::client::room_state::{new_cache, CurrentRoomState};
+    use crate::config::caseta_remote::{ButtonAction, ButtonId, CasetaRemote};
+    use crate::config::scene::{Device, DeviceKind, Room, Scene, SharedTopology};
+
+    const ROOM_ID: &str = "0c329b86-a7fb-4765-8fdd-2e87f37da685";
+    const GROUPED_LIGHT_ID: &str = "ba8c44e4-0229-4888-8eeb-ce4a3d48cca8";
+
+    // the lamp answers, and the fan has dropped off the network
+    struct OutletDriver;
+
+    #[async_trait]
+    impl DeviceDriver for OutletDriver {
+        async fn activate_scene(
+            &self,
+            _room: &Room,
+            _device: &Device,
+            _brightness: Option<f32>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn turn_off(&self, _room: &Room, _device: &Device) -> Result<()> {
+            Ok(())
+        }
+
+        async fn set_brightness(
+            &self,
+            _room: &Room,
+            _device: &Device,
+            _brightness: f32,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn read_state(&self, _room: &Room, device: &Device) -> Result<DeviceState> {
+            match device.name() {
+                "Lamp" => Ok(DeviceState {
+                    on: true,
+                    brightness: None,
+                }),
+                name => Err(anyhow!("{} didn't answer", name)),
+            }
+        }
+    }
+
+    fn api() -> HttpApi {
+        let scene = Scene {
+            name: "bright".to_string(),
+            devices: vec![
+                Device::WemoOutlet {
+                    name: "Lamp".to_string(),
+                    on: true,
+                },
+                Device::WemoOutlet {
+                    name: "Fan".to_string(),
+                    on: true,
+                },
+            ],
+        };
+        let room = Room {
+            name: "Living Room".to_string(),
+            room_id: ROOM_ID.parse().unwrap(),
+            grouped_light_room_id: GROUPED_LIGHT_ID.parse().unwrap(),
+            scenes: vec![scene.clone()],
+            remotes: vec![2],
+            bindings: vec![],
+        };
+        let remote = CasetaRemote::FiveButtonPico {
+            id: 2,
+            name: "living room pico".to_string(),
+        };
+        let topology = SharedTopology::new(HashMap::from([(2, (remote, room))]));
+        let room_states = new_cache();
+        room_states.insert(
+            ROOM_ID.parse().unwrap(),
+            CurrentRoomState::new(Some(scene), Some(60.0), true),
+        );
+        let (action_sender, _action_receiver) = mpsc::channel(4);
+        let remote_watcher = RemoteWatcher::new(2, ButtonId::Up, action_sender.clone());
+        remote_watcher
+            .remote_history
+            .lock()
+            .unwrap()
+            .increment(&ButtonId::Up, &ButtonAction::Press)
+            .unwrap();
+        let remote_watchers = RemoteWatchers::default();
+        remote_watchers
+            .lock()
+            .unwrap()
+            .insert(2, Arc::new(remote_watcher));
+        HttpApi::new(
+            topology,
+            action_sender,
+            Arc::new(room_states),
+            remote_watchers,
+        )
+        .with_device_drivers(
+            DeviceDrivers::new().with_driver(DeviceKind::WemoOutlet, Arc::new(OutletDriver)),
+        )
+    }
+
+    async fn get(api: &HttpApi, path: &str) -> (StatusCode, Value) {
+        let response = api
+            .handle(Request::get(path).body(Body::empty()).unwrap())
+            .await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn it_reports_rooms_with_their_cached_state() {
+        let api = api();
+
+        let (status, rooms) = get(&api, "/rooms").await;
+        let (_status, room) = get(&api, &format!("/rooms/{}", ROOM_ID)).await;
+        let (missing_status, _body) = get(&api, "/rooms/Office").await;
+
+        assert_that(&status).is_equal_to(StatusCode::OK);
+        assert_that(&rooms.as_array().unwrap().len()).is_equal_to(1);
+        assert_that(&rooms[0]["room_id"]).is_equal_to(&room["room_id"]);
+        assert_that(&room["name"]).is_equal_to(json!("Living Room"));
+        assert_that(&room["remotes"]).is_equal_to(json!([2]));
+        assert_that(&room["scenes"]).is_equal_to(json!(["bright"]));
+        assert_that(&room["state"]["scene"]).is_equal_to(json!("bright"));
+        assert_that(&room["state"]["brightness"]).is_equal_to(json!(60.0));
+        assert_that(&room["state"]["on"]).is_equal_to(json!(true));
+        assert_that(&room["state"]["age_secs"].as_f64().unwrap()).is_less_than(1.0);
+        assert_that(&missing_status).is_equal_to(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_reads_device_states_for_a_single_room() {
+        let api = api();
+
+        let (_status, rooms) = get(&api, "/rooms").await;
+        let (status, room) = get(&api, "/rooms/living%20room").await;
+
+        assert_that(&rooms[0].get("devices")).is_none();
+        assert_that(&status).is_equal_to(StatusCode::OK);
+        assert_that(&room["devices"]).is_equal_to(json!([
+            {"name": "Lamp", "on": true, "brightness": null, "error": null},
+            {"name": "Fan", "on": null, "brightness": null, "error": "Fan didn't answer"},
+        ]));
+    }
+
+    #[tokio::test]
+    async fn it_reports_remotes_with_their_watchers() {
+        let (status, remotes) = get(&api(), "/remotes").await;
+
+        assert_that(&status).is_equal_to(StatusCode::OK);
+        let remote = &remotes[0];
+        assert_that(&remote["type"]).is_equal_to(json!("five_button_pico"));
+        assert_that(&remote["id"]).is_equal_to(json!(2));
+        assert_that(&remote["room"]).is_equal_to(json!("Living Room"));
+        assert_that(&remote["watcher"]["button_id"]).is_equal_to(json!("up"));
+        assert_that(&remote["watcher"]["button_state"])
+            .is_equal_to(json!("FirstPressAwaitingRelease"));
+        assert_that(&remote["watcher"]["finished"]).is_equal_to(json!(false));
+    }
+}
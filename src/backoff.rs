@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+/// Jittered exponential backoff between reconnection attempts.
+///
+/// Each delay is drawn from the upper half of an exponentially growing window, so attempts
+/// never land back to back but also don't synchronize with other clients retrying against the
+/// same hub, bridge or broker. The window stops growing at `max_delay`.
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    attempt: u32,
+}
+
+impl ExponentialBackoff {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+            attempt: 0,
+        }
+    }
+
+    /// the number of delays handed out since the last reset
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let window = self.window(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half_window_millis = (window.as_millis() / 2) as u64;
+        let jitter_millis = fastrand::u64(0..=half_window_millis);
+        Duration::from_millis(half_window_millis + jitter_millis)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    fn window(&self, attempt: u32) -> Duration {
+        let multiplier = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(multiplier)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use spectral::prelude::*;
+
+    use crate::backoff::ExponentialBackoff;
+
+    #[test]
+    fn it_grows_delays_up_to_the_ceiling() {
+        let mut backoff =
+            ExponentialBackoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+
+        let delays: Vec<Duration> = (0..40).map(|_| backoff.next_delay()).collect();
+
+        assert_that(&delays[0]).is_greater_than_or_equal_to(Duration::from_millis(50));
+        assert_that(&delays[0]).is_less_than_or_equal_to(Duration::from_millis(100));
+        assert_that(&delays[3]).is_greater_than_or_equal_to(Duration::from_millis(400));
+        assert_that(&delays[3]).is_less_than_or_equal_to(Duration::from_millis(800));
+        for delay in delays.iter().skip(4) {
+            assert_that(delay).is_greater_than_or_equal_to(Duration::from_millis(500));
+            assert_that(delay).is_less_than_or_equal_to(Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn it_starts_over_after_a_reset() {
+        let mut backoff =
+            ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(60));
+        (0..5).for_each(|_| {
+            backoff.next_delay();
+        });
+
+        backoff.reset();
+
+        assert_that(&backoff.attempt()).is_equal_to(0);
+        assert_that(&backoff.next_delay()).is_less_than_or_equal_to(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,195 @@
+use std::str::FromStr;
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::Decoder;
+
+use super::message::{Message, MessageParseError};
+
+// the hub writes its prompts without a trailing line break and waits for input on the same line,
+// so they have to be split off the front of the buffer instead of waiting for a `\r\n`.
+// the prompt is followed by a single space that may or may not show up in the same read.
+const PROMPTS: [&str; 3] = ["login:", "password:", "GNET>"];
+
+/// Splits the caseta telnet stream into [`Message`]s.
+///
+/// Partial lines stay buffered until the rest of the line arrives, and a read that contains
+/// several lines yields each of them in order. `login:`, `password:` and `GNET>` prompts are
+/// yielded as their own messages and stripped from the front of whatever follows them.
+///
+/// A line that can't be parsed is yielded as an `Err` item rather than a decoder error, so one
+/// bad line doesn't end the stream.
+#[derive(Debug, Default)]
+pub struct CasetaCodec;
+
+impl CasetaCodec {
+    pub fn new() -> Self {
+        CasetaCodec
+    }
+
+    fn skip_leading_whitespace(src: &mut BytesMut) {
+        let whitespace_count = src
+            .iter()
+            .take_while(|byte| byte.is_ascii_whitespace())
+            .count();
+        src.advance(whitespace_count);
+    }
+
+    fn split_prompt(src: &mut BytesMut) -> Option<BytesMut> {
+        let prompt = PROMPTS
+            .iter()
+            .find(|prompt| src.starts_with(prompt.as_bytes()))?;
+        Some(src.split_to(prompt.len()))
+    }
+
+    fn is_partial_prompt(src: &BytesMut) -> bool {
+        PROMPTS
+            .iter()
+            .any(|prompt| src.len() < prompt.len() && prompt.as_bytes().starts_with(src))
+    }
+
+    fn parse(frame: &[u8]) -> Result<Message, MessageParseError> {
+        let contents = std::str::from_utf8(frame)?;
+        Message::from_str(contents.trim_end())
+    }
+}
+
+impl Decoder for CasetaCodec {
+    type Item = Result<Message, MessageParseError>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Self::skip_leading_whitespace(src);
+
+        if let Some(prompt) = Self::split_prompt(src) {
+            return Ok(Some(Self::parse(&prompt)));
+        }
+
+        if Self::is_partial_prompt(src) {
+            // wait for the rest of the prompt before deciding what this is
+            return Ok(None);
+        }
+
+        match src.iter().position(|byte| *byte == b'\n') {
+            Some(line_end) => {
+                let line = src.split_to(line_end + 1);
+                Ok(Some(Self::parse(&line)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use spectral::prelude::*;
+    use tokio_util::codec::Decoder;
+
+    use crate::caseta::codec::CasetaCodec;
+    use crate::caseta::message::{Message, MessageParseError};
+    use crate::config::caseta_remote::{ButtonAction, ButtonId};
+
+    fn decode_all(codec: &mut CasetaCodec, buffer: &mut BytesMut) -> Vec<Message> {
+        let mut messages = Vec::new();
+        while let Some(message) = codec.decode(buffer).expect("unable to decode the buffer") {
+            messages.push(message.expect("unable to parse the message"));
+        }
+        messages
+    }
+
+    #[test]
+    fn it_decodes_coalesced_lines_in_order() {
+        let mut codec = CasetaCodec::new();
+        let mut buffer = BytesMut::from("~DEVICE,2,2,3\r\n~DEVICE,2,2,4\r\n");
+
+        let messages = decode_all(&mut codec, &mut buffer);
+
+        assert_that(&messages).is_equal_to(vec![
+            Message::ButtonEvent {
+                remote_id: 2,
+                button_id: ButtonId::PowerOn,
+                button_action: ButtonAction::Press,
+            },
+            Message::ButtonEvent {
+                remote_id: 2,
+                button_id: ButtonId::PowerOn,
+                button_action: ButtonAction::Release,
+            },
+        ]);
+        assert_that(&buffer.is_empty()).is_true();
+    }
+
+    #[test]
+    fn it_buffers_lines_split_across_reads() {
+        let mut codec = CasetaCodec::new();
+        let mut buffer = BytesMut::from("~DEVICE,3,");
+
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_empty();
+
+        buffer.extend_from_slice(b"4,3\r");
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_empty();
+
+        buffer.extend_from_slice(b"\n~DEVICE,3,4");
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_equal_to(vec![Message::ButtonEvent {
+            remote_id: 3,
+            button_id: ButtonId::PowerOff,
+            button_action: ButtonAction::Press,
+        }]);
+
+        buffer.extend_from_slice(b",4\r\n");
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_equal_to(vec![Message::ButtonEvent {
+            remote_id: 3,
+            button_id: ButtonId::PowerOff,
+            button_action: ButtonAction::Release,
+        }]);
+    }
+
+    #[test]
+    fn it_decodes_prompts_without_line_endings() {
+        let mut codec = CasetaCodec::new();
+        let mut buffer = BytesMut::from("login: ");
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_equal_to(vec![Message::LoginPrompt]);
+
+        buffer.extend_from_slice(b"\r\npass");
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_empty();
+
+        buffer.extend_from_slice(b"word: ");
+        assert_that(&decode_all(&mut codec, &mut buffer))
+            .is_equal_to(vec![Message::PasswordPrompt]);
+
+        buffer.extend_from_slice(b"\r\nGNET> ");
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_equal_to(vec![Message::LoggedIn]);
+    }
+
+    #[test]
+    fn it_strips_the_prompt_prefix_from_a_line() {
+        let mut codec = CasetaCodec::new();
+        let mut buffer = BytesMut::from("GNET> ~DEVICE,2,5,3\r\nGNET> \r\n");
+
+        assert_that(&decode_all(&mut codec, &mut buffer)).is_equal_to(vec![
+            Message::LoggedIn,
+            Message::ButtonEvent {
+                remote_id: 2,
+                button_id: ButtonId::Up,
+                button_action: ButtonAction::Press,
+            },
+            Message::LoggedIn,
+        ]);
+    }
+
+    #[test]
+    fn it_keeps_decoding_after_an_unparsable_line() {
+        let mut codec = CasetaCodec::new();
+        let mut buffer = BytesMut::from("~DEVICE,9,1,3\r\n~DEVICE,2,4,3\r\n");
+
+        let unknown_button = codec.decode(&mut buffer).unwrap();
+        let power_off = codec.decode(&mut buffer).unwrap();
+
+        assert_that(&unknown_button).is_equal_to(Some(Err(MessageParseError::UnknownButtonId(1))));
+        assert_that(&power_off).is_equal_to(Some(Ok(Message::ButtonEvent {
+            remote_id: 2,
+            button_id: ButtonId::PowerOff,
+            button_action: ButtonAction::Press,
+        })));
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use tracing::{debug, instrument};
+
+use super::message::{
+    IntegrationError, IntegrationId, Message, MonitoringType, OutputAction, MONITORING_DISABLED,
+    MONITORING_ENABLED,
+};
+
+const OUTPUT_LEVEL_ACTION: u8 = 1;
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// a command we can send to the caseta hub over the integration protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum CasetaCommand {
+    /// `#OUTPUT,<id>,1,<level>,<fade>`: drive a dimmer or switch to `level` percent over `fade`
+    SetOutputLevel {
+        output_id: IntegrationId,
+        level: f32,
+        fade: Duration,
+    },
+    /// `?OUTPUT,<id>,1`: ask for an output's current level
+    QueryOutputLevel { output_id: IntegrationId },
+    /// `?DEVICE,<id>,<component>,<action>`: ask for the state of a device component
+    QueryDevice {
+        device_id: IntegrationId,
+        component: u8,
+        action: u8,
+    },
+    /// `#MONITORING,<type>,<1|2>`: turn a category of hub reports on or off
+    SetMonitoring {
+        monitoring_type: MonitoringType,
+        enabled: bool,
+    },
+}
+
+impl CasetaCommand {
+    pub fn to_wire_format(&self) -> String {
+        format!("{}\r\n", self)
+    }
+
+    /// whether `message` is the hub's reply to this command
+    pub fn is_response(&self, message: &Message) -> bool {
+        match (self, message) {
+            (
+                CasetaCommand::SetOutputLevel { output_id, .. }
+                | CasetaCommand::QueryOutputLevel { output_id },
+                Message::OutputUpdate {
+                    output_id: reported_output_id,
+                    action: OutputAction::Level(_),
+                },
+            ) => output_id == reported_output_id,
+            (
+                CasetaCommand::QueryDevice {
+                    device_id,
+                    component,
+                    ..
+                },
+                Message::DeviceUpdate {
+                    device_id: reported_device_id,
+                    component: reported_component,
+                    ..
+                },
+            ) => device_id == reported_device_id && component == reported_component,
+            (
+                CasetaCommand::SetMonitoring {
+                    monitoring_type, ..
+                },
+                Message::Monitoring {
+                    monitoring_type: reported_monitoring_type,
+                    ..
+                },
+            ) => monitoring_type == reported_monitoring_type,
+            _ => false,
+        }
+    }
+
+    // the hub accepts fade times as `SS.ss`
+    fn format_fade(fade: &Duration) -> String {
+        if fade.subsec_millis() == 0 {
+            format!("{}", fade.as_secs())
+        } else {
+            format!("{:.2}", fade.as_secs_f32())
+        }
+    }
+}
+
+impl Display for CasetaCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CasetaCommand::SetOutputLevel {
+                output_id,
+                level,
+                fade,
+            } => write!(
+                f,
+                "#OUTPUT,{},{},{:.2},{}",
+                output_id,
+                OUTPUT_LEVEL_ACTION,
+                level,
+                Self::format_fade(fade)
+            ),
+            CasetaCommand::QueryOutputLevel { output_id } => {
+                write!(f, "?OUTPUT,{},{}", output_id, OUTPUT_LEVEL_ACTION)
+            }
+            CasetaCommand::QueryDevice {
+                device_id,
+                component,
+                action,
+            } => write!(f, "?DEVICE,{},{},{}", device_id, component, action),
+            CasetaCommand::SetMonitoring {
+                monitoring_type,
+                enabled,
+            } => write!(
+                f,
+                "#MONITORING,{},{}",
+                u8::from(*monitoring_type),
+                if *enabled {
+                    MONITORING_ENABLED
+                } else {
+                    MONITORING_DISABLED
+                }
+            ),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CommandError {
+    #[error("the caseta hub rejected the command: {0}")]
+    Rejected(IntegrationError),
+    #[error("the caseta hub did not respond within {0:?}")]
+    Timeout(Duration),
+    #[error("the caseta connection was closed before the hub responded")]
+    Disconnected,
+    #[error("unable to write the command to the caseta hub: {0}")]
+    WriteFailed(String),
+    #[error("got an unexpected response from the caseta hub: {0}")]
+    UnexpectedResponse(String),
+}
+
+pub type CommandResponder = oneshot::Sender<Result<Message, CommandError>>;
+
+/// a command waiting to be written to the hub, or waiting for the hub's response
+#[derive(Debug)]
+pub struct PendingCommand {
+    pub command: CasetaCommand,
+    pub responder: CommandResponder,
+}
+
+/// A cloneable handle for sending commands through a [`super::connection::DelegatingCasetaConnectionManager`].
+///
+/// The manager writes commands to whichever hub connection is live, and each call resolves with
+/// the `~` response that matches the command.
+#[derive(Debug, Clone)]
+pub struct CasetaCommandClient {
+    command_sender: mpsc::Sender<PendingCommand>,
+    response_timeout: Duration,
+}
+
+impl CasetaCommandClient {
+    pub fn new(command_sender: mpsc::Sender<PendingCommand>) -> Self {
+        Self {
+            command_sender,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+        }
+    }
+
+    pub fn with_response_timeout(mut self, response_timeout: Duration) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    pub async fn send(&self, command: CasetaCommand) -> Result<Message, CommandError> {
+        let (responder, response_receiver) = oneshot::channel();
+        self.command_sender
+            .send(PendingCommand { command, responder })
+            .await
+            .map_err(|_| CommandError::Disconnected)?;
+
+        match time::timeout(self.response_timeout, response_receiver).await {
+            Ok(Ok(response)) => {
+                debug!(response=?response, "got a response from the caseta hub");
+                response
+            }
+            Ok(Err(_receive_error)) => Err(CommandError::Disconnected),
+            Err(_elapsed) => Err(CommandError::Timeout(self.response_timeout)),
+        }
+    }
+
+    pub async fn set_output_level(
+        &self,
+        output_id: IntegrationId,
+        level: f32,
+        fade: Duration,
+    ) -> Result<f32, CommandError> {
+        let response = self
+            .send(CasetaCommand::SetOutputLevel {
+                output_id,
+                level,
+                fade,
+            })
+            .await?;
+        Self::output_level(response)
+    }
+
+    pub async fn query_output_level(&self, output_id: IntegrationId) -> Result<f32, CommandError> {
+        let response = self
+            .send(CasetaCommand::QueryOutputLevel { output_id })
+            .await?;
+        Self::output_level(response)
+    }
+
+    pub async fn query_device(
+        &self,
+        device_id: IntegrationId,
+        component: u8,
+        action: u8,
+    ) -> Result<Message, CommandError> {
+        self.send(CasetaCommand::QueryDevice {
+            device_id,
+            component,
+            action,
+        })
+        .await
+    }
+
+    pub async fn set_monitoring(
+        &self,
+        monitoring_type: MonitoringType,
+        enabled: bool,
+    ) -> Result<(), CommandError> {
+        self.send(CasetaCommand::SetMonitoring {
+            monitoring_type,
+            enabled,
+        })
+        .await
+        .map(|_response| ())
+    }
+
+    fn output_level(response: Message) -> Result<f32, CommandError> {
+        match response {
+            Message::OutputUpdate {
+                action: OutputAction::Level(level),
+                ..
+            } => Ok(level),
+            other => Err(CommandError::UnexpectedResponse(other.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use spectral::prelude::*;
+
+    use crate::caseta::command::CasetaCommand;
+    use crate::caseta::message::{Message, MonitoringType, OutputAction};
+
+    #[test]
+    fn it_formats_commands_for_the_hub() {
+        let set_level = CasetaCommand::SetOutputLevel {
+            output_id: 5,
+            level: 75.0,
+            fade: Duration::from_secs(2),
+        };
+        let fractional_fade = CasetaCommand::SetOutputLevel {
+            output_id: 5,
+            level: 0.0,
+            fade: Duration::from_millis(250),
+        };
+        let monitoring = CasetaCommand::SetMonitoring {
+            monitoring_type: MonitoringType::Zone,
+            enabled: false,
+        };
+
+        assert_that(&set_level.to_wire_format()).is_equal_to("#OUTPUT,5,1,75.00,2\r\n".to_string());
+        assert_that(&fractional_fade.to_wire_format())
+            .is_equal_to("#OUTPUT,5,1,0.00,0.25\r\n".to_string());
+        assert_that(&CasetaCommand::QueryOutputLevel { output_id: 5 }.to_wire_format())
+            .is_equal_to("?OUTPUT,5,1\r\n".to_string());
+        assert_that(
+            &CasetaCommand::QueryDevice {
+                device_id: 2,
+                component: 82,
+                action: 9,
+            }
+            .to_wire_format(),
+        )
+        .is_equal_to("?DEVICE,2,82,9\r\n".to_string());
+        assert_that(&monitoring.to_wire_format()).is_equal_to("#MONITORING,5,2\r\n".to_string());
+    }
+
+    #[test]
+    fn it_matches_responses_to_commands() {
+        let query = CasetaCommand::QueryOutputLevel { output_id: 5 };
+
+        assert_that(&query.is_response(&Message::OutputUpdate {
+            output_id: 5,
+            action: OutputAction::Level(40.0),
+        }))
+        .is_true();
+        assert_that(&query.is_response(&Message::OutputUpdate {
+            output_id: 6,
+            action: OutputAction::Level(40.0),
+        }))
+        .is_false();
+        assert_that(&query.is_response(&Message::OutputUpdate {
+            output_id: 5,
+            action: OutputAction::StartRaising,
+        }))
+        .is_false();
+    }
+}
@@ -1,23 +1,48 @@
-use std::{str::FromStr, time::Duration};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use anyhow::bail;
 use async_trait::async_trait;
-use bytes::BytesMut;
+use futures_util::StreamExt;
+use socket2::{SockRef, TcpKeepalive};
 use thiserror::Error;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::AsyncWriteExt,
     net::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpStream,
     },
     sync::mpsc,
-    time,
+    time::{self, Instant},
 };
-use tracing::{debug, error, info, instrument};
+use tokio_util::codec::FramedRead;
+use tracing::{debug, error, info, instrument, warn};
 use url::Host;
 
+use crate::backoff::ExponentialBackoff;
+use crate::metrics::metrics;
+
+use super::codec::CasetaCodec;
+use super::command::{CasetaCommandClient, CommandError, PendingCommand};
 use super::message::Message;
 
+// commands are written as soon as the manager sees them, so this only needs to absorb bursts
+const COMMAND_BUFFER_SIZE: usize = 16;
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_MAXIMUM_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_KEEP_ALIVE_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+// the kernel-level keepalive catches dead peers even when we aren't writing anything
+const TCP_KEEPALIVE_TIME: Duration = Duration::from_secs(30);
+const TCP_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
 #[async_trait]
 pub trait TcpSocketProvider: std::fmt::Debug {
     async fn new_socket(&self) -> Result<TcpStream, anyhow::Error>;
@@ -45,7 +70,15 @@ impl TcpSocketProvider for DefaultTcpSocketProvider {
         .await;
 
         match connection {
-            Ok(Ok(tcp_stream)) => Ok(tcp_stream),
+            Ok(Ok(tcp_stream)) => {
+                let keepalive = TcpKeepalive::new()
+                    .with_time(TCP_KEEPALIVE_TIME)
+                    .with_interval(TCP_KEEPALIVE_INTERVAL);
+                if let Err(e) = SockRef::from(&tcp_stream).set_tcp_keepalive(&keepalive) {
+                    warn!(error=%e, "unable to enable tcp keepalive on the caseta connection");
+                }
+                Ok(tcp_stream)
+            }
             Ok(Err(e)) => bail!("unable to connect: {}", e),
             Err(_elapsed) => bail!("timed out trying to connect"),
         }
@@ -69,6 +102,11 @@ pub trait ReadWriteConnection: std::fmt::Debug {
     async fn write_keep_alive_message(&mut self) -> Result<(), ConnectionManagerError>;
 }
 
+pub type SplitConnection = (
+    Box<dyn ReadOnlyConnection + Send + Sync>,
+    Box<dyn WriteOnlyConnection + Send + Sync>,
+);
+
 #[derive(Error, Debug)]
 pub enum ConnectionManagerError {
     #[error("received an empty message")]
@@ -79,57 +117,142 @@ pub enum ConnectionManagerError {
     RecoverableError(String),
     #[error("encountered an unrecoverable error: {0}")]
     UnrecoverableError(String),
+    #[error("the caseta hub rejected our credentials: {0}")]
+    AuthenticationError(String),
+}
+
+impl ConnectionManagerError {
+    /// transient errors come from the network or the hub going away, so they're worth retrying.
+    /// anything else (e.g. bad credentials) will fail the same way on every attempt.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ConnectionManagerError::EmptyMessageError
+                | ConnectionManagerError::LivenessError
+                | ConnectionManagerError::RecoverableError(_)
+        )
+    }
+}
+
+/// Counters describing the delegating connection manager's connection history, along with
+/// what the health checks need to know about the current connection.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    connections: AtomicU64,
+    reconnections: AtomicU64,
+    failed_connection_attempts: AtomicU64,
+    missed_keep_alives: AtomicU64,
+    logged_in: AtomicBool,
+    // the last time the manager did anything at all. it wakes up at least once per keep alive
+    last_activity: Mutex<Option<Instant>>,
+    // the last frame the hub sent. a busy hub never gets a keep alive (the timer starts over
+    // with every message), so button events count as much as keep alive answers and logging in
+    last_message_received: Mutex<Option<Instant>>,
+}
+
+impl ConnectionStats {
+    pub fn connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn reconnections(&self) -> u64 {
+        self.reconnections.load(Ordering::Relaxed)
+    }
+
+    pub fn failed_connection_attempts(&self) -> u64 {
+        self.failed_connection_attempts.load(Ordering::Relaxed)
+    }
+
+    pub fn missed_keep_alives(&self) -> u64 {
+        self.missed_keep_alives.load(Ordering::Relaxed)
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in.load(Ordering::Relaxed)
+    }
+
+    pub fn since_last_activity(&self) -> Option<Duration> {
+        self.last_activity.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    pub fn since_last_message(&self) -> Option<Duration> {
+        self.last_message_received
+            .lock()
+            .unwrap()
+            .map(|at| at.elapsed())
+    }
+
+    fn record_connection(&self) {
+        if self.connections.fetch_add(1, Ordering::Relaxed) > 0 {
+            self.reconnections.fetch_add(1, Ordering::Relaxed);
+            metrics().record_caseta_reconnect();
+        }
+        self.logged_in.store(true, Ordering::Relaxed);
+        self.record_message_received();
+    }
+
+    fn record_disconnection(&self) {
+        self.logged_in.store(false, Ordering::Relaxed);
+    }
+
+    fn record_failed_connection_attempt(&self) {
+        self.failed_connection_attempts
+            .fetch_add(1, Ordering::Relaxed);
+        metrics().record_caseta_failed_connection_attempt();
+    }
+
+    fn record_missed_keep_alive(&self) {
+        self.missed_keep_alives.fetch_add(1, Ordering::Relaxed);
+        metrics().record_caseta_missed_keep_alive();
+    }
+
+    fn record_message_received(&self) {
+        *self.last_message_received.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn record_activity(&self) {
+        *self.last_activity.lock().unwrap() = Some(Instant::now());
+    }
 }
 
 #[derive(Debug)]
 pub struct CasetaReadConnectionManager {
-    connection: OwnedReadHalf,
+    connection: FramedRead<OwnedReadHalf, CasetaCodec>,
     disconnect_receiver: mpsc::Receiver<()>,
 }
 
 impl CasetaReadConnectionManager {
     fn new(connection: OwnedReadHalf, disconnect_receiver: mpsc::Receiver<()>) -> Self {
         Self {
-            connection,
+            connection: FramedRead::new(connection, CasetaCodec::new()),
             disconnect_receiver,
         }
     }
 
     async fn read_frame(&mut self) -> Result<Option<Message>, ConnectionManagerError> {
-        let mut buffer = BytesMut::with_capacity(128);
-        let stream_read_future = self.connection.read_buf(&mut buffer);
-
-        tokio::select! {
-            _disconnect_message = self.disconnect_receiver.recv() => {
-                info!("The connection to the caseta hub is no longer alive");
-                return Err(ConnectionManagerError::LivenessError)
-            },
-            read_result = stream_read_future => {
-                let num_bytes_read = read_result.expect("there was a problem reading the buffer");
-                if num_bytes_read == 0 {
-                    if buffer.is_empty() {
-                        return Ok(None)
-                    } else {
-                        // todo: not sure if this is appropriate here. maybe this is a
-                        // recoverable error (aka one where we should just kill this connection
-                        // and replace it with a new one)
-                        return Err(ConnectionManagerError::EmptyMessageError);
+        loop {
+            tokio::select! {
+                _disconnect_message = self.disconnect_receiver.recv() => {
+                    info!("The connection to the caseta hub is no longer alive");
+                    return Err(ConnectionManagerError::LivenessError)
+                },
+                next_frame = self.connection.next() => {
+                    match next_frame {
+                        // the hub closed the connection
+                        None => return Ok(None),
+                        Some(Ok(Ok(message))) => return Ok(Some(message)),
+                        Some(Ok(Err(parse_error))) if parse_error.is_unknown_device_event() => {
+                            debug!(error=%parse_error, "skipping an event from a device we don't handle");
+                        }
+                        Some(Ok(Err(parse_error))) => {
+                            warn!(error=%parse_error, "skipping a message we couldn't parse");
+                        }
+                        Some(Err(e)) => {
+                            info!(error=%e, "unable to read from the caseta connection");
+                            return Err(ConnectionManagerError::LivenessError)
+                        }
                     }
                 }
-
-                let contents = match std::str::from_utf8(&buffer[..]) {
-                    Ok(parsed_buffer) => parsed_buffer,
-                    Err(e) => return Err(
-                        ConnectionManagerError::UnrecoverableError(
-                            format!("got unparsable contents, buffer bytes cannot be parsed from utf-8 into String: {}", e)
-                        )
-                    )
-                };
-                return match Message::from_str(contents) {
-                    Ok(message) => Ok(Some(message)),
-                    Err(e) => Err(ConnectionManagerError::UnrecoverableError(
-                        format!("got an unparsable message. message from Caseta cannot be parsed into a Message object: {}", e)))
-                }
             }
         }
     }
@@ -166,7 +289,7 @@ impl WriteOnlyConnection for CasetaWriteConnectionManager {
             Ok(_) => {}
             Err(e) => {
                 error!(error=%e, "couldn't write the socket read/write buffer");
-                return Err(ConnectionManagerError::UnrecoverableError(format!("unable to write the socket read/write buffer. was the connection closed?, error: {}", e)));
+                return Err(ConnectionManagerError::RecoverableError(format!("unable to write the socket read/write buffer. was the connection closed?, error: {}", e)));
             }
         }
 
@@ -175,7 +298,7 @@ impl WriteOnlyConnection for CasetaWriteConnectionManager {
             Ok(_) => Ok(()),
             Err(e) => {
                 error!(error=%e, "couldn't flush the socket read/write buffer");
-                Err(ConnectionManagerError::UnrecoverableError(format!("couldn't flush the socket read/write buffer. was the connection closed? error: {}", e)))
+                Err(ConnectionManagerError::RecoverableError(format!("couldn't flush the socket read/write buffer. was the connection closed? error: {}", e)))
             }
         }
     }
@@ -227,40 +350,38 @@ impl CasetaConnectionManager {
         let mut caseta_read_half = CasetaReadConnectionManager::new(tcp_read_half, receiver);
         let mut caseta_write_half = CasetaWriteConnectionManager::new(tcp_write_half, sender);
 
-        let login_response = Self::log_in(
-            caseta_username,
-            caseta_password,
-            &mut caseta_read_half,
-            &mut caseta_write_half,
+        let login_response = time::timeout(
+            LOGIN_TIMEOUT,
+            Self::log_in(
+                caseta_username,
+                caseta_password,
+                &mut caseta_read_half,
+                &mut caseta_write_half,
+            ),
         )
         .await;
 
-        if let Err(e) = login_response {
-            return Err(ConnectionManagerError::UnrecoverableError(format!(
-                "there was a problem authenticating with the caseta hub: {}",
-                e
-            )));
+        match login_response {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(e),
+            Err(_elapsed) => {
+                return Err(ConnectionManagerError::RecoverableError(
+                    "timed out waiting to log in to the caseta hub".to_string(),
+                ))
+            }
         }
         self.connection = Option::Some((caseta_read_half, caseta_write_half));
 
         Ok(())
     }
 
-    fn split(
-        self,
-    ) -> Result<
-        (
-            Box<dyn ReadOnlyConnection + Send + Sync>,
-            Box<dyn WriteOnlyConnection + Send + Sync>,
-        ),
-        ConnectionManagerError,
-    > {
-        return match self.connection {
+    fn split(self) -> Result<SplitConnection, ConnectionManagerError> {
+        match self.connection {
             Some((read_half, write_half)) => Ok((Box::new(read_half), Box::new(write_half))),
             None => {
                 Err(ConnectionManagerError::UnrecoverableError("This is a bug; you cannot split a ReadWriteConnection before initializing it. did you call the initialize method?".to_string()))
             }
-        };
+        }
     }
     #[instrument(level = "debug", skip(caseta_username, caseta_password))]
     async fn log_in(
@@ -284,7 +405,16 @@ impl CasetaConnectionManager {
         caseta_write_half
             .write_message(format!("{}\r\n", caseta_password))
             .await?;
-        Self::ensure_expected_message(Message::LoggedIn, caseta_read_half.read_frame().await)?;
+
+        let login_result = caseta_read_half.read_frame().await;
+        if let Ok(Some(Message::LoginPrompt)) = login_result {
+            // the hub asks for the username again when it doesn't like the username or password
+            error!("the caseta hub rejected our credentials");
+            return Err(ConnectionManagerError::AuthenticationError(
+                "the caseta hub rejected the username or password".to_string(),
+            ));
+        }
+        Self::ensure_expected_message(Message::LoggedIn, login_result)?;
         Ok(())
     }
 
@@ -299,23 +429,30 @@ impl CasetaConnectionManager {
                     return Ok(());
                 }
                 error!("got an unexpected message: {}", message);
-                return Err(ConnectionManagerError::UnrecoverableError(format!(
+                Err(ConnectionManagerError::UnrecoverableError(format!(
                     "unexpected message: {}",
                     message
-                )));
+                )))
             }
             Ok(None) => {
-                error!("got an empty message we did not expect");
-                return Err(ConnectionManagerError::UnrecoverableError(
-                    "unexpected empty message".to_string(),
-                ));
+                error!("the caseta hub closed the connection while we were logging in");
+                Err(ConnectionManagerError::RecoverableError(
+                    "the connection was closed while logging in".to_string(),
+                ))
+            }
+            Err(e) if e.is_transient() => {
+                error!("got an error while logging in: {}", e);
+                Err(ConnectionManagerError::RecoverableError(format!(
+                    "got an error while logging in: {}",
+                    e
+                )))
             }
             Err(e) => {
                 error!("got an error: {}", e);
-                return Err(ConnectionManagerError::UnrecoverableError(format!(
+                Err(ConnectionManagerError::UnrecoverableError(format!(
                     "got an unexpected error: {}",
                     e
-                )));
+                )))
             }
         }
     }
@@ -323,15 +460,7 @@ impl CasetaConnectionManager {
 
 #[async_trait]
 pub trait CasetaConnectionProvider: std::fmt::Debug {
-    async fn new_connection(
-        &mut self,
-    ) -> Result<
-        (
-            Box<dyn ReadOnlyConnection + Send + Sync>,
-            Box<dyn WriteOnlyConnection + Send + Sync>,
-        ),
-        ConnectionManagerError,
-    >;
+    async fn new_connection(&mut self) -> Result<SplitConnection, ConnectionManagerError>;
 }
 
 #[derive(Debug)]
@@ -357,20 +486,12 @@ impl DefaultCasetaConnectionProvider {
 
 #[async_trait]
 impl CasetaConnectionProvider for DefaultCasetaConnectionProvider {
-    async fn new_connection(
-        &mut self,
-    ) -> Result<
-        (
-            Box<dyn ReadOnlyConnection + Send + Sync>,
-            Box<dyn WriteOnlyConnection + Send + Sync>,
-        ),
-        ConnectionManagerError,
-    > {
+    async fn new_connection(&mut self) -> Result<SplitConnection, ConnectionManagerError> {
         let mut connection = CasetaConnectionManager::new();
         let tcp_stream = self.tcp_socket_provider.new_socket().await;
 
         if let Err(e) = tcp_stream {
-            return Err(ConnectionManagerError::UnrecoverableError(format!(
+            return Err(ConnectionManagerError::RecoverableError(format!(
                 "there was a problem getting a tcp connection to the caseta hub: {}",
                 e
             )));
@@ -386,48 +507,54 @@ impl CasetaConnectionProvider for DefaultCasetaConnectionProvider {
 
 #[derive(Debug)]
 pub struct DelegatingCasetaConnectionManager {
-    connection_manager: Option<(
-        Box<dyn ReadOnlyConnection + Send + Sync>,
-        Box<dyn WriteOnlyConnection + Send + Sync>,
-    )>,
+    connection_manager: Option<SplitConnection>,
     caseta_connection_provider: Box<dyn CasetaConnectionProvider + Send + Sync>,
+    command_sender: mpsc::Sender<PendingCommand>,
+    command_receiver: mpsc::Receiver<PendingCommand>,
+    // commands that have been written to the hub and are waiting for a response, oldest first
+    pending_commands: VecDeque<PendingCommand>,
+    backoff: ExponentialBackoff,
+    stats: Arc<ConnectionStats>,
+    keep_alive_interval: Duration,
+    keep_alive_response_timeout: Duration,
+    // set when a keep alive has been written and we're still waiting for the hub's `GNET>`
+    keep_alive_response_deadline: Option<Instant>,
 }
 
 #[async_trait]
 impl ReadOnlyConnection for DelegatingCasetaConnectionManager {
     #[instrument(level = "debug", skip(self))]
     async fn await_message(&mut self) -> Result<Option<Message>, ConnectionManagerError> {
-        match self.connection_manager {
-            Some(_) => {}
-            None => {
+        loop {
+            self.stats.record_activity();
+            if self.connection_manager.is_none() {
                 debug!("no delegate caseta connection present. creating a new caseta connection");
-                let new_connection = self.caseta_connection_provider.new_connection().await;
-                if let Err(e) = new_connection {
-                    return Err(ConnectionManagerError::UnrecoverableError(format!(
-                        "there was a problem creating a new caseta connection: {}",
-                        e
-                    )));
-                }
-                let new_connection = new_connection.unwrap();
-                self.connection_manager = Option::Some(new_connection);
+                self.connect().await?;
             }
-        };
-        loop {
+            let keep_alive_interval = self.keep_alive_interval;
+            let keep_alive_response_deadline = self.keep_alive_response_deadline;
             let connection_manager = self.connection_manager.as_mut().unwrap();
             let (read_connection, write_connection) = connection_manager;
 
             tokio::select! {
                 next_message = read_connection.await_message() => {
+                    if let Ok(Some(_)) = next_message {
+                        self.stats.record_message_received();
+                    }
                     match next_message {
                         Ok(Some(Message::LoggedIn)) => {
                             debug!("got the logged in prompt: {}. in this case, it's a response from the keep alive message", Message::LoggedIn);
+                            self.keep_alive_response_deadline = None;
                             continue;
                         },
-                        Ok(Some(message)) => return Ok(Some(message)),
+                        Ok(Some(message)) => {
+                            self.resolve_pending_command(&message);
+                            return Ok(Some(message))
+                        },
                         Ok(None) | Err(ConnectionManagerError::LivenessError) => {
                             // liveness errors mean the delegate connection is dead. drop the delegate connection so we can replace it.
                             info!("the existing caseta connection is no longer valid. Replacing it with an empty option to trigger reconnection");
-                            self.connection_manager = Option::None;
+                            self.drop_connection();
                             continue;
                         }
                         Err(e) => return Err(ConnectionManagerError::UnrecoverableError(format!(
@@ -436,15 +563,57 @@ impl ReadOnlyConnection for DelegatingCasetaConnectionManager {
                         ))),
                     };
                 },
+                Some(pending_command) = self.command_receiver.recv() => {
+                    debug!(command=%pending_command.command, "writing a command to the caseta hub");
+                    let write_result = write_connection
+                        .write_message(pending_command.command.to_wire_format())
+                        .await;
+                    match write_result {
+                        Ok(_) => self.pending_commands.push_back(pending_command),
+                        Err(e) => {
+                            error!(error=%e, "unable to write a command to the caseta hub. Replacing the connection");
+                            // the caller may have stopped waiting, in which case there's no one to tell
+                            let _ = pending_command
+                                .responder
+                                .send(Err(CommandError::WriteFailed(e.to_string())));
+                            self.drop_connection();
+                        }
+                    }
+                    continue;
+                },
                 keep_alive_result = async {
-                    time::sleep(Duration::from_secs(60)).await;
-                    debug!("writing keep alive message");
-                    write_connection.write_keep_alive_message().await
+                    match keep_alive_response_deadline {
+                        Some(deadline) => {
+                            time::sleep_until(deadline).await;
+                            Err(ConnectionManagerError::LivenessError)
+                        }
+                        None => {
+                            time::sleep(keep_alive_interval).await;
+                            debug!("writing keep alive message");
+                            write_connection.write_keep_alive_message().await
+                        }
+                    }
                 } => {
                     match keep_alive_result {
-                        Ok(_) => continue,
-                        Err(e) => return Err(ConnectionManagerError::UnrecoverableError(format!("unable to write the keepalive message: {}", e)))
+                        Ok(_) => {
+                            self.keep_alive_response_deadline =
+                                Some(Instant::now() + self.keep_alive_response_timeout);
+                        }
+                        Err(ConnectionManagerError::LivenessError) => {
+                            self.stats.record_missed_keep_alive();
+                            warn!(
+                                missed_keep_alives = self.stats.missed_keep_alives(),
+                                "the caseta hub didn't answer the keep alive message within {:?}. Replacing the connection",
+                                self.keep_alive_response_timeout
+                            );
+                            self.drop_connection();
+                        }
+                        Err(e) => {
+                            error!(error=%e, "unable to write the keep alive message. Replacing the connection");
+                            self.drop_connection();
+                        }
                     }
+                    continue;
                 }
 
             };
@@ -456,9 +625,443 @@ impl DelegatingCasetaConnectionManager {
     pub fn new(
         caseta_connection_provider: Box<dyn CasetaConnectionProvider + Send + Sync>,
     ) -> Self {
+        let (command_sender, command_receiver) = mpsc::channel(COMMAND_BUFFER_SIZE);
         Self {
             connection_manager: Option::None,
-            caseta_connection_provider: caseta_connection_provider,
+            caseta_connection_provider,
+            command_sender,
+            command_receiver,
+            pending_commands: VecDeque::new(),
+            backoff: ExponentialBackoff::new(
+                DEFAULT_INITIAL_RECONNECT_DELAY,
+                DEFAULT_MAXIMUM_RECONNECT_DELAY,
+            ),
+            stats: Arc::new(ConnectionStats::default()),
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            keep_alive_response_timeout: DEFAULT_KEEP_ALIVE_RESPONSE_TIMEOUT,
+            keep_alive_response_deadline: None,
+        }
+    }
+
+    /// write a keep alive every `interval` the connection is idle, and give up on the connection
+    /// if the hub doesn't answer it within `response_timeout`
+    pub fn with_keep_alive(mut self, interval: Duration, response_timeout: Duration) -> Self {
+        self.keep_alive_interval = interval;
+        self.keep_alive_response_timeout = response_timeout;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: ExponentialBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<ConnectionStats> {
+        self.stats.clone()
+    }
+
+    // keep trying to connect until we succeed or hit an error that retrying won't fix
+    async fn connect(&mut self) -> Result<(), ConnectionManagerError> {
+        loop {
+            match self.caseta_connection_provider.new_connection().await {
+                Ok(new_connection) => {
+                    self.stats.record_connection();
+                    info!(
+                        failed_attempts = self.backoff.attempt(),
+                        reconnections = self.stats.reconnections(),
+                        "connected to the caseta hub"
+                    );
+                    self.backoff.reset();
+                    self.connection_manager = Option::Some(new_connection);
+                    return Ok(());
+                }
+                Err(e) if e.is_transient() => {
+                    self.stats.record_failed_connection_attempt();
+                    self.stats.record_activity();
+                    let delay = self.backoff.next_delay();
+                    warn!(
+                        error=%e,
+                        attempt = self.backoff.attempt(),
+                        failed_connection_attempts = self.stats.failed_connection_attempts(),
+                        delay_millis = delay.as_millis() as u64,
+                        "unable to connect to the caseta hub. retrying in {:?}",
+                        delay
+                    );
+                    time::sleep(delay).await;
+                }
+                Err(e) => {
+                    error!(error=%e, "unable to connect to the caseta hub, and retrying won't help");
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// commands sent through this client are written to the hub while `await_message` is being
+    /// polled, so something has to keep reading messages for the commands to make progress.
+    pub fn command_client(&self) -> CasetaCommandClient {
+        CasetaCommandClient::new(self.command_sender.clone())
+    }
+
+    fn drop_connection(&mut self) {
+        self.stats.record_disconnection();
+        self.connection_manager = Option::None;
+        self.keep_alive_response_deadline = None;
+        // dropping the responders lets anyone still waiting on a response know the connection is gone
+        self.pending_commands.clear();
+    }
+
+    fn resolve_pending_command(&mut self, message: &Message) {
+        self.pending_commands
+            .retain(|pending_command| !pending_command.responder.is_closed());
+
+        let (position, response) = match message {
+            // errors don't say which command they belong to, so they go to the oldest command
+            Message::Error(integration_error) => (
+                (!self.pending_commands.is_empty()).then_some(0),
+                Err(CommandError::Rejected(*integration_error)),
+            ),
+            _ => (
+                self.pending_commands
+                    .iter()
+                    .position(|pending_command| pending_command.command.is_response(message)),
+                Ok(message.clone()),
+            ),
+        };
+
+        if let Some(pending_command) =
+            position.and_then(|index| self.pending_commands.remove(index))
+        {
+            debug!(command=%pending_command.command, "got the response to a caseta command");
+            let _ = pending_command.responder.send(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::net::{Shutdown, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use socket2::SockRef;
+    use spectral::prelude::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+
+    use crate::backoff::ExponentialBackoff;
+    use crate::caseta::command::CommandError;
+    use crate::caseta::connection::{
+        CasetaConnectionProvider, ConnectionManagerError, DefaultCasetaConnectionProvider,
+        DelegatingCasetaConnectionManager, ReadOnlyConnection, SplitConnection, TcpSocketProvider,
+        WriteOnlyConnection,
+    };
+    use crate::caseta::message::{IntegrationError, Message, OutputAction};
+    use crate::config::caseta_remote::{ButtonAction, ButtonId};
+
+    #[derive(Debug)]
+    struct FakeReadConnection {
+        messages: mpsc::Receiver<Message>,
+    }
+
+    #[async_trait]
+    impl ReadOnlyConnection for FakeReadConnection {
+        async fn await_message(&mut self) -> Result<Option<Message>, ConnectionManagerError> {
+            Ok(self.messages.recv().await)
+        }
+    }
+
+    #[derive(Debug)]
+    struct FakeWriteConnection {
+        written_messages: mpsc::UnboundedSender<String>,
+    }
+
+    #[async_trait]
+    impl WriteOnlyConnection for FakeWriteConnection {
+        async fn write_message(&mut self, message: String) -> Result<(), ConnectionManagerError> {
+            self.written_messages.send(message).unwrap();
+            Ok(())
+        }
+
+        async fn write_keep_alive_message(&mut self) -> Result<(), ConnectionManagerError> {
+            self.write_message("\r\n".to_string()).await
+        }
+    }
+
+    #[derive(Debug)]
+    struct FakeConnectionProvider {
+        failures: VecDeque<ConnectionManagerError>,
+        connection: Option<SplitConnection>,
+    }
+
+    #[async_trait]
+    impl CasetaConnectionProvider for FakeConnectionProvider {
+        async fn new_connection(&mut self) -> Result<SplitConnection, ConnectionManagerError> {
+            if let Some(failure) = self.failures.pop_front() {
+                return Err(failure);
+            }
+            self.connection.take().ok_or_else(|| {
+                ConnectionManagerError::UnrecoverableError("no more fake connections".to_string())
+            })
+        }
+    }
+
+    // the first socket can't be written to, like a connection the hub resets right after it
+    // asks for our username
+    #[derive(Debug)]
+    struct FlakyTcpSocketProvider {
+        address: SocketAddr,
+        sockets: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl TcpSocketProvider for FlakyTcpSocketProvider {
+        async fn new_socket(&self) -> Result<TcpStream, anyhow::Error> {
+            let tcp_stream = TcpStream::connect(self.address).await?;
+            if self.sockets.fetch_add(1, Ordering::SeqCst) == 0 {
+                SockRef::from(&tcp_stream).shutdown(Shutdown::Write)?;
+            }
+            Ok(tcp_stream)
         }
     }
+
+    fn fake_hub() -> (
+        DelegatingCasetaConnectionManager,
+        mpsc::Sender<Message>,
+        mpsc::UnboundedReceiver<String>,
+    ) {
+        fake_hub_with_failures(vec![])
+    }
+
+    fn fake_hub_with_failures(
+        failures: Vec<ConnectionManagerError>,
+    ) -> (
+        DelegatingCasetaConnectionManager,
+        mpsc::Sender<Message>,
+        mpsc::UnboundedReceiver<String>,
+    ) {
+        let (message_sender, messages) = mpsc::channel(8);
+        let (written_messages, written_message_receiver) = mpsc::unbounded_channel();
+        let provider = FakeConnectionProvider {
+            failures: failures.into(),
+            connection: Some((
+                Box::new(FakeReadConnection { messages }),
+                Box::new(FakeWriteConnection { written_messages }),
+            )),
+        };
+        let backoff = ExponentialBackoff::new(Duration::from_millis(1), Duration::from_millis(5));
+        (
+            DelegatingCasetaConnectionManager::new(Box::new(provider)).with_backoff(backoff),
+            message_sender,
+            written_message_receiver,
+        )
+    }
+
+    #[tokio::test]
+    async fn it_resolves_commands_with_the_matching_response() {
+        let (mut manager, message_sender, mut written_messages) = fake_hub();
+        let command_client = manager.command_client();
+        tokio::spawn(async move { while let Ok(Some(_)) = manager.await_message().await {} });
+
+        let query = tokio::spawn(async move { command_client.query_output_level(5).await });
+        let written_command = written_messages.recv().await.unwrap();
+        message_sender
+            .send(Message::OutputUpdate {
+                output_id: 6,
+                action: OutputAction::Level(10.0),
+            })
+            .await
+            .unwrap();
+        message_sender
+            .send(Message::OutputUpdate {
+                output_id: 5,
+                action: OutputAction::Level(40.0),
+            })
+            .await
+            .unwrap();
+
+        assert_that(&written_command).is_equal_to("?OUTPUT,5,1\r\n".to_string());
+        assert_that(&query.await.unwrap()).is_ok_containing(40.0);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_commands_when_the_hub_reports_an_error() {
+        let (mut manager, message_sender, mut written_messages) = fake_hub();
+        let command_client = manager.command_client();
+        tokio::spawn(async move { while let Ok(Some(_)) = manager.await_message().await {} });
+
+        let query = tokio::spawn(async move { command_client.query_output_level(99).await });
+        written_messages.recv().await.unwrap();
+        message_sender
+            .send(Message::Error(IntegrationError::ObjectDoesNotExist))
+            .await
+            .unwrap();
+
+        assert_that(&query.await.unwrap())
+            .is_err_containing(CommandError::Rejected(IntegrationError::ObjectDoesNotExist));
+    }
+
+    #[tokio::test]
+    async fn it_retries_transient_connection_failures() {
+        let (mut manager, message_sender, _written_messages) = fake_hub_with_failures(vec![
+            ConnectionManagerError::RecoverableError("connection timed out".to_string()),
+            ConnectionManagerError::RecoverableError("connection refused".to_string()),
+        ]);
+        let stats = manager.stats();
+        message_sender
+            .send(Message::Error(IntegrationError::UnsupportedCommand))
+            .await
+            .unwrap();
+
+        let message = manager.await_message().await;
+
+        assert_that(&message.unwrap())
+            .is_equal_to(Some(Message::Error(IntegrationError::UnsupportedCommand)));
+        assert_that(&stats.failed_connection_attempts()).is_equal_to(2);
+        assert_that(&stats.connections()).is_equal_to(1);
+    }
+
+    #[tokio::test]
+    async fn it_retries_when_a_write_fails_while_logging_in() {
+        let hub = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = hub.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = hub.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buffer = vec![0; 1024];
+                    socket.write_all(b"login: ").await.unwrap();
+                    if socket.read(&mut buffer).await.unwrap() == 0 {
+                        return;
+                    }
+                    socket.write_all(b"password: ").await.unwrap();
+                    let _ = socket.read(&mut buffer).await.unwrap();
+                    socket
+                        .write_all(b"\r\nGNET> ~DEVICE,2,2,3\r\n")
+                        .await
+                        .unwrap();
+                    std::future::pending::<()>().await;
+                });
+            }
+        });
+        let provider = DefaultCasetaConnectionProvider::new(
+            "lutron".to_string(),
+            "integration".to_string(),
+            Box::new(FlakyTcpSocketProvider {
+                address,
+                sockets: AtomicUsize::new(0),
+            }),
+        );
+        let mut manager = DelegatingCasetaConnectionManager::new(Box::new(provider)).with_backoff(
+            ExponentialBackoff::new(Duration::from_millis(1), Duration::from_millis(5)),
+        );
+
+        let message = tokio::time::timeout(Duration::from_secs(5), manager.await_message())
+            .await
+            .expect("the manager should log in on its second attempt");
+
+        assert_that(&message.unwrap()).is_equal_to(Some(Message::ButtonEvent {
+            remote_id: 2,
+            button_id: ButtonId::PowerOn,
+            button_action: ButtonAction::Press,
+        }));
+        assert_that(&manager.stats().failed_connection_attempts()).is_equal_to(1);
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_when_the_hub_rejects_our_credentials() {
+        let (mut manager, _message_sender, _written_messages) =
+            fake_hub_with_failures(vec![ConnectionManagerError::AuthenticationError(
+                "bad password".to_string(),
+            )]);
+
+        let message = manager.await_message().await;
+
+        assert!(matches!(
+            message,
+            Err(ConnectionManagerError::AuthenticationError(_))
+        ));
+        assert_that(&manager.stats().failed_connection_attempts()).is_equal_to(0);
+    }
+
+    #[tokio::test]
+    async fn it_tracks_keep_alive_answers_for_the_health_checks() {
+        let (manager, message_sender, mut written_messages) = fake_hub();
+        let mut manager =
+            manager.with_keep_alive(Duration::from_millis(10), Duration::from_secs(1));
+        let stats = manager.stats();
+        let answered_before_connecting = stats.since_last_message();
+        let hub = tokio::spawn(async move {
+            written_messages.recv().await;
+            // long enough that the answer can't be mistaken for logging in
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            message_sender.send(Message::LoggedIn).await.unwrap();
+            message_sender
+                .send(Message::OutputUpdate {
+                    output_id: 5,
+                    action: OutputAction::Level(40.0),
+                })
+                .await
+                .unwrap();
+            message_sender
+        });
+
+        let message = manager.await_message().await;
+        let _message_sender = hub.await.unwrap();
+
+        assert_that(&message.is_ok()).is_true();
+        assert_that(&answered_before_connecting).is_none();
+        assert_that(&stats.is_logged_in()).is_true();
+        assert_that(&stats.since_last_message().unwrap()).is_less_than(Duration::from_millis(40));
+        assert_that(&stats.since_last_activity()).is_some();
+    }
+
+    #[tokio::test]
+    async fn it_counts_any_message_as_hearing_from_the_hub() {
+        let (manager, message_sender, _written_messages) = fake_hub();
+        // long enough that no keep alive goes out during the test
+        let mut manager = manager.with_keep_alive(Duration::from_secs(60), Duration::from_secs(1));
+        let stats = manager.stats();
+        let hub = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            message_sender
+                .send(Message::ButtonEvent {
+                    remote_id: 2,
+                    button_id: ButtonId::PowerOn,
+                    button_action: ButtonAction::Press,
+                })
+                .await
+                .unwrap();
+            message_sender
+        });
+
+        let message = manager.await_message().await;
+        let _message_sender = hub.await.unwrap();
+
+        assert_that(&message.is_ok()).is_true();
+        assert_that(&stats.since_last_message().unwrap()).is_less_than(Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn it_replaces_the_connection_when_the_hub_misses_a_keep_alive() {
+        let (manager, _message_sender, mut written_messages) = fake_hub();
+        let mut manager =
+            manager.with_keep_alive(Duration::from_millis(10), Duration::from_millis(10));
+        let stats = manager.stats();
+
+        // the fake provider only has one connection, so reconnecting fails
+        let result = manager.await_message().await;
+
+        assert_that(&written_messages.recv().await).is_equal_to(Some("\r\n".to_string()));
+        assert!(matches!(
+            result,
+            Err(ConnectionManagerError::UnrecoverableError(_))
+        ));
+        assert_that(&stats.missed_keep_alives()).is_equal_to(1);
+        assert_that(&stats.connections()).is_equal_to(1);
+        assert_that(&stats.is_logged_in()).is_false();
+    }
 }
@@ -1,51 +1,378 @@
 use crate::config::caseta_remote::{ButtonAction, ButtonId, RemoteId};
-use std::fmt::{Debug, Display};
-use std::str::FromStr;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::{FromStr, Utf8Error};
+use thiserror::Error;
 
-#[derive(Debug, PartialEq)]
+pub type IntegrationId = u8;
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Message {
     ButtonEvent {
         remote_id: RemoteId,
         button_id: ButtonId,
         button_action: ButtonAction,
     },
+    /// any `~DEVICE` report that isn't a button press or release, e.g. the reply to a `?DEVICE`
+    /// query for a keypad LED
+    DeviceUpdate {
+        device_id: IntegrationId,
+        component: u8,
+        action: u8,
+        parameters: Vec<String>,
+    },
+    OutputUpdate {
+        output_id: IntegrationId,
+        action: OutputAction,
+    },
+    OccupancyUpdate {
+        group_id: IntegrationId,
+        state: OccupancyState,
+    },
+    Error(IntegrationError),
+    System {
+        action: SystemAction,
+        parameters: Vec<String>,
+    },
+    Monitoring {
+        monitoring_type: MonitoringType,
+        enabled: bool,
+    },
     LoggedIn,
     LoginPrompt,
     PasswordPrompt,
 }
 
+/// what a dimmer or switch is doing, as reported by `~OUTPUT,<id>,<action>,<parameters...>`
+#[derive(Debug, PartialEq, Clone)]
+pub enum OutputAction {
+    /// the output's level, from 0 (off) to 100 (fully on)
+    Level(f32),
+    StartRaising,
+    StartLowering,
+    StopRaisingOrLowering,
+    Other {
+        action: u8,
+        parameters: Vec<String>,
+    },
+}
+
+/// the state of an occupancy group, as reported by `~GROUP,<id>,3,<state>`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OccupancyState {
+    Occupied,
+    Unoccupied,
+    Unknown,
+}
+
+/// the hub's response to a command it couldn't execute, as reported by `~ERROR,<code>`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum IntegrationError {
+    ParameterCountMismatch,
+    ObjectDoesNotExist,
+    InvalidActionNumber,
+    ParameterDataOutOfRange,
+    ParameterDataMalformed,
+    UnsupportedCommand,
+    Other(u8),
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum SystemAction {
+    TimeOfDay,
+    Date,
+    LatitudeAndLongitude,
+    TimeZone,
+    Sunset,
+    Sunrise,
+    OsRevision,
+    LoadShed,
+    Other(u8),
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum MonitoringType {
+    Diagnostic,
+    Event,
+    Button,
+    Led,
+    Zone,
+    Occupancy,
+    Photosensor,
+    Scene,
+    SystemVariable,
+    ReplyState,
+    PromptState,
+    All,
+    Other(u8),
+}
+
+const BUTTON_PRESS_ACTION: u8 = 3;
+const BUTTON_RELEASE_ACTION: u8 = 4;
+const OCCUPANCY_GROUP_ACTION: u8 = 3;
+pub(crate) const MONITORING_ENABLED: u8 = 1;
+pub(crate) const MONITORING_DISABLED: u8 = 2;
+
+impl From<u8> for IntegrationError {
+    fn from(code: u8) -> Self {
+        match code {
+            1 => IntegrationError::ParameterCountMismatch,
+            2 => IntegrationError::ObjectDoesNotExist,
+            3 => IntegrationError::InvalidActionNumber,
+            4 => IntegrationError::ParameterDataOutOfRange,
+            5 => IntegrationError::ParameterDataMalformed,
+            6 => IntegrationError::UnsupportedCommand,
+            other => IntegrationError::Other(other),
+        }
+    }
+}
+
+impl From<u8> for SystemAction {
+    fn from(action: u8) -> Self {
+        match action {
+            1 => SystemAction::TimeOfDay,
+            2 => SystemAction::Date,
+            4 => SystemAction::LatitudeAndLongitude,
+            5 => SystemAction::TimeZone,
+            6 => SystemAction::Sunset,
+            7 => SystemAction::Sunrise,
+            8 => SystemAction::OsRevision,
+            11 => SystemAction::LoadShed,
+            other => SystemAction::Other(other),
+        }
+    }
+}
+
+impl From<u8> for MonitoringType {
+    fn from(monitoring_type: u8) -> Self {
+        match monitoring_type {
+            1 => MonitoringType::Diagnostic,
+            2 => MonitoringType::Event,
+            3 => MonitoringType::Button,
+            4 => MonitoringType::Led,
+            5 => MonitoringType::Zone,
+            6 => MonitoringType::Occupancy,
+            7 => MonitoringType::Photosensor,
+            8 => MonitoringType::Scene,
+            10 => MonitoringType::SystemVariable,
+            11 => MonitoringType::ReplyState,
+            12 => MonitoringType::PromptState,
+            255 => MonitoringType::All,
+            other => MonitoringType::Other(other),
+        }
+    }
+}
+
+impl From<MonitoringType> for u8 {
+    fn from(monitoring_type: MonitoringType) -> Self {
+        match monitoring_type {
+            MonitoringType::Diagnostic => 1,
+            MonitoringType::Event => 2,
+            MonitoringType::Button => 3,
+            MonitoringType::Led => 4,
+            MonitoringType::Zone => 5,
+            MonitoringType::Occupancy => 6,
+            MonitoringType::Photosensor => 7,
+            MonitoringType::Scene => 8,
+            MonitoringType::SystemVariable => 10,
+            MonitoringType::ReplyState => 11,
+            MonitoringType::PromptState => 12,
+            MonitoringType::All => 255,
+            MonitoringType::Other(other) => other,
+        }
+    }
+}
+
+impl Display for IntegrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Display for MonitoringType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MessageParseError {
+    #[error("the message is missing its {field} field: {message}")]
+    MissingField {
+        field: &'static str,
+        message: String,
+    },
+    #[error("only numeric values are allowed for the {field} field, but got {value}")]
+    InvalidNumber { field: &'static str, value: String },
+    #[error("{0} is not a valid button id")]
+    UnknownButtonId(u8),
+    #[error("{value} is not a valid value for the {field} field")]
+    UnknownValue { field: &'static str, value: u8 },
+    #[error("the message contains bytes that cannot be parsed from utf-8 into a String: {0}")]
+    InvalidUtf8(#[from] Utf8Error),
+    #[error("got an un-parseable message: {0}")]
+    UnknownMessage(String),
+}
+
+impl MessageParseError {
+    /// lines that are well formed, but describe something we don't handle (e.g. an event from a
+    /// dimmer or sensor button we don't have a mapping for) can be skipped without
+    /// invalidating the rest of the stream.
+    pub fn is_unknown_device_event(&self) -> bool {
+        matches!(
+            self,
+            MessageParseError::UnknownButtonId(_) | MessageParseError::UnknownValue { .. }
+        )
+    }
+}
+
+fn parse_field<T: FromStr>(
+    parts: &[&str],
+    index: usize,
+    field: &'static str,
+    message: &str,
+) -> Result<T, MessageParseError> {
+    let value = parts.get(index).ok_or(MessageParseError::MissingField {
+        field,
+        message: message.to_string(),
+    })?;
+    value.parse().map_err(|_| MessageParseError::InvalidNumber {
+        field,
+        value: value.to_string(),
+    })
+}
+
+fn remaining_fields(parts: &[&str], index: usize) -> Vec<String> {
+    parts
+        .iter()
+        .skip(index)
+        .map(|part| part.to_string())
+        .collect()
+}
+
+impl Message {
+    fn parse_device(parts: &[&str], message: &str) -> Result<Message, MessageParseError> {
+        let device_id = parse_field(parts, 1, "device id", message)?;
+        let component: u8 = parse_field(parts, 2, "component", message)?;
+        let action: u8 = parse_field(parts, 3, "device action", message)?;
+        if action != BUTTON_PRESS_ACTION && action != BUTTON_RELEASE_ACTION {
+            return Ok(Message::DeviceUpdate {
+                device_id,
+                component,
+                action,
+                parameters: remaining_fields(parts, 4),
+            });
+        }
+
+        Ok(Message::ButtonEvent {
+            remote_id: device_id,
+            button_id: component
+                .try_into()
+                .map_err(|_| MessageParseError::UnknownButtonId(component))?,
+            button_action: action
+                .try_into()
+                .map_err(|_| MessageParseError::UnknownValue {
+                    field: "device action",
+                    value: action,
+                })?,
+        })
+    }
+
+    fn parse_output(parts: &[&str], message: &str) -> Result<Message, MessageParseError> {
+        let output_id = parse_field(parts, 1, "output id", message)?;
+        let action_value: u8 = parse_field(parts, 2, "output action", message)?;
+        let action = match action_value {
+            1 => OutputAction::Level(parse_field(parts, 3, "output level", message)?),
+            2 => OutputAction::StartRaising,
+            3 => OutputAction::StartLowering,
+            4 => OutputAction::StopRaisingOrLowering,
+            other => OutputAction::Other {
+                action: other,
+                parameters: remaining_fields(parts, 3),
+            },
+        };
+        Ok(Message::OutputUpdate { output_id, action })
+    }
+
+    fn parse_group(parts: &[&str], message: &str) -> Result<Message, MessageParseError> {
+        let group_id = parse_field(parts, 1, "group id", message)?;
+        let action: u8 = parse_field(parts, 2, "group action", message)?;
+        if action != OCCUPANCY_GROUP_ACTION {
+            return Err(MessageParseError::UnknownValue {
+                field: "group action",
+                value: action,
+            });
+        }
+        let state_value: u8 = parse_field(parts, 3, "occupancy state", message)?;
+        let state = match state_value {
+            3 => OccupancyState::Occupied,
+            4 => OccupancyState::Unoccupied,
+            255 => OccupancyState::Unknown,
+            other => {
+                return Err(MessageParseError::UnknownValue {
+                    field: "occupancy state",
+                    value: other,
+                })
+            }
+        };
+        Ok(Message::OccupancyUpdate { group_id, state })
+    }
+
+    fn parse_error(parts: &[&str], message: &str) -> Result<Message, MessageParseError> {
+        let code: u8 = parse_field(parts, 1, "error code", message)?;
+        Ok(Message::Error(code.into()))
+    }
+
+    fn parse_system(parts: &[&str], message: &str) -> Result<Message, MessageParseError> {
+        let action: u8 = parse_field(parts, 1, "system action", message)?;
+        Ok(Message::System {
+            action: action.into(),
+            parameters: remaining_fields(parts, 2),
+        })
+    }
+
+    fn parse_monitoring(parts: &[&str], message: &str) -> Result<Message, MessageParseError> {
+        let monitoring_type: u8 = parse_field(parts, 1, "monitoring type", message)?;
+        let state: u8 = parse_field(parts, 2, "monitoring state", message)?;
+        let enabled = match state {
+            MONITORING_ENABLED => true,
+            MONITORING_DISABLED => false,
+            other => {
+                return Err(MessageParseError::UnknownValue {
+                    field: "monitoring state",
+                    value: other,
+                })
+            }
+        };
+        Ok(Message::Monitoring {
+            monitoring_type: monitoring_type.into(),
+            enabled,
+        })
+    }
+}
+
 impl FromStr for Message {
-    type Err = String;
+    type Err = MessageParseError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        if s.starts_with("login: ") {
+        if s.starts_with("login:") {
             return Ok(Message::LoginPrompt);
-        } else if s.starts_with("password: ") {
+        } else if s.starts_with("password:") {
             return Ok(Message::PasswordPrompt);
         } else if s.starts_with("GNET>") {
             return Ok(Message::LoggedIn);
-        } else if s.starts_with("~DEVICE") {
-            let parts: Vec<&str> = s.trim().split(",").collect();
-            let remote_id: u8 = parts[1].parse().expect(
-                format!("only integer values are allowed here, but got {}", parts[1]).as_str(),
-            );
-            let button_id: u8 = parts[2].parse().expect(
-                format!("only integer values are allowed here, but got {}", parts[2]).as_str(),
-            );
-            let button_action_value: u8 = parts[3]
-                .parse()
-                .expect(format!("only integers are allowed, but got {}", parts[3]).as_str());
-            let parsed_message = Message::ButtonEvent {
-                remote_id,
-                button_id: button_id.try_into().expect("got an invalid button ID"),
-                button_action: button_action_value
-                    .try_into()
-                    .expect("got an invalid button action ID"),
-            };
-            return Ok(parsed_message);
         }
 
-        Err(format!("got an un-parseable message: {}", s))
+        let message = s.trim();
+        let parts: Vec<&str> = message.split(',').collect();
+        match parts[0] {
+            "~DEVICE" => Self::parse_device(&parts, message),
+            "~OUTPUT" => Self::parse_output(&parts, message),
+            "~GROUP" => Self::parse_group(&parts, message),
+            "~ERROR" => Self::parse_error(&parts, message),
+            "~SYSTEM" => Self::parse_system(&parts, message),
+            "~MONITORING" => Self::parse_monitoring(&parts, message),
+            _ => Err(MessageParseError::UnknownMessage(s.to_string())),
+        }
     }
 }
 
@@ -64,6 +391,205 @@ impl Display for Message {
                 "ButtonAction remote_id: {}, button_id: {}, button_action: {}",
                 remote_id, button_id, button_action
             ),
+            Message::DeviceUpdate {
+                device_id,
+                component,
+                action,
+                parameters,
+            } => write!(
+                f,
+                "DeviceUpdate device_id: {}, component: {}, action: {}, parameters: {}",
+                device_id,
+                component,
+                action,
+                parameters.join(",")
+            ),
+            Message::OutputUpdate { output_id, action } => {
+                write!(
+                    f,
+                    "OutputUpdate output_id: {}, action: {:?}",
+                    output_id, action
+                )
+            }
+            Message::OccupancyUpdate { group_id, state } => {
+                write!(
+                    f,
+                    "OccupancyUpdate group_id: {}, state: {:?}",
+                    group_id, state
+                )
+            }
+            Message::Error(error) => write!(f, "Error: {}", error),
+            Message::System { action, parameters } => write!(
+                f,
+                "System action: {:?}, parameters: {}",
+                action,
+                parameters.join(",")
+            ),
+            Message::Monitoring {
+                monitoring_type,
+                enabled,
+            } => write!(
+                f,
+                "Monitoring monitoring_type: {}, enabled: {}",
+                monitoring_type, enabled
+            ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use spectral::prelude::*;
+
+    use crate::caseta::message::{
+        IntegrationError, Message, MessageParseError, MonitoringType, OccupancyState, OutputAction,
+        SystemAction,
+    };
+    use crate::config::caseta_remote::{ButtonAction, ButtonId};
+
+    #[test]
+    fn it_parses_a_button_event() {
+        let message = Message::from_str("~DEVICE,4,3,4\r\n").expect("unable to parse message");
+
+        assert_that(&message).is_equal_to(Message::ButtonEvent {
+            remote_id: 4,
+            button_id: ButtonId::Favorite,
+            button_action: ButtonAction::Release,
+        });
+    }
+
+    #[test]
+    fn it_parses_two_button_pico_events() {
+        let power_on = Message::from_str("~DEVICE,3,2,3\r\n").expect("unable to parse message");
+        let power_off = Message::from_str("~DEVICE,3,4,4\r\n").expect("unable to parse message");
+
+        assert_that(&power_on).is_equal_to(Message::ButtonEvent {
+            remote_id: 3,
+            button_id: ButtonId::PowerOn,
+            button_action: ButtonAction::Press,
+        });
+        assert_that(&power_off).is_equal_to(Message::ButtonEvent {
+            remote_id: 3,
+            button_id: ButtonId::PowerOff,
+            button_action: ButtonAction::Release,
+        });
+    }
+
+    #[test]
+    fn it_returns_errors_for_malformed_device_lines() {
+        assert_that(&Message::from_str("~DEVICE,4,3")).is_err_containing(
+            MessageParseError::MissingField {
+                field: "device action",
+                message: "~DEVICE,4,3".to_string(),
+            },
+        );
+        assert_that(&Message::from_str("~DEVICE,four,3,3")).is_err_containing(
+            MessageParseError::InvalidNumber {
+                field: "device id",
+                value: "four".to_string(),
+            },
+        );
+        assert_that(&Message::from_str("~DEVICE,4,300,3")).is_err_containing(
+            MessageParseError::InvalidNumber {
+                field: "component",
+                value: "300".to_string(),
+            },
+        );
+    }
+
+    #[test]
+    fn it_flags_unknown_buttons() {
+        let unknown_button = Message::from_str("~DEVICE,4,1,3").unwrap_err();
+
+        assert_that(&unknown_button).is_equal_to(MessageParseError::UnknownButtonId(1));
+        assert_that(&unknown_button.is_unknown_device_event()).is_true();
+    }
+
+    #[test]
+    fn it_parses_non_button_device_reports() {
+        let led_state = Message::from_str("~DEVICE,4,82,9,1").expect("unable to parse message");
+
+        assert_that(&led_state).is_equal_to(Message::DeviceUpdate {
+            device_id: 4,
+            component: 82,
+            action: 9,
+            parameters: vec!["1".to_string()],
+        });
+    }
+
+    #[test]
+    fn it_parses_output_updates() {
+        let level = Message::from_str("~OUTPUT,7,1,42.50").expect("unable to parse message");
+        let raising = Message::from_str("~OUTPUT,7,2").expect("unable to parse message");
+        let other = Message::from_str("~OUTPUT,7,29,6").expect("unable to parse message");
+
+        assert_that(&level).is_equal_to(Message::OutputUpdate {
+            output_id: 7,
+            action: OutputAction::Level(42.5),
+        });
+        assert_that(&raising).is_equal_to(Message::OutputUpdate {
+            output_id: 7,
+            action: OutputAction::StartRaising,
+        });
+        assert_that(&other).is_equal_to(Message::OutputUpdate {
+            output_id: 7,
+            action: OutputAction::Other {
+                action: 29,
+                parameters: vec!["6".to_string()],
+            },
+        });
+        assert_that(&Message::from_str("~OUTPUT,7,1")).is_err_containing(
+            MessageParseError::MissingField {
+                field: "output level",
+                message: "~OUTPUT,7,1".to_string(),
+            },
+        );
+    }
+
+    #[test]
+    fn it_parses_occupancy_updates() {
+        let occupied = Message::from_str("~GROUP,12,3,3").expect("unable to parse message");
+        let unoccupied = Message::from_str("~GROUP,12,3,4").expect("unable to parse message");
+
+        assert_that(&occupied).is_equal_to(Message::OccupancyUpdate {
+            group_id: 12,
+            state: OccupancyState::Occupied,
+        });
+        assert_that(&unoccupied).is_equal_to(Message::OccupancyUpdate {
+            group_id: 12,
+            state: OccupancyState::Unoccupied,
+        });
+        assert_that(&Message::from_str("~GROUP,12,3,9")).is_err_containing(
+            MessageParseError::UnknownValue {
+                field: "occupancy state",
+                value: 9,
+            },
+        );
+    }
+
+    #[test]
+    fn it_parses_hub_responses() {
+        assert_that(&Message::from_str("~ERROR,2").unwrap())
+            .is_equal_to(Message::Error(IntegrationError::ObjectDoesNotExist));
+        assert_that(&Message::from_str("~SYSTEM,1,14:02:33").unwrap()).is_equal_to(
+            Message::System {
+                action: SystemAction::TimeOfDay,
+                parameters: vec!["14:02:33".to_string()],
+            },
+        );
+        assert_that(&Message::from_str("~MONITORING,5,1").unwrap()).is_equal_to(
+            Message::Monitoring {
+                monitoring_type: MonitoringType::Zone,
+                enabled: true,
+            },
+        );
+        assert_that(&Message::from_str("~MONITORING,255,2").unwrap()).is_equal_to(
+            Message::Monitoring {
+                monitoring_type: MonitoringType::All,
+                enabled: false,
+            },
+        );
+    }
+}
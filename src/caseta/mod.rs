@@ -1,3 +1,5 @@
+pub mod codec;
+pub mod command;
 pub mod connection;
 pub mod message;
 pub mod remote;
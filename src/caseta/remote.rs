@@ -1,7 +1,9 @@
 use crate::client::dispatcher::{DeviceAction, DeviceActionMessage};
-use crate::config::caseta_remote::{ButtonAction, ButtonId};
+use crate::config::caseta_remote::{ButtonAction, ButtonId, RemoteId};
+use crate::metrics::metrics;
 use anyhow::{bail, ensure};
 use anyhow::{Ok, Result};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -18,6 +20,9 @@ const REMOTE_WATCHER_LOOP_SLEEP_DURATION: Duration = Duration::from_millis(250);
 // so a 5 second hard timeout here is probably enough to capture the longest long presses
 const REMOTE_WATCHER_LOOP_MAXIMUM_DURATION: Duration = Duration::from_secs(5);
 
+/// the latest watcher for each remote that's been pressed
+pub type RemoteWatchers = Arc<Mutex<HashMap<RemoteId, Arc<RemoteWatcher>>>>;
+
 #[derive(Debug)]
 pub struct RemoteWatcher {
     pub remote_history: Arc<Mutex<RemoteHistory>>,
@@ -122,9 +127,16 @@ impl RemoteHistory {
     }
 
     pub fn is_finished(&self) -> bool {
-        let now = Instant::now();
-        let elapsed_tracking_time = now.duration_since(self.tracking_started_at);
-        self.finished || elapsed_tracking_time >= REMOTE_WATCHER_LOOP_MAXIMUM_DURATION
+        self.finished || self.has_timed_out()
+    }
+
+    /// how long ago the first press came in
+    pub fn tracking_duration(&self) -> Duration {
+        Instant::now().duration_since(self.tracking_started_at)
+    }
+
+    fn has_timed_out(&self) -> bool {
+        self.tracking_duration() >= REMOTE_WATCHER_LOOP_MAXIMUM_DURATION
     }
 }
 
@@ -171,8 +183,12 @@ pub async fn remote_watcher_loop(watcher: Arc<RemoteWatcher>) {
             let button_state = button_state.as_ref().unwrap();
             match button_state {
                 ButtonState::FirstPressAwaitingRelease => {
-                    // perform the long press started action here
                     debug!(remote_id=remote_id, button_id=%button_id, button_state=%button_state, "a long press has started but not finished");
+                    device_action_message = Option::Some(DeviceActionMessage::new(
+                        DeviceAction::LongPressStart,
+                        remote_id,
+                        button_id,
+                    ));
                 }
                 ButtonState::FirstPressAndFirstRelease => {
                     // perform the single press action
@@ -207,11 +223,8 @@ pub async fn remote_watcher_loop(watcher: Arc<RemoteWatcher>) {
         finished = locked_history.is_finished();
     }
 
-    match device_action_message {
-        Some(message) => {
-            watcher.action_sender.send(message).await.unwrap();
-        }
-        None => {}
+    if let Some(message) = device_action_message.take() {
+        send_device_action(&watcher, message).await;
     }
 
     if finished {
@@ -223,6 +236,7 @@ pub async fn remote_watcher_loop(watcher: Arc<RemoteWatcher>) {
         let history = watcher.remote_history.clone();
         {
             let mut locked_history = history.lock().unwrap();
+            let timed_out = locked_history.has_timed_out();
             let button_state = locked_history
                 .button_state
                 .as_ref()
@@ -238,6 +252,17 @@ pub async fn remote_watcher_loop(watcher: Arc<RemoteWatcher>) {
                         button_id,
                     ));
                 }
+                ButtonState::FirstPressAwaitingRelease if timed_out => {
+                    // the hub stops reporting a held button after a while, so we may never see
+                    // the release. end the long press ourselves so anything it started gets stopped
+                    locked_history.finished = true;
+                    debug!(remote_id=%remote_id, button_id=%button_id, "a long press timed out before we saw the release");
+                    device_action_message = Option::Some(DeviceActionMessage::new(
+                        DeviceAction::LongPressComplete,
+                        remote_id,
+                        button_id,
+                    ));
+                }
                 ButtonState::FirstPressAwaitingRelease => {
                     // a long press is still ongoing here. continue onward
                     debug!(remote_id=%remote_id, button_id=%button_id, "a long press is still ongoing here");
@@ -262,17 +287,27 @@ pub async fn remote_watcher_loop(watcher: Arc<RemoteWatcher>) {
                     ));
                 }
             }
-            finished = locked_history.is_finished();
+            finished = locked_history.finished || timed_out;
         }
 
-        match device_action_message {
-            Some(message) => {
-                watcher.action_sender.send(message).await.unwrap();
-            }
-            None => {}
+        if let Some(message) = device_action_message.take() {
+            send_device_action(&watcher, message).await;
         }
         if finished {
             return;
         }
     }
 }
+
+async fn send_device_action(watcher: &RemoteWatcher, message: DeviceActionMessage) {
+    if let DeviceActionMessage::ButtonPress {
+        device_action,
+        remote_id,
+        button_id,
+    } = message
+    {
+        let since_first_press = watcher.remote_history.lock().unwrap().tracking_duration();
+        metrics().record_device_action(remote_id, button_id, device_action, since_first_press);
+    }
+    watcher.action_sender.send(message).await.unwrap();
+}
@@ -0,0 +1,47 @@
+use anyhow::{bail, Result};
+
+use crate::cli::{configured_hue_client, CheckConfigArguments};
+use crate::config::caseta_remote::get_caseta_remote_configuration;
+use crate::config::scene::get_raw_room_configurations;
+use crate::config::validation::{check_against_bridge, validate_topology};
+
+/// checks the configuration files, then the hue ids in them against the bridge
+pub async fn run(arguments: CheckConfigArguments) -> Result<()> {
+    let remote_configuration = get_caseta_remote_configuration()?;
+    let home_configuration =
+        match validate_topology(&remote_configuration, get_raw_room_configurations()?) {
+            Ok(home_configuration) => home_configuration,
+            Err(errors) => {
+                println!("{}", errors);
+                bail!("the remote and scene configuration isn't valid")
+            }
+        };
+    println!(
+        "the remote and scene configuration has {} rooms and {} remotes",
+        home_configuration.rooms.len(),
+        remote_configuration.remotes.len()
+    );
+    if arguments.skip_bridge {
+        return Ok(());
+    }
+
+    let report = check_against_bridge(&home_configuration, &configured_hue_client()?).await?;
+    if !report.unconfigured_scenes.is_empty() {
+        println!("these hue scenes aren't used by any configured scene:");
+        for scene in report.unconfigured_scenes.iter() {
+            println!("  - {}", scene);
+        }
+    }
+    if !report.problems.is_empty() {
+        println!("these hue ids don't match the bridge:");
+        for problem in report.problems.iter() {
+            println!("  - {}", problem);
+        }
+        bail!(
+            "found {} hue id(s) that don't match the bridge",
+            report.problems.len()
+        )
+    }
+    println!("every configured hue id matches the bridge");
+    Ok(())
+}
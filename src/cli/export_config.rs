@@ -0,0 +1,265 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde_yaml::{Mapping, Sequence, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::cli::{configured_hue_client, ExportConfigArguments};
+use crate::client::model::hue::{HueReference, HueRoom, HueScene};
+use crate::config::config_file::update_yaml_file;
+use crate::config::scene::{scene_configuration_file_name, HomeConfiguration};
+
+/// writes the bridge's rooms and scenes into the scene configuration file
+pub async fn run(arguments: ExportConfigArguments) -> Result<()> {
+    let hue_client = configured_hue_client()?;
+    let rooms = hue_client.get_rooms().await?;
+    let scenes = hue_client.get_scenes().await?;
+
+    let output = arguments
+        .output
+        .unwrap_or_else(|| PathBuf::from(scene_configuration_file_name()));
+    update_yaml_file(&output, |configuration| {
+        merge_bridge_configuration(configuration, &rooms, &scenes)
+    })?;
+    eprintln!(
+        "wrote {} rooms to {}. add each room's caseta remotes to its `remotes` list",
+        rooms.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Adds the bridge's rooms and hue scenes to a scene configuration.
+///
+/// Rooms are matched by `room_id`. A room that's already configured keeps its name, remotes,
+/// bindings and scenes, and only picks up hue scenes that none of its scenes use yet. New rooms
+/// get one scene per hue scene and an empty `remotes` list. Rooms without a grouped light, and
+/// new rooms without any scenes, are skipped since the listener can't drive them.
+pub(crate) fn merge_bridge_configuration(
+    configuration: &mut Mapping,
+    rooms: &HashMap<Uuid, HueRoom>,
+    scenes: &[HueScene],
+) -> Result<()> {
+    let configured_rooms = configuration
+        .entry(Value::from("rooms"))
+        .or_insert_with(|| Value::Sequence(Sequence::new()))
+        .as_sequence_mut()
+        .ok_or_else(|| anyhow!("`rooms` should be a list"))?;
+
+    let mut bridge_rooms: Vec<&HueRoom> = rooms.values().collect();
+    bridge_rooms.sort_by(|a, b| a.metadata.name.cmp(&b.metadata.name));
+    for room in bridge_rooms {
+        let grouped_light_room_id = match room.grouped_light_id() {
+            Some(grouped_light_room_id) => grouped_light_room_id,
+            None => {
+                warn!(room=%room.metadata.name, "skipping a hue room without a grouped light");
+                continue;
+            }
+        };
+        let mut room_scenes: Vec<&HueScene> = scenes
+            .iter()
+            .filter(|scene| matches!(scene.group, HueReference::Room(id) if id == room.id))
+            .collect();
+        room_scenes.sort_by(|a, b| a.metadata.name.cmp(&b.metadata.name));
+
+        let room_id = room.id.to_string();
+        let configured_room = configured_rooms
+            .iter_mut()
+            .find(|configured_room| configured_room["room_id"].as_str() == Some(&room_id));
+        match configured_room {
+            Some(configured_room) => {
+                let configured_room = configured_room
+                    .as_mapping_mut()
+                    .ok_or_else(|| anyhow!("room {} should be a mapping", room_id))?;
+                configured_room.insert(
+                    Value::from("grouped_light_room_id"),
+                    Value::from(grouped_light_room_id.to_string()),
+                );
+                let configured_scenes = configured_room
+                    .entry(Value::from("scenes"))
+                    .or_insert_with(|| Value::Sequence(Sequence::new()))
+                    .as_sequence_mut()
+                    .ok_or_else(|| anyhow!("the scenes for room {} should be a list", room_id))?;
+                let known_scene_ids = hue_scene_ids(configured_scenes);
+                configured_scenes.extend(
+                    room_scenes
+                        .into_iter()
+                        .filter(|scene| !known_scene_ids.contains(&scene.id.to_string()))
+                        .map(scene_value),
+                );
+            }
+            None if room_scenes.is_empty() => {
+                warn!(room=%room.metadata.name, "skipping a hue room without any scenes");
+            }
+            None => {
+                let mut new_room = Mapping::new();
+                new_room.insert(Value::from("name"), Value::from(room.metadata.name.clone()));
+                new_room.insert(Value::from("room_id"), Value::from(room_id));
+                new_room.insert(
+                    Value::from("grouped_light_room_id"),
+                    Value::from(grouped_light_room_id.to_string()),
+                );
+                new_room.insert(Value::from("remotes"), Value::Sequence(Sequence::new()));
+                new_room.insert(
+                    Value::from("scenes"),
+                    Value::Sequence(room_scenes.into_iter().map(scene_value).collect()),
+                );
+                configured_rooms.push(Value::Mapping(new_room));
+            }
+        }
+    }
+
+    serde_yaml::from_value::<HomeConfiguration>(Value::Mapping(configuration.clone()))
+        .context("the merged scene configuration isn't valid")?;
+    Ok(())
+}
+
+fn hue_scene_ids(scenes: &Sequence) -> HashSet<String> {
+    scenes
+        .iter()
+        .filter_map(|scene| scene["devices"].as_sequence())
+        .flatten()
+        .filter(|device| device["type"].as_str() == Some("hue_scene"))
+        .filter_map(|device| device["id"].as_str().map(String::from))
+        .collect()
+}
+
+fn scene_value(scene: &HueScene) -> Value {
+    let mut device = Mapping::new();
+    device.insert(Value::from("type"), Value::from("hue_scene"));
+    device.insert(Value::from("id"), Value::from(scene.id.to_string()));
+    device.insert(
+        Value::from("name"),
+        Value::from(scene.metadata.name.clone()),
+    );
+
+    let mut scene_value = Mapping::new();
+    scene_value.insert(
+        Value::from("name"),
+        Value::from(scene.metadata.name.clone()),
+    );
+    scene_value.insert(
+        Value::from("devices"),
+        Value::Sequence(vec![Value::Mapping(device)]),
+    );
+    Value::Mapping(scene_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_yaml::{Mapping, Value};
+    use spectral::prelude::*;
+    use uuid::Uuid;
+
+    use crate::cli::export_config::merge_bridge_configuration;
+    use crate::client::model::hue::{HueRoom, HueScene};
+    use crate::config::scene::HomeConfiguration;
+
+    const LIVING_ROOM_ID: &str = "0c329b86-a7fb-4765-8fdd-2e87f37da685";
+    const LIVING_ROOM_GROUPED_LIGHT_ID: &str = "ba8c44e4-0229-4888-8eeb-ce4a3d48cca8";
+    const OFFICE_ID: &str = "5f2a3c1e-6b1d-4c59-9a4b-0e1f2d3c4b5a";
+    const OFFICE_GROUPED_LIGHT_ID: &str = "9d8c7b6a-5f4e-4d3c-8b2a-1f0e9d8c7b6a";
+    const WARM_SCENE_ID: &str = "a3011bb2-dd50-4fd9-b143-7ea03f367088";
+    const BRIGHT_SCENE_ID: &str = "e6a1f4c2-3b5d-4e7f-9a1b-2c3d4e5f6a7b";
+    const FOCUS_SCENE_ID: &str = "c1d2e3f4-a5b6-4c7d-8e9f-0a1b2c3d4e5f";
+
+    fn room(id: &str, name: &str, grouped_light_id: &str) -> (Uuid, HueRoom) {
+        let room: HueRoom = serde_json::from_str(&format!(
+            r#"{{"id":"{}","children":[],"services":[{{"rid":"{}","rtype":"grouped_light"}}],"metadata":{{"name":"{}","archetype":"living_room"}}}}"#,
+            id, grouped_light_id, name
+        ))
+        .unwrap();
+        (room.id, room)
+    }
+
+    fn scene(id: &str, name: &str, room_id: &str) -> HueScene {
+        serde_json::from_str(&format!(
+            r#"{{"id":"{}","metadata":{{"name":"{}"}},"group":{{"rid":"{}","rtype":"room"}}}}"#,
+            id, name, room_id
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn it_merges_bridge_rooms_into_an_existing_configuration() {
+        let existing = format!(
+            r#"
+rooms:
+- name: Living Room
+  room_id: {}
+  grouped_light_room_id: {}
+  remotes: [2]
+  bindings:
+  - button: favorite
+    press: single_press_complete
+    action:
+      type: do_nothing
+  scenes:
+  - name: fireside
+    devices:
+    - type: hue_scene
+      id: {}
+      name: warm
+    - type: wemo_outlet
+      name: Fireplace
+      'on': true
+"#,
+            LIVING_ROOM_ID, LIVING_ROOM_GROUPED_LIGHT_ID, WARM_SCENE_ID
+        );
+        let mut configuration: Mapping = serde_yaml::from_str(&existing).unwrap();
+        let rooms = HashMap::from([
+            room(LIVING_ROOM_ID, "Living Room", LIVING_ROOM_GROUPED_LIGHT_ID),
+            room(OFFICE_ID, "Office", OFFICE_GROUPED_LIGHT_ID),
+        ]);
+        let scenes = vec![
+            scene(WARM_SCENE_ID, "warm", LIVING_ROOM_ID),
+            scene(BRIGHT_SCENE_ID, "bright", LIVING_ROOM_ID),
+            scene(FOCUS_SCENE_ID, "focus", OFFICE_ID),
+        ];
+
+        merge_bridge_configuration(&mut configuration, &rooms, &scenes).unwrap();
+        let merged_once = configuration.clone();
+        merge_bridge_configuration(&mut configuration, &rooms, &scenes).unwrap();
+
+        let home: HomeConfiguration =
+            serde_yaml::from_value(Value::Mapping(configuration.clone())).unwrap();
+        assert_that(&configuration).is_equal_to(merged_once);
+        assert_that(&home.rooms).has_length(2);
+        let living_room = &home.rooms[0];
+        assert_that(&living_room.remotes).is_equal_to(vec![2]);
+        assert_that(&living_room.bindings).has_length(1);
+        let scene_names: Vec<&str> = living_room
+            .scenes
+            .iter()
+            .map(|scene| scene.name.as_str())
+            .collect();
+        assert_that(&scene_names).is_equal_to(vec!["fireside", "bright"]);
+        assert_that(&living_room.scenes[0].devices).has_length(2);
+        let office = &home.rooms[1];
+        assert_that(&office.name).is_equal_to("Office".to_string());
+        assert_that(&office.grouped_light_room_id.to_string())
+            .is_equal_to(OFFICE_GROUPED_LIGHT_ID.to_string());
+        assert_that(&office.remotes).is_empty();
+        assert_that(&office.scenes).has_length(1);
+    }
+    #[test]
+    fn it_skips_new_rooms_without_scenes() {
+        let mut configuration = Mapping::new();
+        let rooms = HashMap::from([
+            room(LIVING_ROOM_ID, "Living Room", LIVING_ROOM_GROUPED_LIGHT_ID),
+            room(OFFICE_ID, "Office", OFFICE_GROUPED_LIGHT_ID),
+        ]);
+        let scenes = vec![scene(WARM_SCENE_ID, "warm", LIVING_ROOM_ID)];
+
+        merge_bridge_configuration(&mut configuration, &rooms, &scenes).unwrap();
+
+        let home: HomeConfiguration =
+            serde_yaml::from_value(Value::Mapping(configuration)).unwrap();
+        assert_that(&home.rooms).has_length(1);
+        assert_that(&home.rooms[0].name).is_equal_to("Living Room".to_string());
+    }
+}
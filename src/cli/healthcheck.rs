@@ -0,0 +1,52 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::{Client, Url};
+
+use crate::cli::HealthcheckArguments;
+use crate::config::auth_configuration::get_http_api_configuration;
+
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// asks the running listener's http api whether it's healthy, for docker's `HEALTHCHECK`
+pub async fn run(arguments: HealthcheckArguments) -> Result<()> {
+    let url = match arguments.url {
+        Some(url) => url,
+        None => configured_url(arguments.ready)?,
+    };
+    let response = Client::new()
+        .get(url.clone())
+        .timeout(HEALTHCHECK_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| anyhow!("unable to reach {}: {}", url, e))?;
+    let status = response.status();
+    println!("{}", response.text().await.unwrap_or_default());
+    if !status.is_success() {
+        bail!("{} answered {}", url, status)
+    }
+    Ok(())
+}
+
+// the listener's own health check, reached over loopback when it listens on every address
+fn configured_url(ready: bool) -> Result<Url> {
+    let http_api_configuration = get_http_api_configuration()?;
+    let port = http_api_configuration.port.ok_or_else(|| {
+        anyhow!("the http api isn't turned on, so there's nothing to ask. set http_api.port")
+    })?;
+    let address = match http_api_configuration.bind_address {
+        IpAddr::V4(address) if address.is_unspecified() => "127.0.0.1".to_string(),
+        IpAddr::V6(address) if address.is_unspecified() => "[::1]".to_string(),
+        IpAddr::V4(address) => address.to_string(),
+        IpAddr::V6(address) => format!("[{}]", address),
+    };
+    let path = match ready {
+        true => "readyz",
+        false => "healthz",
+    };
+    Ok(Url::parse(&format!(
+        "http://{}:{}/{}",
+        address, port, path
+    ))?)
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use serde_yaml::Value;
+
+use crate::cli::HuePairArguments;
+use crate::client::hue_pairing::HuePairingClient;
+use crate::client::hue_tls::HueTlsVerification;
+use crate::config::config_file::update_yaml_file;
+
+const PAIRING_APPLICATION_NAME: &str = "caseta_listener";
+// the bridge allows 19 characters after the `#` in a device type
+const MAXIMUM_DEVICE_NAME_LENGTH: usize = 19;
+
+/// pairs with the bridge, then saves the new key to the auth configuration file or prints it
+pub async fn run(arguments: HuePairArguments) -> Result<()> {
+    let tls_verification = HueTlsVerification::try_from(&arguments.tls)?;
+    let client = HuePairingClient::new(&arguments.host, &tls_verification);
+    let device_type = format!(
+        "{}#{}",
+        PAIRING_APPLICATION_NAME,
+        arguments
+            .device_name
+            .chars()
+            .take(MAXIMUM_DEVICE_NAME_LENGTH)
+            .collect::<String>()
+    );
+
+    eprintln!(
+        "press the link button on the hue bridge at {}. waiting up to {:?}",
+        arguments.host, arguments.timeout
+    );
+    let credentials = client.pair(&device_type, arguments.timeout).await?;
+
+    match arguments.config_file {
+        Some(config_file) => {
+            update_yaml_file(&config_file, |configuration| {
+                configuration.insert(
+                    Value::from("hue_application_key"),
+                    Value::from(credentials.application_key),
+                );
+                Ok(())
+            })?;
+            eprintln!("saved hue_application_key to {}", config_file.display());
+        }
+        None => println!("{}", credentials.application_key),
+    }
+    Ok(())
+}
@@ -0,0 +1,347 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use url::{Host, Url};
+
+use crate::client::hue::HueClient;
+use crate::client::hue_tls::HueTlsVerification;
+use crate::config::auth_configuration::{get_auth_configuration, HueTlsConfiguration};
+
+pub mod check_config;
+pub mod export_config;
+pub mod healthcheck;
+pub mod hue_pair;
+
+const DEFAULT_PAIRING_DEVICE_NAME: &str = "listener";
+const DEFAULT_PAIRING_TIMEOUT_SECS: u64 = 120;
+
+pub const USAGE: &str = "\
+usage:
+  caseta_listener
+      listen for caseta remote button presses
+  caseta_listener hue pair --host <bridge> [options]
+      create a hue application key. press the bridge's link button while this runs
+      --bridge-id <id>                     verify the bridge's certificate for this bridge id
+      --certificate-fingerprint <sha256>   also pin the bridge's certificate
+      --accept-invalid-certificates        skip verifying the bridge's certificate
+      --write <file>                       save the key to this auth configuration file
+      --device-name <name>                 how the bridge lists this key (default: listener)
+      --timeout-secs <secs>                how long to wait for the link button (default: 120)
+  caseta_listener export-config [--output <file>]
+      add the hue bridge's rooms and scenes to the scene configuration file, keeping what's
+      already there. it uses the hue settings from the auth configuration
+      --output <file>                      the file to update (default: the scene configuration file)
+  caseta_listener check-config [--skip-bridge]
+      check the remote and scene configuration, then compare its hue ids with the bridge
+      --skip-bridge                        only check the configuration files
+  caseta_listener healthcheck [--ready] [--url <url>]
+      exit non-zero unless the running listener's http api says it's healthy. it finds the api
+      with the http_api settings from the configuration
+      --ready                              check /readyz instead of /healthz
+      --url <url>                          the health check to ask instead
+  caseta_listener help
+      show this message";
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CliError {
+    #[error("unknown command: {0}")]
+    UnknownCommand(String),
+    #[error("unknown option: {0}")]
+    UnknownOption(String),
+    #[error("{0} needs a value")]
+    MissingValue(String),
+    #[error("{0} is required")]
+    MissingOption(&'static str),
+    #[error("{value} isn't a valid value for {option}")]
+    InvalidValue { option: String, value: String },
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Listen,
+    HuePair(HuePairArguments),
+    ExportConfig(ExportConfigArguments),
+    CheckConfig(CheckConfigArguments),
+    Healthcheck(HealthcheckArguments),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct HuePairArguments {
+    pub host: Host<String>,
+    pub tls: HueTlsConfiguration,
+    pub config_file: Option<PathBuf>,
+    pub device_name: String,
+    pub timeout: Duration,
+}
+
+#[derive(Debug)]
+pub struct ExportConfigArguments {
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct CheckConfigArguments {
+    pub skip_bridge: bool,
+}
+
+#[derive(Debug)]
+pub struct HealthcheckArguments {
+    pub ready: bool,
+    pub url: Option<Url>,
+}
+
+/// parses the command line, without the program name
+pub fn parse_arguments<I>(arguments: I) -> Result<Command, CliError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut arguments = arguments.into_iter();
+    match arguments.next().as_deref() {
+        None => Ok(Command::Listen),
+        Some("help") | Some("--help") | Some("-h") => Ok(Command::Help),
+        Some("hue") => match arguments.next().as_deref() {
+            Some("pair") => parse_hue_pair_arguments(Options::new(arguments)),
+            Some(other) => Err(CliError::UnknownCommand(format!("hue {}", other))),
+            None => Err(CliError::UnknownCommand(String::from("hue"))),
+        },
+        Some("export-config") => parse_export_config_arguments(Options::new(arguments)),
+        Some("check-config") => parse_check_config_arguments(Options::new(arguments)),
+        Some("healthcheck") => parse_healthcheck_arguments(Options::new(arguments)),
+        Some(other) => Err(CliError::UnknownCommand(other.to_string())),
+    }
+}
+
+fn parse_export_config_arguments<I>(mut options: Options<I>) -> Result<Command, CliError>
+where
+    I: Iterator<Item = String>,
+{
+    let mut output = None;
+    while let Some(option) = options.next_option()? {
+        match option.as_str() {
+            "--output" => output = Some(PathBuf::from(options.value(&option)?)),
+            _ => return Err(CliError::UnknownOption(option)),
+        }
+    }
+    Ok(Command::ExportConfig(ExportConfigArguments { output }))
+}
+
+fn parse_hue_pair_arguments<I>(mut options: Options<I>) -> Result<Command, CliError>
+where
+    I: Iterator<Item = String>,
+{
+    let mut host = None;
+    let mut tls = HueTlsConfiguration::default();
+    let mut config_file = None;
+    let mut device_name = String::from(DEFAULT_PAIRING_DEVICE_NAME);
+    let mut timeout = Duration::from_secs(DEFAULT_PAIRING_TIMEOUT_SECS);
+
+    while let Some(option) = options.next_option()? {
+        match option.as_str() {
+            "--host" => {
+                let value = options.value(&option)?;
+                host = Some(Host::parse(&value).map_err(|_| invalid_value(&option, &value))?);
+            }
+            "--bridge-id" => tls.bridge_id = Some(options.value(&option)?),
+            "--certificate-fingerprint" => {
+                tls.certificate_fingerprint = Some(options.value(&option)?)
+            }
+            "--accept-invalid-certificates" => tls.accept_invalid_certificates = true,
+            "--write" => config_file = Some(PathBuf::from(options.value(&option)?)),
+            "--device-name" => device_name = options.value(&option)?,
+            "--timeout-secs" => {
+                let value = options.value(&option)?;
+                let secs = value.parse().map_err(|_| invalid_value(&option, &value))?;
+                timeout = Duration::from_secs(secs);
+            }
+            _ => return Err(CliError::UnknownOption(option)),
+        }
+    }
+
+    Ok(Command::HuePair(HuePairArguments {
+        host: host.ok_or(CliError::MissingOption("--host"))?,
+        tls,
+        config_file,
+        device_name,
+        timeout,
+    }))
+}
+
+fn parse_check_config_arguments<I>(mut options: Options<I>) -> Result<Command, CliError>
+where
+    I: Iterator<Item = String>,
+{
+    let mut skip_bridge = false;
+    while let Some(option) = options.next_option()? {
+        match option.as_str() {
+            "--skip-bridge" => skip_bridge = true,
+            _ => return Err(CliError::UnknownOption(option)),
+        }
+    }
+    Ok(Command::CheckConfig(CheckConfigArguments { skip_bridge }))
+}
+
+fn parse_healthcheck_arguments<I>(mut options: Options<I>) -> Result<Command, CliError>
+where
+    I: Iterator<Item = String>,
+{
+    let mut ready = false;
+    let mut url = None;
+    while let Some(option) = options.next_option()? {
+        match option.as_str() {
+            "--ready" => ready = true,
+            "--url" => {
+                let value = options.value(&option)?;
+                url = Some(Url::parse(&value).map_err(|_| invalid_value(&option, &value))?);
+            }
+            _ => return Err(CliError::UnknownOption(option)),
+        }
+    }
+    Ok(Command::Healthcheck(HealthcheckArguments { ready, url }))
+}
+
+/// a hue client for the bridge in the auth configuration
+pub(crate) fn configured_hue_client() -> Result<HueClient> {
+    let auth_configuration = get_auth_configuration()?;
+    let tls_verification = HueTlsVerification::try_from(&auth_configuration.hue_tls)?;
+    Ok(HueClient::new(
+        auth_configuration.hue_host,
+        auth_configuration.hue_application_key,
+        &tls_verification,
+    ))
+}
+
+fn invalid_value(option: &str, value: &str) -> CliError {
+    CliError::InvalidValue {
+        option: option.to_string(),
+        value: value.to_string(),
+    }
+}
+
+// walks `--option value` and `--option=value` style arguments
+struct Options<I> {
+    arguments: I,
+    inline_value: Option<String>,
+}
+
+impl<I> Options<I>
+where
+    I: Iterator<Item = String>,
+{
+    fn new(arguments: I) -> Self {
+        Self {
+            arguments,
+            inline_value: None,
+        }
+    }
+
+    fn next_option(&mut self) -> Result<Option<String>, CliError> {
+        if let Some(value) = self.inline_value.take() {
+            return Err(CliError::UnknownOption(value));
+        }
+        let argument = match self.arguments.next() {
+            Some(argument) => argument,
+            None => return Ok(None),
+        };
+        if !argument.starts_with("--") {
+            return Err(CliError::UnknownOption(argument));
+        }
+        match argument.split_once('=') {
+            Some((option, value)) => {
+                self.inline_value = Some(value.to_string());
+                Ok(Some(option.to_string()))
+            }
+            None => Ok(Some(argument)),
+        }
+    }
+
+    fn value(&mut self, option: &str) -> Result<String, CliError> {
+        self.inline_value
+            .take()
+            .or_else(|| self.arguments.next())
+            .ok_or_else(|| CliError::MissingValue(option.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use spectral::prelude::*;
+
+    use crate::cli::{
+        parse_arguments, CheckConfigArguments, CliError, Command, ExportConfigArguments,
+        HealthcheckArguments,
+    };
+
+    fn parse(arguments: &str) -> Result<Command, CliError> {
+        parse_arguments(arguments.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn it_parses_hue_pair() {
+        let command = parse(
+            "hue pair --host 192.168.1.20 --bridge-id=001788fffe6a1b2c --write auth.yaml --timeout-secs 30",
+        )
+        .unwrap();
+
+        match command {
+            Command::HuePair(arguments) => {
+                assert_that(&arguments.host.to_string()).is_equal_to("192.168.1.20".to_string());
+                assert_that(&arguments.tls.bridge_id)
+                    .is_equal_to(Some("001788fffe6a1b2c".to_string()));
+                assert_that(&arguments.tls.accept_invalid_certificates).is_false();
+                assert_that(&arguments.config_file.is_some()).is_true();
+                assert_that(&arguments.device_name).is_equal_to("listener".to_string());
+                assert_that(&arguments.timeout).is_equal_to(Duration::from_secs(30));
+            }
+            other => panic!("expected hue pair, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_rejects_bad_arguments() {
+        assert!(matches!(parse(""), Ok(Command::Listen)));
+        assert!(matches!(
+            parse("check-config --skip-bridge"),
+            Ok(Command::CheckConfig(CheckConfigArguments {
+                skip_bridge: true
+            }))
+        ));
+        assert!(matches!(
+            parse("export-config --output scenes.yaml"),
+            Ok(Command::ExportConfig(ExportConfigArguments {
+                output: Some(_)
+            }))
+        ));
+        assert_that(&parse("hue pair").unwrap_err()).is_equal_to(CliError::MissingOption("--host"));
+        assert_that(&parse("hue pair --host").unwrap_err())
+            .is_equal_to(CliError::MissingValue("--host".to_string()));
+        assert_that(
+            &parse("hue pair --host bridge --accept-invalid-certificates=yes").unwrap_err(),
+        )
+        .is_equal_to(CliError::UnknownOption("yes".to_string()));
+        assert_that(&parse("hue pair --host bridge --timeout-secs soon").unwrap_err()).is_equal_to(
+            CliError::InvalidValue {
+                option: "--timeout-secs".to_string(),
+                value: "soon".to_string(),
+            },
+        );
+        assert!(matches!(
+            parse("healthcheck --ready"),
+            Ok(Command::Healthcheck(HealthcheckArguments {
+                ready: true,
+                url: None
+            }))
+        ));
+        assert_that(&parse("healthcheck --url localhost").unwrap_err()).is_equal_to(
+            CliError::InvalidValue {
+                option: "--url".to_string(),
+                value: "localhost".to_string(),
+            },
+        );
+        assert_that(&parse("pair").unwrap_err())
+            .is_equal_to(CliError::UnknownCommand("pair".to_string()));
+    }
+}
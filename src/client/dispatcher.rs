@@ -1,25 +1,35 @@
-use crate::caseta::message;
+use crate::client::driver::DeviceDrivers;
+use crate::client::grouped_light_queue::GroupedLightQueue;
 use crate::client::hue::HueClient;
+use crate::client::mqtt::MqttPublisher;
 use crate::client::room_state::CurrentRoomState;
+use crate::config::bindings::{find_action, RoomAction};
 use crate::config::caseta_remote::{ButtonId, CasetaRemote, RemoteId};
-use crate::config::scene::{Device, Room, Scene, Topology};
-use anyhow::{bail, ensure, Ok, Result};
+use crate::config::scene::{Room, Scene, SharedTopology};
+use crate::metrics::metrics;
+use anyhow::{anyhow, ensure, Ok, Result};
 use log::warn;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::Mutex;
 use tracing::{debug, instrument};
 use uuid::Uuid;
 
-use super::model::hue::{GroupedLight, HueResponse};
+use super::model::hue::{DimmingDeltaAction, GroupedLight, HueResponse, LightGroupDimmingDelta};
 use super::room_state::CurrentRoomStateCache;
 
 const BRIGHTNESS_UPDATE_AMOUNT: f32 = 10.0;
 const MAXIMUM_BRIGHTNESS_PERCENT: f32 = 100.0;
 const MINIMUM_BRIGHTNESS_PERCENT: f32 = 1.0;
+// how long a held button takes to ramp from minimum to maximum brightness. this matches how long
+// the hub keeps reporting a held button, so one long press can cover the whole range
+const FULL_RANGE_BRIGHTNESS_RAMP_DURATION: Duration = Duration::from_secs(5);
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
 pub enum DeviceAction {
     SinglePressComplete,
     DoublePressComplete,
@@ -28,59 +38,85 @@ pub enum DeviceAction {
     LongPressComplete,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct DeviceActionMessage {
-    device_action: DeviceAction,
-    remote_id: RemoteId,
-    button_id: ButtonId,
+/// something for the dispatcher to do. button presses go through the room's bindings, and room
+/// actions (from the http api, say) skip straight to the action
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceActionMessage {
+    ButtonPress {
+        device_action: DeviceAction,
+        remote_id: RemoteId,
+        button_id: ButtonId,
+    },
+    RoomAction {
+        room_name: String,
+        room_action: RoomAction,
+    },
 }
 
 impl DeviceActionMessage {
     pub fn new(device_action: DeviceAction, remote_id: RemoteId, button_id: ButtonId) -> Self {
-        DeviceActionMessage {
+        DeviceActionMessage::ButtonPress {
             device_action,
             remote_id,
             button_id,
         }
     }
+
+    pub fn room_action(room_name: &str, room_action: RoomAction) -> Self {
+        DeviceActionMessage::RoomAction {
+            room_name: room_name.to_string(),
+            room_action,
+        }
+    }
 }
 
 pub struct DeviceActionDispatcher {
     hue_client: HueClient,
-    topology: Arc<Topology>,
+    grouped_light_queue: GroupedLightQueue,
+    drivers: DeviceDrivers,
+    topology: SharedTopology,
     current_scene_cache: Arc<CurrentRoomStateCache>,
     room_mutexes: Mutex<HashMap<Uuid, Arc<Mutex<()>>>>,
+    mqtt_publisher: Option<MqttPublisher>,
 }
 
 impl DeviceActionDispatcher {
     pub fn new(
         hue_client: HueClient,
-        topology: Arc<Topology>,
+        grouped_light_queue: GroupedLightQueue,
+        drivers: DeviceDrivers,
+        topology: SharedTopology,
         current_scene_cache: Arc<CurrentRoomStateCache>,
     ) -> DeviceActionDispatcher {
         DeviceActionDispatcher {
             hue_client,
+            grouped_light_queue,
+            drivers,
             topology,
             current_scene_cache,
             room_mutexes: Mutex::new(HashMap::new()),
+            mqtt_publisher: None,
         }
     }
 
+    /// share every classified button press with the mqtt broker before acting on it
+    pub fn with_mqtt_publisher(mut self, mqtt_publisher: MqttPublisher) -> Self {
+        self.mqtt_publisher = Some(mqtt_publisher);
+        self
+    }
+
     async fn get_current_state(&self, room: &Room) -> Result<CurrentRoomState> {
         let cache_entry = self.current_scene_cache.get(&room.room_id);
-        return match cache_entry {
+        match cache_entry {
             Some(entry) => Ok(entry),
             None => {
                 let grouped_light_response = self
                     .hue_client
                     .get_grouped_light(room.grouped_light_room_id)
                     .await?;
-                Ok(Self::build_cache_entry(
-                    Option::None,
-                    &grouped_light_response,
-                ))
+                Self::build_cache_entry(Option::None, &grouped_light_response)
             }
-        };
+        }
     }
 
     fn cache_current_state(&self, room_id: Uuid, current_room_state: CurrentRoomState) {
@@ -90,25 +126,29 @@ impl DeviceActionDispatcher {
     fn build_cache_entry(
         scene: Option<Scene>,
         grouped_light_response: &HueResponse<GroupedLight>,
-    ) -> CurrentRoomState {
+    ) -> Result<CurrentRoomState> {
         let grouped_light = grouped_light_response
             .data
             .first()
-            .expect("there should be a single grouped light in this response");
+            .ok_or_else(|| anyhow!("the bridge didn't return a grouped light"))?;
         let brightness = match grouped_light.on.on {
             true => Some(grouped_light.dimming.brightness),
             false => None,
         };
-        CurrentRoomState::new(scene, brightness, grouped_light.on.on)
+        Ok(CurrentRoomState::new(
+            scene,
+            brightness,
+            grouped_light.on.on,
+        ))
     }
 
-    fn get_room_configuration(&self, remote_id: u8) -> (&CasetaRemote, &Room) {
-        let (remote, room) = &self
-            .topology
+    // the remote might have been dropped from the configuration since it was pressed
+    fn get_room_configuration(&self, remote_id: u8) -> Result<(CasetaRemote, Room)> {
+        self.topology
+            .current()
             .get(&remote_id)
-            .expect(format!("no configuration present for remote {}", remote_id).as_str());
-
-        return (remote, room);
+            .cloned()
+            .ok_or_else(|| anyhow!("no configuration present for remote {}", remote_id))
     }
 
     fn get_bounded_next_higher_brightness_val(current_value: f32) -> f32 {
@@ -123,35 +163,214 @@ impl DeviceActionDispatcher {
         f32::max(MINIMUM_BRIGHTNESS_PERCENT, next_lower_value)
     }
 
-    async fn handle_button_press(&self, message: DeviceActionMessage) -> Result<()> {
-        let room_mutex;
+    async fn handle_message(&self, message: DeviceActionMessage) -> Result<()> {
+        if let (
+            Some(mqtt_publisher),
+            DeviceActionMessage::ButtonPress {
+                device_action,
+                remote_id,
+                button_id,
+            },
+        ) = (&self.mqtt_publisher, &message)
         {
-            // lock the map of room mutexes while ensuring a room mutex exists for this room
-            let (_remote, room) = self.get_room_configuration(message.remote_id);
-            let mut room_mutexes_lock = self.room_mutexes.lock().await;
-            room_mutex = room_mutexes_lock
-                .entry(room.room_id)
-                .or_insert(Arc::new(Mutex::new(())))
-                .clone();
+            mqtt_publisher.publish_device_action(*remote_id, *button_id, *device_action);
+        }
+        let handler = match message {
+            DeviceActionMessage::ButtonPress { .. } => "button_press",
+            DeviceActionMessage::RoomAction { .. } => "room_action",
+        };
+        let started_at = Instant::now();
+        let result = self.dispatch(message).await;
+        metrics().record_dispatch(handler, result.is_ok(), started_at.elapsed());
+        if let Err(e) = &result {
+            warn!("unable to handle a {}: {:#}", handler, e);
+        }
+        result
+    }
+
+    async fn dispatch(&self, message: DeviceActionMessage) -> Result<()> {
+        match message {
+            DeviceActionMessage::ButtonPress {
+                device_action,
+                remote_id,
+                button_id,
+            } => {
+                self.handle_button_press(device_action, remote_id, button_id)
+                    .await
+            }
+            DeviceActionMessage::RoomAction {
+                room_name,
+                room_action,
+            } => self.handle_room_action(&room_name, &room_action).await,
         }
+    }
+
+    // lock the map of room mutexes while ensuring a room mutex exists for this room
+    async fn get_room_mutex(&self, room_id: Uuid) -> Arc<Mutex<()>> {
+        let mut room_mutexes_lock = self.room_mutexes.lock().await;
+        room_mutexes_lock
+            .entry(room_id)
+            .or_insert(Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    async fn handle_button_press(
+        &self,
+        device_action: DeviceAction,
+        remote_id: RemoteId,
+        button_id: ButtonId,
+    ) -> Result<()> {
+        let (remote, room) = self.get_room_configuration(remote_id)?;
+        // a held button reports a LongPressOngoing every quarter second. most of those aren't
+        // bound to anything, so don't wait on the room or ask the bridge about them
+        let is_bound = |room_on| {
+            find_action(&room.bindings, &remote, button_id, device_action, room_on).is_some()
+        };
+        if !is_bound(true) && !is_bound(false) {
+            debug!(
+                room=%room.name,
+                button_id=%button_id,
+                device_action=?device_action,
+                "no action is bound to this button press"
+            );
+            return Ok(());
+        }
+        let room_mutex = self.get_room_mutex(room.room_id).await;
 
         // get the room mutex, lock it, and hold the lock until we're done making API requests
         let _locked_room_mutex = room_mutex.lock().await;
 
-        match message.button_id {
-            ButtonId::PowerOn => self.handle_power_on_button_press(message).await,
-            ButtonId::Up => self.handle_up_button_press(message).await,
-            ButtonId::Favorite => self.handle_favorite_button_press(message).await,
-            ButtonId::Down => self.handle_down_button_press(message).await,
-            ButtonId::PowerOff => self.handle_power_off_button_press(message).await,
+        let (remote, room) = self.get_room_configuration(remote_id)?;
+        let room = &room;
+        let current_room_state = self.get_current_state(room).await?;
+        let room_action = find_action(
+            &room.bindings,
+            &remote,
+            button_id,
+            device_action,
+            current_room_state.on,
+        );
+
+        match room_action {
+            Some(room_action) => {
+                debug!(
+                    room=%room.name,
+                    button_id=%button_id,
+                    device_action=?device_action,
+                    room_action=?room_action,
+                    "performing the bound action for a button press"
+                );
+                self.perform_room_action(&room_action, room, current_room_state)
+                    .await
+            }
+            None => {
+                debug!(
+                    room=%room.name,
+                    button_id=%button_id,
+                    device_action=?device_action,
+                    "no action is bound to this button press"
+                );
+                Ok(())
+            }
         }
     }
 
-    async fn turn_room_on<'a>(
+    async fn handle_room_action(&self, room_name: &str, room_action: &RoomAction) -> Result<()> {
+        let room = self
+            .topology
+            .room_named(room_name)
+            .ok_or_else(|| anyhow!("no configuration present for room {}", room_name))?;
+        let room_mutex = self.get_room_mutex(room.room_id).await;
+        let _locked_room_mutex = room_mutex.lock().await;
+
+        let current_room_state = self.get_current_state(&room).await?;
+        debug!(room=%room.name, room_action=?room_action, "performing a requested room action");
+        self.perform_room_action(room_action, &room, current_room_state)
+            .await
+    }
+
+    async fn perform_room_action(
         &self,
-        room: &'a Room,
-        current_room_state: &CurrentRoomState,
+        room_action: &RoomAction,
+        room: &Room,
+        current_room_state: CurrentRoomState,
     ) -> Result<()> {
+        match room_action {
+            RoomAction::TurnOn if current_room_state.on => {
+                debug!("room {} is already on. nothing to do", room.name);
+                Ok(())
+            }
+            RoomAction::TurnOn => self.turn_room_on(room, &current_room_state).await,
+            RoomAction::TurnOff => self.turn_room_off(room, &current_room_state).await,
+            RoomAction::Toggle if current_room_state.on => {
+                self.turn_room_off(room, &current_room_state).await
+            }
+            RoomAction::Toggle => self.turn_room_on(room, &current_room_state).await,
+            RoomAction::RecallScene { scene } => {
+                let target_scene = Self::get_scene_by_name(room, scene)?;
+                if current_room_state.on {
+                    self.change_scene(room, current_room_state, target_scene)
+                        .await
+                } else {
+                    let mut room_state = current_room_state.clone();
+                    room_state.scene = Some(target_scene.clone());
+                    self.turn_room_on(room, &room_state).await
+                }
+            }
+            // everything else adjusts a room that's already on
+            _ if !current_room_state.on => {
+                debug!("room {} is off. nothing to adjust", room.name);
+                Ok(())
+            }
+            RoomAction::BrightnessUp { steps } => {
+                self.change_brightness(
+                    room,
+                    current_room_state,
+                    *steps,
+                    Self::get_bounded_next_higher_brightness_val,
+                )
+                .await
+            }
+            RoomAction::BrightnessDown { steps } => {
+                self.change_brightness(
+                    room,
+                    current_room_state,
+                    *steps,
+                    Self::get_bounded_next_lower_brightness_val,
+                )
+                .await
+            }
+            RoomAction::RampBrightnessUp => {
+                self.start_brightness_ramp(room, &current_room_state, DimmingDeltaAction::Up)
+                    .await
+            }
+            RoomAction::RampBrightnessDown => {
+                self.start_brightness_ramp(room, &current_room_state, DimmingDeltaAction::Down)
+                    .await
+            }
+            RoomAction::StopBrightnessRamp => {
+                self.stop_brightness_ramp(room, current_room_state).await
+            }
+            RoomAction::NextScene | RoomAction::PreviousScene | RoomAction::FirstScene => {
+                // the current scene might have been renamed or removed since it was cached
+                let target_scene = match (&current_room_state.scene, room_action) {
+                    (Some(current_scene), RoomAction::NextScene) => {
+                        Self::get_next_scene(room, current_scene)
+                    }
+                    (Some(current_scene), RoomAction::PreviousScene) => {
+                        Self::get_previous_scene(room, current_scene)
+                    }
+                    _ => None,
+                }
+                .unwrap_or_else(|| Self::get_first_scene(room));
+                self.change_scene(room, current_room_state, target_scene)
+                    .await
+            }
+            RoomAction::DoNothing => Ok(()),
+        }
+    }
+
+    async fn turn_room_on(&self, room: &Room, current_room_state: &CurrentRoomState) -> Result<()> {
         ensure!(
             !current_room_state.on,
             "cannot turn on a room that is already on. this is a bug"
@@ -160,14 +379,11 @@ impl DeviceActionDispatcher {
         let target_scene = current_room_state
             .scene
             .as_ref()
-            .or_else(|| Option::Some(Self::get_first_scene(room)))
-            .expect("rooms must have at least one configured scene. this is a bug");
+            .unwrap_or_else(|| Self::get_first_scene(room));
 
-        for device in target_scene.devices.iter() {
-            if let Device::HueScene { id, .. } = device {
-                let _response = self.hue_client.recall_scene(id, Option::None).await?;
-            }
-        }
+        self.drivers
+            .activate_scene(room, target_scene, Option::None)
+            .await?;
 
         let current_light_status = self
             .hue_client
@@ -176,258 +392,151 @@ impl DeviceActionDispatcher {
 
         self.cache_current_state(
             room.room_id,
-            Self::build_cache_entry(Option::Some(target_scene.clone()), &current_light_status),
+            Self::build_cache_entry(Option::Some(target_scene.clone()), &current_light_status)?,
         );
         Ok(())
     }
 
-    async fn handle_power_on_button_press(&self, message: DeviceActionMessage) -> Result<()> {
-        ensure!(message.button_id == ButtonId::PowerOn);
-        let (remote, room) = self.get_room_configuration(message.remote_id);
-        match remote {
-            CasetaRemote::TwoButtonPico { .. } => {
-                bail!("we haven't implemented 2 button picos yet")
-            }
-            _ => (),
-        }
-
-        let current_room_state = self.get_current_state(room).await?;
-
-        match message.device_action {
-            DeviceAction::SinglePressComplete => {
-                debug!("got a single press for remote in room {}", room.name);
-                return self.turn_room_on(room, &current_room_state).await;
-            }
-            DeviceAction::DoublePressComplete => {
-                debug!("got a double press for remote in room {}", room.name)
-            }
-            DeviceAction::LongPressStart => {
-                debug!("a long press has started in room {}", room.name)
-            }
-            DeviceAction::LongPressOngoing => {
-                debug!("a long press is still ongoing in room {}", room.name)
-            }
-            DeviceAction::LongPressComplete => {
-                debug!("our long press in {} is complete", room.name)
-            }
-        }
-
-        Ok(())
-    }
-
-    async fn handle_power_off_button_press(&self, message: DeviceActionMessage) -> Result<()> {
-        ensure!(message.button_id == ButtonId::PowerOff);
-        let (remote, room) = self.get_room_configuration(message.remote_id);
-        match remote {
-            CasetaRemote::TwoButtonPico { .. } => bail!("two button picos are not supported yet"),
-            _ => (),
-        }
-
-        let current_room_state = self.get_current_state(room).await?;
-
-        if message.device_action == DeviceAction::SinglePressComplete && !current_room_state.on {
-            debug!("room is off, but got a power off single button press, so we're turning the room on");
-            return self.turn_room_on(room, &current_room_state).await;
-        }
-
-        match message.device_action {
-            DeviceAction::SinglePressComplete
-            | DeviceAction::DoublePressComplete
-            | DeviceAction::LongPressComplete => {
-                self.hue_client.turn_off(room.grouped_light_room_id).await?;
-                let mut turned_off_scene = current_room_state.clone();
-                turned_off_scene.on = false;
-                self.cache_current_state(room.room_id, turned_off_scene);
-            }
-            DeviceAction::LongPressStart | DeviceAction::LongPressOngoing => (),
-        }
+    async fn turn_room_off(
+        &self,
+        room: &Room,
+        current_room_state: &CurrentRoomState,
+    ) -> Result<()> {
+        self.drivers.turn_off(room).await?;
+        let mut turned_off_scene = current_room_state.clone();
+        turned_off_scene.on = false;
+        self.cache_current_state(room.room_id, turned_off_scene);
         Ok(())
     }
 
-    async fn handle_up_button_press(&self, message: DeviceActionMessage) -> Result<()> {
-        ensure!(message.button_id == ButtonId::Up);
-        let (_remote, room) = self.get_room_configuration(message.remote_id);
-        let current_room_state = self.get_current_state(room).await?;
-
-        if message.device_action == DeviceAction::SinglePressComplete && !current_room_state.on {
-            debug!("room is off, but got an up button single button press, so we're turning the room on");
-            return self.turn_room_on(room, &current_room_state).await;
-        }
-
-        if !current_room_state.on {
-            // can't increase brightness of a room that's off
-            // todo: maybe a double press when it's off turns the lights on to full brightness?
+    async fn start_brightness_ramp(
+        &self,
+        room: &Room,
+        current_room_state: &CurrentRoomState,
+        direction: DimmingDeltaAction,
+    ) -> Result<()> {
+        let current_brightness = current_room_state
+            .brightness
+            .unwrap_or(MINIMUM_BRIGHTNESS_PERCENT);
+        let remaining_brightness = match direction {
+            DimmingDeltaAction::Up => MAXIMUM_BRIGHTNESS_PERCENT - current_brightness,
+            DimmingDeltaAction::Down => current_brightness - MINIMUM_BRIGHTNESS_PERCENT,
+            DimmingDeltaAction::Stop => 0.0,
+        };
+        if remaining_brightness <= 0.0 {
+            debug!("room {} can't get any brighter or dimmer", room.name);
             return Ok(());
         }
 
-        self.handle_brightness_change_button_press(
-            message,
-            room,
-            current_room_state,
-            Self::get_bounded_next_higher_brightness_val,
-        )
-        .await
+        // ramp at the same rate no matter where we start, like a lutron dimmer does
+        let transition = FULL_RANGE_BRIGHTNESS_RAMP_DURATION
+            .mul_f32(remaining_brightness / MAXIMUM_BRIGHTNESS_PERCENT);
+        self.grouped_light_queue
+            .update_dimming_delta(
+                room.grouped_light_room_id,
+                LightGroupDimmingDelta::new(direction, remaining_brightness),
+                Some(transition),
+            )
+            .await
     }
 
-    async fn handle_down_button_press(&self, message: DeviceActionMessage) -> Result<()> {
-        ensure!(message.button_id == ButtonId::Down);
-        let (_remote, room) = self.get_room_configuration(message.remote_id);
-        let current_room_state = self.get_current_state(room).await?;
-
-        if message.device_action == DeviceAction::SinglePressComplete && !current_room_state.on {
-            debug!("room is off, but got a down button single button press, so we're turning the room on");
-            return self.turn_room_on(room, &current_room_state).await;
-        }
-
-        if !current_room_state.on {
-            // can't decrease brightness of a room that's off
-            // todo: maybe a double press when it's off turns the lights on to minimum brightness?
-            return Ok(());
-        }
+    async fn stop_brightness_ramp(
+        &self,
+        room: &Room,
+        current_room_state: CurrentRoomState,
+    ) -> Result<()> {
+        self.grouped_light_queue
+            .update_dimming_delta(
+                room.grouped_light_room_id,
+                LightGroupDimmingDelta::STOP,
+                Option::None,
+            )
+            .await?;
 
-        self.handle_brightness_change_button_press(
-            message,
-            room,
-            current_room_state,
-            Self::get_bounded_next_lower_brightness_val,
-        )
-        .await
+        // the bridge decided where the ramp stopped, so ask it where we ended up
+        let current_light_status = self
+            .hue_client
+            .get_grouped_light(room.grouped_light_room_id)
+            .await?;
+        self.cache_current_state(
+            room.room_id,
+            Self::build_cache_entry(current_room_state.scene, &current_light_status)?,
+        );
+        Ok(())
     }
 
-    async fn handle_brightness_change_button_press(
+    async fn change_brightness(
         &self,
-        message: DeviceActionMessage,
         room: &Room,
         current_room_state: CurrentRoomState,
+        steps: u8,
         update_fn: fn(f32) -> f32,
     ) -> Result<()> {
-        if !current_room_state.on {
-            // can't update brightness of a room that's off
-            // todo: maybe a double press when it's off turns the lights on to full brightness?
-            return Ok(());
-        }
-
-        let mut target_brightness = current_room_state.brightness.expect(
-            format!(
+        let current_brightness = current_room_state.brightness.unwrap_or_else(|| {
+            panic!(
                 "room {} is on, but its brightness is not specified",
                 room.name
             )
-            .as_str(),
-        );
-        match message.device_action {
-            DeviceAction::SinglePressComplete
-            | DeviceAction::LongPressStart
-            | DeviceAction::LongPressOngoing => {
-                target_brightness = update_fn(target_brightness);
-            }
-            DeviceAction::DoublePressComplete => {
-                let intermediate_brightness = update_fn(target_brightness);
-                target_brightness = update_fn(intermediate_brightness);
-            }
-            DeviceAction::LongPressComplete => {
-                // no op here. the long press is over, so there's no update needed
-            }
-        }
+        });
+        let target_brightness =
+            (0..steps).fold(current_brightness, |brightness, _| update_fn(brightness));
 
-        self.hue_client
-            .update_brightness(room.grouped_light_room_id, target_brightness)
-            .await?;
+        self.drivers.set_brightness(room, target_brightness).await?;
         let mut new_room_state = current_room_state.clone();
         new_room_state.brightness = Some(target_brightness);
         self.cache_current_state(room.room_id, new_room_state);
         Ok(())
     }
 
-    async fn handle_favorite_button_press(&self, message: DeviceActionMessage) -> Result<()> {
-        ensure!(message.button_id == ButtonId::Favorite);
-        let (remote, room) = self.get_room_configuration(message.remote_id);
-        match remote {
-            CasetaRemote::TwoButtonPico { .. } => {
-                bail!("two button picos don't have favorite buttons")
-            }
-            _ => (),
-        }
-        let mut current_room_state = self.get_current_state(room).await?;
-
-        if message.device_action == DeviceAction::SinglePressComplete && !current_room_state.on {
-            debug!("room is off, but got a favorite button single button press, so we're turning the room on");
-            return self.turn_room_on(room, &current_room_state).await;
-        }
-
-        if !current_room_state.on {
-            // don't do anything to the scene if the lights in the room aren't on
-            return Ok(());
-        }
-
+    async fn change_scene(
+        &self,
+        room: &Room,
+        mut current_room_state: CurrentRoomState,
+        target_scene: &Scene,
+    ) -> Result<()> {
         let brightness = current_room_state
             .brightness
             .expect("rooms that are on must have a brightness value associated with them");
-        let target_scene;
-
-        if current_room_state.scene.is_none() {
-            target_scene = Self::get_first_scene(room);
-        } else {
-            let current_scene = current_room_state.scene.unwrap();
-            match message.device_action {
-                DeviceAction::SinglePressComplete => {
-                    target_scene = Self::get_next_scene(room, &current_scene);
-                }
-                DeviceAction::DoublePressComplete => {
-                    target_scene = Self::get_previous_scene(room, &current_scene);
-                }
-                DeviceAction::LongPressComplete => target_scene = Self::get_first_scene(room),
-                DeviceAction::LongPressStart | DeviceAction::LongPressOngoing => return Ok(()), // no actions to take for non-terminal long press states
-            }
-        }
-        // here is where we'd update the hue device
-        for device in target_scene.devices.iter() {
-            if let Device::HueScene { id, name } = device {
-                debug!(
-                    "updating the hue scene to {} at brightness level {}",
-                    name, brightness
-                );
-                let _response = self
-                    .hue_client
-                    .recall_scene(id, Option::Some(brightness))
-                    .await?;
-            }
-        }
+
+        self.drivers
+            .activate_scene(room, target_scene, Option::Some(brightness))
+            .await?;
         current_room_state.scene = Option::Some(target_scene.clone());
         self.cache_current_state(room.room_id, current_room_state);
 
         Ok(())
     }
 
-    fn get_scene_index(room: &Room, current_scene: &Scene) -> usize {
+    fn get_scene_index(room: &Room, current_scene: &Scene) -> Option<usize> {
         room.scenes
             .iter()
             .position(|scene| scene.name == current_scene.name)
-            .expect(
-                format!(
-                    "scene {} should be present in configuration, but it was not",
-                    current_scene.name
-                )
-                .as_str(),
-            )
     }
 
-    fn get_next_scene<'a>(room: &'a Room, current_scene: &Scene) -> &'a Scene {
-        let position = Self::get_scene_index(room, current_scene);
+    fn get_next_scene<'a>(room: &'a Room, current_scene: &Scene) -> Option<&'a Scene> {
+        let position = Self::get_scene_index(room, current_scene)?;
         let scene_count = room.scenes.len();
-        room.scenes.get((position + 1) % scene_count).unwrap()
+        room.scenes.get((position + 1) % scene_count)
     }
 
-    fn get_previous_scene<'a>(room: &'a Room, current_scene: &Scene) -> &'a Scene {
-        let position = Self::get_scene_index(room, current_scene);
+    fn get_previous_scene<'a>(room: &'a Room, current_scene: &Scene) -> Option<&'a Scene> {
+        let position = Self::get_scene_index(room, current_scene)?;
         let scene_count = room.scenes.len();
         let previous_scene_position = match position {
             0 => scene_count - 1,
             _ => (position - 1) % scene_count,
         };
-        room.scenes.get(previous_scene_position).unwrap()
+        room.scenes.get(previous_scene_position)
     }
 
-    fn get_first_scene<'a>(room: &'a Room) -> &'a Scene {
+    fn get_scene_by_name<'a>(room: &'a Room, scene_name: &str) -> Result<&'a Scene> {
+        room.scenes
+            .iter()
+            .find(|scene| scene.name == scene_name)
+            .ok_or_else(|| anyhow!("room {} has no scene named {}", room.name, scene_name))
+    }
+
+    fn get_first_scene(room: &Room) -> &Scene {
         room.scenes
             .first()
             .expect("Rooms must be configured with at least one scene")
@@ -439,16 +548,335 @@ pub async fn dispatcher_loop(
     dispatcher: Arc<DeviceActionDispatcher>,
     mut action_receiver: Receiver<DeviceActionMessage>,
 ) -> Result<()> {
-    loop {
-        let message = action_receiver.recv().await.unwrap();
+    while let Some(message) = action_receiver.recv().await {
         let dispatcher_instance = dispatcher.clone();
-        tokio::spawn(async move {
-            dispatcher_instance
-                .clone()
-                .handle_button_press(message)
-                .await
-        });
+        tokio::spawn(async move { dispatcher_instance.clone().handle_message(message).await });
     }
 
     warn!("exited the dispatcher loop. is the application shutting down?");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use reqwest::Url;
+    use spectral::prelude::*;
+    use uuid::Uuid;
+
+    use crate::client::dispatcher::{DeviceAction, DeviceActionDispatcher, DeviceActionMessage};
+    use crate::client::driver::{DeviceDriver, DeviceDrivers, DeviceState};
+    use crate::client::grouped_light_queue::GroupedLightQueue;
+    use crate::client::hue::HueClient;
+    use crate::client::hue_tls::HueTlsVerification;
+    use crate::client::room_state::{new_cache, CurrentRoomState, CurrentRoomStateCache};
+    use crate::client::test_server::TestServer;
+    use crate::config::bindings::RoomAction;
+    use crate::config::caseta_remote::{ButtonId, CasetaRemote};
+    use crate::config::scene::{Device, DeviceKind, Room, Scene, SharedTopology, Topology};
+
+    const ROOM_ID: &str = "0c329b86-a7fb-4765-8fdd-2e87f37da685";
+    const GROUPED_LIGHT_ID: &str = "ba8c44e4-0229-4888-8eeb-ce4a3d48cca8";
+    // nothing should reach this, since nothing listens on the discard port
+    const UNREACHABLE_BRIDGE_URL: &str = "http://127.0.0.1:9/";
+
+    #[derive(Default)]
+    struct RecordingDriver {
+        activated: Mutex<Vec<String>>,
+        turned_off: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl DeviceDriver for RecordingDriver {
+        async fn activate_scene(
+            &self,
+            _room: &Room,
+            device: &Device,
+            _brightness: Option<f32>,
+        ) -> Result<()> {
+            self.activated
+                .lock()
+                .unwrap()
+                .push(device.name().to_string());
+            Ok(())
+        }
+
+        async fn turn_off(&self, _room: &Room, device: &Device) -> Result<()> {
+            self.turned_off
+                .lock()
+                .unwrap()
+                .push(device.name().to_string());
+            Ok(())
+        }
+
+        async fn set_brightness(
+            &self,
+            _room: &Room,
+            _device: &Device,
+            _brightness: f32,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn read_state(&self, _room: &Room, _device: &Device) -> Result<DeviceState> {
+            Ok(DeviceState {
+                on: true,
+                brightness: None,
+            })
+        }
+    }
+
+    fn scene(name: &str) -> Scene {
+        Scene {
+            name: name.to_string(),
+            devices: vec![Device::WemoOutlet {
+                name: name.to_string(),
+                on: true,
+            }],
+        }
+    }
+
+    fn room_with_remote(scene_names: &[&str], remote: CasetaRemote) -> Topology {
+        let room = Room {
+            name: "Living Room".to_string(),
+            room_id: Uuid::parse_str(ROOM_ID).unwrap(),
+            grouped_light_room_id: Uuid::parse_str(GROUPED_LIGHT_ID).unwrap(),
+            scenes: scene_names.iter().map(|name| scene(name)).collect(),
+            remotes: vec![2],
+            bindings: vec![],
+        };
+        HashMap::from([(2, (remote, room))])
+    }
+
+    fn living_room(scene_names: &[&str]) -> Topology {
+        room_with_remote(
+            scene_names,
+            CasetaRemote::FiveButtonPico {
+                id: 2,
+                name: "Living Room Pico".to_string(),
+            },
+        )
+    }
+
+    fn two_button_living_room(scene_names: &[&str]) -> Topology {
+        room_with_remote(
+            scene_names,
+            CasetaRemote::TwoButtonPico {
+                id: 2,
+                name: "Living Room Pico".to_string(),
+            },
+        )
+    }
+
+    fn grouped_light_response(on: bool, brightness: f32) -> String {
+        format!(
+            r#"{{"errors":[],"data":[{{"id":"{}","on":{{"on":{}}},"dimming":{{"brightness":{}}},"owner":{{"rid":"{}","rtype":"room"}}}}]}}"#,
+            GROUPED_LIGHT_ID, on, brightness, ROOM_ID
+        )
+    }
+
+    fn dispatcher(
+        bridge_url: &str,
+        topology: SharedTopology,
+        cache: Arc<CurrentRoomStateCache>,
+    ) -> (DeviceActionDispatcher, Arc<RecordingDriver>) {
+        let hue_client = HueClient::from_bridge_url(
+            Url::parse(bridge_url).unwrap(),
+            "key".to_string(),
+            &HueTlsVerification::bridge_id("001788fffe6a1b2c"),
+        );
+        let driver = Arc::new(RecordingDriver::default());
+        let dispatcher = DeviceActionDispatcher::new(
+            hue_client.clone(),
+            GroupedLightQueue::new(hue_client).with_command_interval(Duration::from_millis(10)),
+            DeviceDrivers::new().with_driver(DeviceKind::WemoOutlet, driver.clone()),
+            topology,
+            cache,
+        );
+        (dispatcher, driver)
+    }
+
+    fn cached_room_state(cache: &CurrentRoomStateCache, on: bool, scene_name: &str) {
+        cache.insert(
+            Uuid::parse_str(ROOM_ID).unwrap(),
+            CurrentRoomState::new(Some(scene(scene_name)), Some(50.0), on),
+        );
+    }
+
+    fn press(button_id: ButtonId, device_action: DeviceAction) -> DeviceActionMessage {
+        DeviceActionMessage::new(device_action, 2, button_id)
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_the_first_scene_when_the_current_one_was_reloaded_away() {
+        let topology = SharedTopology::new(living_room(&["reading", "movie", "party"]));
+        let cache = Arc::new(new_cache());
+        cached_room_state(&cache, true, "movie");
+        let (dispatcher, driver) = dispatcher(UNREACHABLE_BRIDGE_URL, topology.clone(), cache);
+
+        topology.replace(living_room(&["reading", "cinema", "party"]));
+        for room_action in [RoomAction::NextScene, RoomAction::PreviousScene] {
+            dispatcher
+                .handle_message(DeviceActionMessage::room_action(
+                    "Living Room",
+                    room_action.clone(),
+                ))
+                .await
+                .unwrap();
+        }
+
+        assert_that(&*driver.activated.lock().unwrap()).is_equal_to(vec![
+            "reading".to_string(),
+            // the first fallback left the room on a scene it knows about
+            "party".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn it_turns_a_room_on_and_off_from_a_two_button_pico() {
+        let bridge = TestServer::start(200, &grouped_light_response(true, 60.0)).await;
+        let topology = SharedTopology::new(two_button_living_room(&["reading", "movie"]));
+        let cache = Arc::new(new_cache());
+        cached_room_state(&cache, false, "movie");
+        let (dispatcher, driver) = dispatcher(&bridge.base_url, topology, cache.clone());
+        let room_id = Uuid::parse_str(ROOM_ID).unwrap();
+
+        dispatcher
+            .handle_message(press(ButtonId::PowerOn, DeviceAction::SinglePressComplete))
+            .await
+            .unwrap();
+        let turned_on = cache.get(&room_id).unwrap();
+        dispatcher
+            .handle_message(press(ButtonId::PowerOff, DeviceAction::SinglePressComplete))
+            .await
+            .unwrap();
+
+        assert_that(&*driver.activated.lock().unwrap()).is_equal_to(vec!["movie".to_string()]);
+        assert_that(&turned_on.on).is_true();
+        assert_that(&turned_on.brightness).is_equal_to(Some(60.0));
+        // turning a room off turns off every device in every one of its scenes
+        assert_that(&*driver.turned_off.lock().unwrap())
+            .is_equal_to(vec!["reading".to_string(), "movie".to_string()]);
+        assert_that(&cache.get(&room_id).unwrap().on).is_false();
+    }
+
+    #[tokio::test]
+    async fn it_cycles_scenes_with_two_button_pico_double_presses() {
+        let topology = SharedTopology::new(two_button_living_room(&["reading", "movie", "party"]));
+        let cache = Arc::new(new_cache());
+        cached_room_state(&cache, true, "reading");
+        let (dispatcher, driver) = dispatcher(UNREACHABLE_BRIDGE_URL, topology, cache);
+
+        for button_id in [ButtonId::PowerOn, ButtonId::PowerOn, ButtonId::PowerOff] {
+            dispatcher
+                .handle_message(press(button_id, DeviceAction::DoublePressComplete))
+                .await
+                .unwrap();
+        }
+
+        assert_that(&*driver.activated.lock().unwrap()).is_equal_to(vec![
+            "movie".to_string(),
+            "party".to_string(),
+            "movie".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn it_ramps_brightness_while_a_two_button_pico_button_is_held() {
+        let bridge = TestServer::start(200, &grouped_light_response(true, 80.0)).await;
+        let topology = SharedTopology::new(two_button_living_room(&["reading"]));
+        let cache = Arc::new(new_cache());
+        cached_room_state(&cache, true, "reading");
+        let (dispatcher, _driver) = dispatcher(&bridge.base_url, topology, cache);
+
+        for button_id in [ButtonId::PowerOn, ButtonId::PowerOff] {
+            dispatcher
+                .handle_message(press(button_id, DeviceAction::LongPressStart))
+                .await
+                .unwrap();
+            dispatcher
+                .handle_message(press(button_id, DeviceAction::LongPressComplete))
+                .await
+                .unwrap();
+        }
+
+        let bodies: Vec<String> = bridge
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "PUT")
+            .map(|request| request.body)
+            .collect();
+        assert_that(&bodies).has_length(4);
+        assert_that(&bodies[0].contains(r#""action":"up""#)).is_true();
+        assert_that(&bodies[1].contains(r#""action":"stop""#)).is_true();
+        assert_that(&bodies[2].contains(r#""action":"down""#)).is_true();
+        assert_that(&bodies[3].contains(r#""action":"stop""#)).is_true();
+    }
+
+    #[tokio::test]
+    async fn it_ramps_brightness_over_the_remaining_range() {
+        let bridge = TestServer::start(200, &grouped_light_response(true, 80.0)).await;
+        let topology = SharedTopology::new(living_room(&["reading"]));
+        let cache = Arc::new(new_cache());
+        cache.insert(
+            Uuid::parse_str(ROOM_ID).unwrap(),
+            CurrentRoomState::new(Some(scene("reading")), Some(40.0), true),
+        );
+        let (dispatcher, _driver) = dispatcher(&bridge.base_url, topology, cache.clone());
+
+        for (button_id, device_action) in [
+            (ButtonId::Up, DeviceAction::LongPressStart),
+            (ButtonId::Up, DeviceAction::LongPressComplete),
+            (ButtonId::Down, DeviceAction::LongPressStart),
+        ] {
+            dispatcher
+                .handle_message(press(button_id, device_action))
+                .await
+                .unwrap();
+        }
+
+        let bodies: Vec<serde_json::Value> = bridge
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "PUT")
+            .map(|request| serde_json::from_str(&request.body).unwrap())
+            .collect();
+        assert_that(&bodies).has_length(3);
+        // 60% of the way up takes 60% of the full range ramp
+        assert_that(&bodies[0]["dimming_delta"]["action"].as_str()).is_equal_to(Some("up"));
+        assert_that(&bodies[0]["dimming_delta"]["brightness_delta"].as_f64())
+            .is_equal_to(Some(60.0));
+        assert_that(&bodies[0]["dynamics"]["duration"].as_f64().unwrap()).is_close_to(3000.0, 1.0);
+        assert_that(&bodies[1]["dimming_delta"]["action"].as_str()).is_equal_to(Some("stop"));
+        assert_that(&bodies[1]["dynamics"].is_null()).is_true();
+        // the ramp stopped at 80%, so the way down covers 79%
+        assert_that(&bodies[2]["dimming_delta"]["action"].as_str()).is_equal_to(Some("down"));
+        assert_that(&bodies[2]["dimming_delta"]["brightness_delta"].as_f64())
+            .is_equal_to(Some(79.0));
+        assert_that(&bodies[2]["dynamics"]["duration"].as_f64().unwrap()).is_close_to(3950.0, 1.0);
+        let room_state = cache.get(&Uuid::parse_str(ROOM_ID).unwrap()).unwrap();
+        assert_that(&room_state.brightness).is_equal_to(Some(80.0));
+        assert_that(&room_state.scene.map(|scene| scene.name))
+            .is_equal_to(Some("reading".to_string()));
+    }
+
+    #[tokio::test]
+    async fn it_ignores_unbound_presses_without_asking_the_bridge() {
+        let topology = SharedTopology::new(living_room(&["reading"]));
+        // with nothing cached, looking up the room's state would fail against this bridge
+        let (dispatcher, driver) =
+            dispatcher(UNREACHABLE_BRIDGE_URL, topology, Arc::new(new_cache()));
+
+        dispatcher
+            .handle_message(press(ButtonId::Up, DeviceAction::LongPressOngoing))
+            .await
+            .unwrap();
+
+        assert_that(&*driver.activated.lock().unwrap()).is_empty();
+    }
 }
@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use log::warn;
+use tracing::debug;
+
+use crate::caseta::command::CasetaCommandClient;
+use crate::client::grouped_light_queue::GroupedLightQueue;
+use crate::client::hue::HueClient;
+use crate::client::nanoleaf::NanoleafClient;
+use crate::client::wemo::WemoClient;
+use crate::config::scene::{Device, DeviceKind, Room, Scene};
+
+const CASETA_DIMMER_FADE: Duration = Duration::from_secs(1);
+
+/// what a driver reports about one of its devices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceState {
+    pub on: bool,
+    /// a percentage, for devices that can dim
+    pub brightness: Option<f32>,
+}
+
+/// Controls one kind of [`Device`]. Each method gets the room the device belongs to, since some
+/// devices (like hue scenes) act on room-level resources.
+#[async_trait]
+pub trait DeviceDriver: Send + Sync {
+    /// put the device into the state a scene describes. `brightness` is set when the room is
+    /// already on and should keep its current brightness.
+    async fn activate_scene(
+        &self,
+        room: &Room,
+        device: &Device,
+        brightness: Option<f32>,
+    ) -> Result<()>;
+
+    async fn turn_off(&self, room: &Room, device: &Device) -> Result<()>;
+
+    /// devices that can't dim ignore this
+    async fn set_brightness(&self, room: &Room, device: &Device, brightness: f32) -> Result<()>;
+
+    async fn read_state(&self, room: &Room, device: &Device) -> Result<DeviceState>;
+}
+
+/// a device that a fanned-out call failed on
+#[derive(Debug)]
+pub struct DeviceFailure {
+    pub device: String,
+    pub error: anyhow::Error,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("{} device(s) failed: {}", .failures.len(), FailureList(.failures))]
+pub struct DeviceErrors {
+    pub failures: Vec<DeviceFailure>,
+}
+
+struct FailureList<'a>(&'a [DeviceFailure]);
+
+impl Display for FailureList<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, failure) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}: {:#}", failure.device, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The drivers for each kind of device. Calls fan out to every device concurrently, and a device
+/// failing doesn't stop the others.
+#[derive(Clone, Default)]
+pub struct DeviceDrivers {
+    drivers: HashMap<DeviceKind, Arc<dyn DeviceDriver>>,
+}
+
+impl DeviceDrivers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_driver(mut self, kind: DeviceKind, driver: Arc<dyn DeviceDriver>) -> Self {
+        self.drivers.insert(kind, driver);
+        self
+    }
+
+    fn driver_for(&self, device: &Device) -> Option<&Arc<dyn DeviceDriver>> {
+        let driver = self.drivers.get(&device.kind());
+        if driver.is_none() {
+            warn!(
+                "there's no driver for {:?} devices, so we're skipping {}",
+                device.kind(),
+                device.name()
+            );
+        }
+        driver
+    }
+
+    pub async fn activate_scene(
+        &self,
+        room: &Room,
+        scene: &Scene,
+        brightness: Option<f32>,
+    ) -> Result<(), DeviceErrors> {
+        debug!(room=%room.name, scene=%scene.name, brightness=?brightness, "activating a scene");
+        self.fan_out(scene.devices.iter(), |driver, device| {
+            driver.activate_scene(room, device, brightness)
+        })
+        .await
+    }
+
+    /// turns off every device in any of the room's scenes
+    pub async fn turn_off(&self, room: &Room) -> Result<(), DeviceErrors> {
+        self.fan_out(room.devices(), |driver, device| {
+            driver.turn_off(room, device)
+        })
+        .await
+    }
+
+    pub async fn set_brightness(&self, room: &Room, brightness: f32) -> Result<(), DeviceErrors> {
+        self.fan_out(room.devices(), |driver, device| {
+            driver.set_brightness(room, device, brightness)
+        })
+        .await
+    }
+
+    /// the state of each of the room's devices that has a driver
+    pub async fn read_state<'a>(&self, room: &'a Room) -> Vec<(&'a Device, Result<DeviceState>)> {
+        let reads = room.devices().into_iter().filter_map(|device| {
+            self.driver_for(device)
+                .map(|driver| async move { (device, driver.read_state(room, device).await) })
+        });
+        join_all(reads).await
+    }
+
+    async fn fan_out<'a, F, Fut>(
+        &'a self,
+        devices: impl IntoIterator<Item = &'a Device>,
+        call: F,
+    ) -> Result<(), DeviceErrors>
+    where
+        F: Fn(&'a dyn DeviceDriver, &'a Device) -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        let calls = devices.into_iter().filter_map(|device| {
+            self.driver_for(device).map(|driver| {
+                let call = call(driver.as_ref(), device);
+                async move { (device, call.await) }
+            })
+        });
+
+        let failures: Vec<DeviceFailure> = join_all(calls)
+            .await
+            .into_iter()
+            .filter_map(|(device, result)| {
+                result.err().map(|error| DeviceFailure {
+                    device: device.name().to_string(),
+                    error,
+                })
+            })
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(DeviceErrors { failures })
+        }
+    }
+}
+
+/// hue scenes are recalled by id, but turning off and dimming act on the room's grouped light.
+/// changes go through the room's [`GroupedLightQueue`], so brightness updates return before the
+/// bridge sees them.
+#[derive(Debug, Clone)]
+pub struct HueSceneDriver {
+    hue_client: HueClient,
+    grouped_light_queue: GroupedLightQueue,
+}
+
+impl HueSceneDriver {
+    pub fn new(hue_client: HueClient, grouped_light_queue: GroupedLightQueue) -> Self {
+        Self {
+            hue_client,
+            grouped_light_queue,
+        }
+    }
+}
+
+#[async_trait]
+impl DeviceDriver for HueSceneDriver {
+    async fn activate_scene(
+        &self,
+        room: &Room,
+        device: &Device,
+        brightness: Option<f32>,
+    ) -> Result<()> {
+        if let Device::HueScene { id, name } = device {
+            debug!(
+                "recalling the hue scene {} at brightness level {:?}",
+                name, brightness
+            );
+            self.grouped_light_queue
+                .recall_scene(room.grouped_light_room_id, *id, brightness)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn turn_off(&self, room: &Room, _device: &Device) -> Result<()> {
+        self.grouped_light_queue
+            .turn_off(room.grouped_light_room_id)
+            .await
+    }
+
+    async fn set_brightness(&self, room: &Room, _device: &Device, brightness: f32) -> Result<()> {
+        self.grouped_light_queue
+            .set_brightness(room.grouped_light_room_id, brightness);
+        Ok(())
+    }
+
+    async fn read_state(&self, room: &Room, _device: &Device) -> Result<DeviceState> {
+        let grouped_light_response = self
+            .hue_client
+            .get_grouped_light(room.grouped_light_room_id)
+            .await?;
+        let grouped_light = grouped_light_response.data.first().ok_or_else(|| {
+            anyhow!(
+                "the bridge didn't return the grouped light {}",
+                room.grouped_light_room_id
+            )
+        })?;
+        Ok(DeviceState {
+            on: grouped_light.on.on,
+            brightness: Some(grouped_light.dimming.brightness),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CasetaDimmerDriver {
+    caseta_command_client: CasetaCommandClient,
+}
+
+impl CasetaDimmerDriver {
+    pub fn new(caseta_command_client: CasetaCommandClient) -> Self {
+        Self {
+            caseta_command_client,
+        }
+    }
+
+    async fn set_level(&self, device: &Device, level: f32) -> Result<()> {
+        if let Device::CasetaDimmer { id, name, .. } = device {
+            debug!("setting the caseta dimmer {} to level {}", name, level);
+            let reported_level = self
+                .caseta_command_client
+                .set_output_level(*id, level, CASETA_DIMMER_FADE)
+                .await?;
+            debug!(
+                "the caseta hub reports dimmer {} is at level {}",
+                id, reported_level
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DeviceDriver for CasetaDimmerDriver {
+    async fn activate_scene(
+        &self,
+        _room: &Room,
+        device: &Device,
+        _brightness: Option<f32>,
+    ) -> Result<()> {
+        match device {
+            Device::CasetaDimmer { level, .. } => self.set_level(device, *level).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn turn_off(&self, _room: &Room, device: &Device) -> Result<()> {
+        self.set_level(device, 0.0).await
+    }
+
+    // caseta dimmers stay at the level their scene sets
+    async fn set_brightness(&self, _room: &Room, _device: &Device, _brightness: f32) -> Result<()> {
+        Ok(())
+    }
+
+    async fn read_state(&self, _room: &Room, device: &Device) -> Result<DeviceState> {
+        let level = match device {
+            Device::CasetaDimmer { id, .. } => {
+                self.caseta_command_client.query_output_level(*id).await?
+            }
+            _ => 0.0,
+        };
+        Ok(DeviceState {
+            on: level > 0.0,
+            brightness: Some(level),
+        })
+    }
+}
+
+/// nanoleaf panels are configured in the auth configuration and looked up by name
+#[derive(Debug, Clone)]
+pub struct NanoleafDriver {
+    nanoleaf_clients: HashMap<String, NanoleafClient>,
+}
+
+impl NanoleafDriver {
+    pub fn new(nanoleaf_clients: HashMap<String, NanoleafClient>) -> Self {
+        Self { nanoleaf_clients }
+    }
+
+    fn get_client(&self, device: &Device) -> Option<&NanoleafClient> {
+        let nanoleaf_client = self.nanoleaf_clients.get(device.name());
+        if nanoleaf_client.is_none() {
+            warn!(
+                "the nanoleaf light panels {} aren't configured in the auth configuration, so we're skipping them",
+                device.name()
+            );
+        }
+        nanoleaf_client
+    }
+}
+
+#[async_trait]
+impl DeviceDriver for NanoleafDriver {
+    async fn activate_scene(
+        &self,
+        _room: &Room,
+        device: &Device,
+        brightness: Option<f32>,
+    ) -> Result<()> {
+        let (nanoleaf_client, on, effect) = match (self.get_client(device), device) {
+            (Some(nanoleaf_client), Device::NanoleafLightPanels { on, effect, .. }) => {
+                (nanoleaf_client, *on, effect)
+            }
+            _ => return Ok(()),
+        };
+
+        if !on {
+            return nanoleaf_client.set_on(false).await;
+        }
+        debug!(
+            "updating the nanoleaf {} to effect {} at brightness level {:?}",
+            device.name(),
+            effect,
+            brightness
+        );
+        nanoleaf_client.select_effect(effect).await?;
+        if let Some(brightness) = brightness {
+            nanoleaf_client.set_brightness(brightness).await?;
+        }
+        Ok(())
+    }
+
+    async fn turn_off(&self, _room: &Room, device: &Device) -> Result<()> {
+        match self.get_client(device) {
+            Some(nanoleaf_client) => nanoleaf_client.set_on(false).await,
+            None => Ok(()),
+        }
+    }
+
+    // setting the brightness turns the panels on, so leave panels that are off alone
+    async fn set_brightness(&self, _room: &Room, device: &Device, brightness: f32) -> Result<()> {
+        let nanoleaf_client = match self.get_client(device) {
+            Some(nanoleaf_client) => nanoleaf_client,
+            None => return Ok(()),
+        };
+        if nanoleaf_client.get_state().await?.on {
+            nanoleaf_client.set_brightness(brightness).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_state(&self, _room: &Room, device: &Device) -> Result<DeviceState> {
+        match self.get_client(device) {
+            Some(nanoleaf_client) => {
+                let state = nanoleaf_client.get_state().await?;
+                Ok(DeviceState {
+                    on: state.on,
+                    brightness: Some(state.brightness),
+                })
+            }
+            None => anyhow::bail!(
+                "the nanoleaf light panels {} aren't configured",
+                device.name()
+            ),
+        }
+    }
+}
+
+/// wemo outlets are configured in the auth configuration and looked up by name
+#[derive(Debug, Clone)]
+pub struct WemoDriver {
+    wemo_clients: HashMap<String, WemoClient>,
+}
+
+impl WemoDriver {
+    pub fn new(wemo_clients: HashMap<String, WemoClient>) -> Self {
+        Self { wemo_clients }
+    }
+
+    fn get_client(&self, device: &Device) -> Option<&WemoClient> {
+        let wemo_client = self.wemo_clients.get(device.name());
+        if wemo_client.is_none() {
+            warn!(
+                "the wemo outlet {} isn't configured, so we're skipping it",
+                device.name()
+            );
+        }
+        wemo_client
+    }
+
+    async fn set_binary_state(&self, device: &Device, on: bool) -> Result<()> {
+        match self.get_client(device) {
+            Some(wemo_client) => {
+                debug!("switching the wemo outlet {} to {}", device.name(), on);
+                wemo_client.set_binary_state(on).await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl DeviceDriver for WemoDriver {
+    async fn activate_scene(
+        &self,
+        _room: &Room,
+        device: &Device,
+        _brightness: Option<f32>,
+    ) -> Result<()> {
+        match device {
+            Device::WemoOutlet { on, .. } => self.set_binary_state(device, *on).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn turn_off(&self, _room: &Room, device: &Device) -> Result<()> {
+        self.set_binary_state(device, false).await
+    }
+
+    async fn set_brightness(&self, _room: &Room, _device: &Device, _brightness: f32) -> Result<()> {
+        Ok(())
+    }
+
+    async fn read_state(&self, _room: &Room, device: &Device) -> Result<DeviceState> {
+        match self.get_client(device) {
+            Some(wemo_client) => Ok(DeviceState {
+                on: wemo_client.get_binary_state().await?,
+                brightness: None,
+            }),
+            None => anyhow::bail!("the wemo outlet {} isn't configured", device.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use reqwest::Url;
+    use spectral::prelude::*;
+    use tokio::sync::Barrier;
+    use uuid::Uuid;
+
+    use crate::client::driver::{DeviceDriver, DeviceDrivers, DeviceState, HueSceneDriver};
+    use crate::client::grouped_light_queue::GroupedLightQueue;
+    use crate::client::hue::HueClient;
+    use crate::client::hue_tls::HueTlsVerification;
+    use crate::client::test_server::TestServer;
+    use crate::config::scene::{Device, DeviceKind, Room, Scene};
+
+    // waits for every outlet in the scene to be called at once, so calling them one at a time
+    // never finishes
+    struct FakeOutletDriver {
+        calls: Mutex<Vec<String>>,
+        barrier: Barrier,
+    }
+
+    #[async_trait]
+    impl DeviceDriver for FakeOutletDriver {
+        async fn activate_scene(
+            &self,
+            _room: &Room,
+            device: &Device,
+            _brightness: Option<f32>,
+        ) -> Result<()> {
+            self.barrier.wait().await;
+            self.calls.lock().unwrap().push(device.name().to_string());
+            if device.name() == "Broken Outlet" {
+                bail!("the outlet didn't answer");
+            }
+            Ok(())
+        }
+
+        async fn turn_off(&self, _room: &Room, device: &Device) -> Result<()> {
+            self.calls.lock().unwrap().push(device.name().to_string());
+            Ok(())
+        }
+
+        async fn set_brightness(
+            &self,
+            _room: &Room,
+            _device: &Device,
+            _brightness: f32,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn read_state(&self, _room: &Room, _device: &Device) -> Result<DeviceState> {
+            Ok(DeviceState {
+                on: true,
+                brightness: None,
+            })
+        }
+    }
+
+    fn outlet(name: &str) -> Device {
+        Device::WemoOutlet {
+            name: name.to_string(),
+            on: true,
+        }
+    }
+
+    fn room() -> Room {
+        let scene = |name: &str, devices| Scene {
+            name: name.to_string(),
+            devices,
+        };
+        Room {
+            name: String::from("Living Room"),
+            room_id: Uuid::new_v4(),
+            grouped_light_room_id: Uuid::new_v4(),
+            scenes: vec![
+                scene(
+                    "fireplace",
+                    vec![
+                        outlet("Fireplace"),
+                        outlet("Broken Outlet"),
+                        outlet("Tree"),
+                        Device::HueScene {
+                            id: Uuid::new_v4(),
+                            name: String::from("warm"),
+                        },
+                    ],
+                ),
+                scene("tree", vec![outlet("Tree")]),
+            ],
+            remotes: vec![2],
+            bindings: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn it_fans_out_to_every_device_and_collects_failures() {
+        let driver = Arc::new(FakeOutletDriver {
+            calls: Mutex::new(Vec::new()),
+            barrier: Barrier::new(3),
+        });
+        let drivers = DeviceDrivers::new().with_driver(DeviceKind::WemoOutlet, driver.clone());
+        let room = room();
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            drivers.activate_scene(&room, &room.scenes[0], None),
+        )
+        .await
+        .expect("the outlets should be called concurrently");
+
+        let errors = result.unwrap_err();
+        assert_that(&driver.calls.lock().unwrap().len()).is_equal_to(3);
+        assert_that(&errors.failures).has_length(1);
+        assert_that(&errors.failures[0].device).is_equal_to(String::from("Broken Outlet"));
+    }
+
+    #[tokio::test]
+    async fn it_turns_off_each_device_once() {
+        let driver = Arc::new(FakeOutletDriver {
+            calls: Mutex::new(Vec::new()),
+            barrier: Barrier::new(1),
+        });
+        let drivers = DeviceDrivers::new().with_driver(DeviceKind::WemoOutlet, driver.clone());
+
+        drivers.turn_off(&room()).await.unwrap();
+
+        let mut calls = driver.calls.lock().unwrap().clone();
+        calls.sort();
+        assert_that(&calls).is_equal_to(vec![
+            String::from("Broken Outlet"),
+            String::from("Fireplace"),
+            String::from("Tree"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn it_reports_a_grouped_light_the_bridge_doesnt_know_about() {
+        // what the bridge answers for a stale grouped light id
+        let server = TestServer::start_with_responses(vec![
+            (200, r#"{"errors":[],"data":[]}"#),
+            (404, r#"{"errors":[{"description":"Not Found"}],"data":[]}"#),
+        ])
+        .await;
+        let hue_client = HueClient::from_bridge_url(
+            Url::parse(&server.base_url).unwrap(),
+            String::from("key"),
+            &HueTlsVerification::bridge_id("001788fffe6a1b2c"),
+        );
+        let driver = HueSceneDriver::new(hue_client.clone(), GroupedLightQueue::new(hue_client));
+        let room = room();
+        let hue_scene = &room.scenes[0].devices[3];
+
+        let empty_data = driver.read_state(&room, hue_scene).await;
+        let not_found = driver.read_state(&room, hue_scene).await;
+
+        assert_that(&empty_data.unwrap_err().to_string())
+            .contains("didn't return the grouped light");
+        assert_that(&not_found.unwrap_err().to_string()).contains("404");
+    }
+}
@@ -0,0 +1,524 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::{Buf, BytesMut};
+use reqwest::Response;
+use tokio::time;
+use tokio_util::codec::Decoder;
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::backoff::ExponentialBackoff;
+use crate::client::grouped_light_queue::GroupedLightQueue;
+use crate::client::hue::HueClient;
+use crate::client::model::hue::{
+    GroupedLightUpdate, HueEvent, HueEventType, HueResourceUpdate, SceneActivity, SceneUpdate,
+};
+use crate::client::room_state::{CurrentRoomState, CurrentRoomStateCache};
+use crate::config::scene::{Device, Room, Scene, SharedTopology};
+
+const DEFAULT_INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_MAXIMUM_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// a single server-sent event. we only care about the `id` and `data` fields.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ServerSentEvent {
+    pub id: Option<String>,
+    pub data: String,
+}
+
+/// Splits a `text/event-stream` body into [`ServerSentEvent`]s.
+///
+/// Events end at a blank line. Multiple `data:` lines in one event are joined with `\n`, and
+/// comment lines (the bridge sends `: hi` when the stream opens) are skipped.
+#[derive(Debug, Default)]
+pub struct ServerSentEventCodec {
+    pending_event: Option<ServerSentEvent>,
+}
+
+impl ServerSentEventCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn handle_field(&mut self, line: &str) {
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        let event = self
+            .pending_event
+            .get_or_insert_with(ServerSentEvent::default);
+        match field {
+            "id" => event.id = Some(value.to_string()),
+            "data" => {
+                if !event.data.is_empty() {
+                    event.data.push('\n');
+                }
+                event.data.push_str(value);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Decoder for ServerSentEventCodec {
+    type Item = ServerSentEvent;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(line_end) = src.iter().position(|byte| *byte == b'\n') {
+            let line = src.split_to(line_end + 1);
+            let line = std::str::from_utf8(&line)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+                .trim_end_matches(['\r', '\n']);
+
+            if line.is_empty() {
+                match self.pending_event.take() {
+                    Some(event) if !event.data.is_empty() => return Ok(Some(event)),
+                    _ => continue,
+                }
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            self.handle_field(line);
+        }
+        Ok(None)
+    }
+}
+
+/// Keeps a [`CurrentRoomStateCache`] in sync with changes made outside of this listener, e.g.
+/// from the hue app, by following the bridge's event stream.
+#[derive(Debug)]
+pub struct HueEventStreamListener {
+    hue_client: HueClient,
+    topology: SharedTopology,
+    current_room_state_cache: Arc<CurrentRoomStateCache>,
+    grouped_light_queue: Option<GroupedLightQueue>,
+    backoff: ExponentialBackoff,
+}
+
+impl HueEventStreamListener {
+    pub fn new(
+        hue_client: HueClient,
+        topology: SharedTopology,
+        current_room_state_cache: Arc<CurrentRoomStateCache>,
+    ) -> Self {
+        Self {
+            hue_client,
+            topology,
+            current_room_state_cache,
+            grouped_light_queue: None,
+            backoff: ExponentialBackoff::new(
+                DEFAULT_INITIAL_RECONNECT_DELAY,
+                DEFAULT_MAXIMUM_RECONNECT_DELAY,
+            ),
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: ExponentialBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// lets the cache keep the brightness a room is headed toward while updates are still queued,
+    /// instead of the steps the bridge reports on the way there
+    pub fn with_grouped_light_queue(mut self, grouped_light_queue: GroupedLightQueue) -> Self {
+        self.grouped_light_queue = Some(grouped_light_queue);
+        self
+    }
+
+    async fn consume_events(&self, mut response: Response) -> Result<()> {
+        let mut codec = ServerSentEventCodec::new();
+        let mut buffer = BytesMut::new();
+        while let Some(chunk) = response.chunk().await? {
+            buffer.extend_from_slice(chunk.chunk());
+            while let Some(event) = codec.decode(&mut buffer)? {
+                self.handle_event(&event);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_event(&self, event: &ServerSentEvent) {
+        let hue_events: Vec<HueEvent> = match serde_json::from_str(&event.data) {
+            Ok(hue_events) => hue_events,
+            Err(e) => {
+                warn!(error=%e, event_id=?event.id, "unable to parse a hue event");
+                return;
+            }
+        };
+
+        hue_events
+            .into_iter()
+            .filter(|hue_event| hue_event.event_type == HueEventType::Update)
+            .flat_map(|hue_event| hue_event.data)
+            .for_each(
+                |resource| match serde_json::from_value::<HueResourceUpdate>(resource) {
+                    Ok(HueResourceUpdate::GroupedLight(update)) => {
+                        self.apply_grouped_light_update(update)
+                    }
+                    Ok(HueResourceUpdate::Scene(update)) => self.apply_scene_update(update),
+                    Ok(HueResourceUpdate::Other) => {}
+                    Err(e) => debug!(error=%e, "skipping a hue resource update we couldn't parse"),
+                },
+            );
+    }
+
+    fn find_room(&self, predicate: impl Fn(&Room) -> bool) -> Option<Room> {
+        self.topology
+            .current()
+            .values()
+            .map(|(_remote, room)| room)
+            .find(|room| predicate(room))
+            .cloned()
+    }
+
+    fn apply_grouped_light_update(&self, update: GroupedLightUpdate) {
+        let room = match self.find_room(|room| room.grouped_light_room_id == update.id) {
+            Some(room) => room,
+            None => return,
+        };
+
+        let mut new_state = match (self.current_room_state_cache.get(&room.room_id), update.on) {
+            (Some(mut state), on) => {
+                if let Some(on) = on {
+                    state.on = on.on;
+                }
+                state.brightness = match (state.on, &update.dimming) {
+                    (false, _) => None,
+                    (true, Some(dimming)) => Some(dimming.brightness),
+                    (true, None) => state.brightness,
+                };
+                state
+            }
+            // without a cached entry, we only know enough to build one when the update says
+            // whether the room is on. otherwise the dispatcher will ask the bridge when it needs to
+            (None, Some(on)) => CurrentRoomState::new(
+                None,
+                update
+                    .dimming
+                    .filter(|_| on.on)
+                    .map(|dimming| dimming.brightness),
+                on.on,
+            ),
+            (None, None) => return,
+        };
+        let pending_brightness = self
+            .grouped_light_queue
+            .as_ref()
+            .and_then(|queue| queue.pending_brightness(update.id));
+        if let (true, Some(pending_brightness)) = (new_state.on, pending_brightness) {
+            new_state.brightness = Some(pending_brightness);
+        }
+        debug!(room=%room.name, new_state=?new_state, "applying a grouped light update from the hue bridge");
+        self.cache_room_state(&room, new_state);
+    }
+
+    fn apply_scene_update(&self, update: SceneUpdate) {
+        let active = match update.status {
+            Some(status) => status.active != SceneActivity::Inactive,
+            None => return,
+        };
+        let (room, scene) = match self.find_scene(&update.id) {
+            Some(room_and_scene) => room_and_scene,
+            None => return,
+        };
+
+        let cached_state = self.current_room_state_cache.get(&room.room_id);
+        let new_state = match (cached_state, active) {
+            (Some(mut state), true) => {
+                state.scene = Some(scene.clone());
+                state
+            }
+            (None, true) => CurrentRoomState::new(Some(scene.clone()), None, true),
+            (Some(mut state), false) => {
+                if !state.scene.as_ref().is_some_and(|current_scene| {
+                    Self::contains_hue_scene(current_scene, &update.id)
+                }) {
+                    return;
+                }
+                state.scene = None;
+                state
+            }
+            (None, false) => return,
+        };
+        debug!(room=%room.name, scene=%scene.name, active, "applying a scene update from the hue bridge");
+        self.cache_room_state(&room, new_state);
+    }
+
+    // the dispatcher trusts whatever's cached, and it can't step the brightness of a room that's
+    // on at an unknown brightness. dropping the entry makes it ask the bridge instead
+    fn cache_room_state(&self, room: &Room, new_state: CurrentRoomState) {
+        if new_state.on && new_state.brightness.is_none() {
+            self.current_room_state_cache.invalidate(&room.room_id);
+            return;
+        }
+        self.current_room_state_cache
+            .insert(room.room_id, new_state);
+    }
+
+    fn find_scene(&self, hue_scene_id: &Uuid) -> Option<(Room, Scene)> {
+        self.topology
+            .current()
+            .values()
+            .map(|(_remote, room)| room)
+            .find_map(|room| {
+                room.scenes
+                    .iter()
+                    .find(|scene| Self::contains_hue_scene(scene, hue_scene_id))
+                    .map(|scene| (room.clone(), scene.clone()))
+            })
+    }
+
+    fn contains_hue_scene(scene: &Scene, hue_scene_id: &Uuid) -> bool {
+        scene
+            .devices
+            .iter()
+            .any(|device| matches!(device, Device::HueScene { id, .. } if id == hue_scene_id))
+    }
+}
+
+/// follow the hue event stream forever, reconnecting whenever the bridge drops it
+#[instrument(skip(listener))]
+pub async fn event_stream_loop(mut listener: HueEventStreamListener) {
+    loop {
+        match listener.hue_client.open_event_stream().await {
+            Ok(response) => {
+                info!("subscribed to the hue event stream");
+                listener.backoff.reset();
+                if let Err(e) = listener.consume_events(response).await {
+                    warn!(error=%e, "the hue event stream failed");
+                } else {
+                    info!("the hue bridge closed the event stream");
+                }
+                // we can't tell what we missed while disconnected, so start fresh
+                listener.current_room_state_cache.invalidate_all();
+            }
+            Err(e) => warn!(error=%e, "unable to open the hue event stream"),
+        }
+
+        let delay = listener.backoff.next_delay();
+        info!(
+            attempt = listener.backoff.attempt(),
+            "reconnecting to the hue event stream in {:?}", delay
+        );
+        time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use reqwest::Url;
+    use spectral::prelude::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Decoder;
+    use uuid::Uuid;
+
+    use crate::backoff::ExponentialBackoff;
+    use crate::client::event_stream::{
+        event_stream_loop, HueEventStreamListener, ServerSentEvent, ServerSentEventCodec,
+    };
+    use crate::client::hue::HueClient;
+    use crate::client::hue_tls::HueTlsVerification;
+    use crate::client::room_state::{new_cache, CurrentRoomState, CurrentRoomStateCache};
+    use crate::config::caseta_remote::CasetaRemote;
+    use crate::config::scene::{Device, Room, Scene, SharedTopology};
+
+    const ROOM_ID: &str = "0c329b86-a7fb-4765-8fdd-2e87f37da685";
+    const GROUPED_LIGHT_ID: &str = "ba8c44e4-0229-4888-8eeb-ce4a3d48cca8";
+    const HUE_SCENE_ID: &str = "a3011bb2-dd50-4fd9-b143-7ea03f367088";
+
+    fn topology() -> SharedTopology {
+        let room = Room {
+            name: "Living Room".to_string(),
+            room_id: Uuid::parse_str(ROOM_ID).unwrap(),
+            grouped_light_room_id: Uuid::parse_str(GROUPED_LIGHT_ID).unwrap(),
+            scenes: vec![Scene {
+                name: "white_warmth".to_string(),
+                devices: vec![Device::HueScene {
+                    id: Uuid::parse_str(HUE_SCENE_ID).unwrap(),
+                    name: "warm_reading_light_scene_0".to_string(),
+                }],
+            }],
+            remotes: vec![2],
+            bindings: vec![],
+        };
+        let remote = CasetaRemote::FiveButtonPico {
+            id: 2,
+            name: "Living Room Pico".to_string(),
+        };
+        SharedTopology::new(HashMap::from([(2, (remote, room))]))
+    }
+
+    fn listener(bridge_url: &str) -> (HueEventStreamListener, Arc<CurrentRoomStateCache>) {
+        let cache = Arc::new(new_cache());
+        let hue_client = HueClient::from_bridge_url(
+            Url::parse(bridge_url).unwrap(),
+            "key".to_string(),
+            &HueTlsVerification::bridge_id("001788fffe6a1b2c"),
+        );
+        let listener =
+            HueEventStreamListener::new(hue_client, topology(), cache.clone()).with_backoff(
+                ExponentialBackoff::new(Duration::from_millis(1), Duration::from_millis(5)),
+            );
+        (listener, cache)
+    }
+
+    fn grouped_light_event(on: bool, brightness: f32) -> String {
+        format!(
+            r#"[{{"type":"update","id":"{}","data":[{{"type":"grouped_light","id":"{}","on":{{"on":{}}},"dimming":{{"brightness":{}}}}}]}}]"#,
+            Uuid::new_v4(),
+            GROUPED_LIGHT_ID,
+            on,
+            brightness
+        )
+    }
+
+    fn on_event(on: bool) -> String {
+        format!(
+            r#"[{{"type":"update","id":"{}","data":[{{"type":"grouped_light","id":"{}","on":{{"on":{}}}}}]}}]"#,
+            Uuid::new_v4(),
+            GROUPED_LIGHT_ID,
+            on
+        )
+    }
+
+    fn scene_event(active: &str) -> String {
+        format!(
+            r#"[{{"type":"update","id":"{}","data":[{{"type":"scene","id":"{}","status":{{"active":"{}"}}}},{{"type":"motion","id":"{}"}}]}}]"#,
+            Uuid::new_v4(),
+            HUE_SCENE_ID,
+            active,
+            Uuid::new_v4()
+        )
+    }
+
+    fn cached_state(cache: &CurrentRoomStateCache) -> Option<CurrentRoomState> {
+        cache.get(&Uuid::parse_str(ROOM_ID).unwrap())
+    }
+
+    #[test]
+    fn it_decodes_server_sent_events() {
+        let mut codec = ServerSentEventCodec::new();
+        let mut buffer = BytesMut::from(": hi\n\nid: 1:0\r\ndata: [1,\r\n");
+
+        assert_that(&codec.decode(&mut buffer).unwrap()).is_none();
+
+        buffer.extend_from_slice(b"data: 2]\r\n\r\nid: 2:0\ndata: []\n\n");
+        assert_that(&codec.decode(&mut buffer).unwrap()).is_equal_to(Some(ServerSentEvent {
+            id: Some("1:0".to_string()),
+            data: "[1,\n2]".to_string(),
+        }));
+        assert_that(&codec.decode(&mut buffer).unwrap()).is_equal_to(Some(ServerSentEvent {
+            id: Some("2:0".to_string()),
+            data: "[]".to_string(),
+        }));
+        assert_that(&codec.decode(&mut buffer).unwrap()).is_none();
+    }
+
+    #[test]
+    fn it_applies_grouped_light_and_scene_updates() {
+        let (listener, cache) = listener("https://127.0.0.1/");
+        let event = |data: String| ServerSentEvent { id: None, data };
+
+        listener.handle_event(&event(grouped_light_event(true, 40.0)));
+        listener.handle_event(&event(scene_event("static")));
+        let state = cached_state(&cache).unwrap();
+        assert_that(&state.on).is_true();
+        assert_that(&state.brightness).is_equal_to(Some(40.0));
+        assert_that(&state.scene.map(|scene| scene.name))
+            .is_equal_to(Some("white_warmth".to_string()));
+
+        listener.handle_event(&event(scene_event("inactive")));
+        listener.handle_event(&event(grouped_light_event(false, 40.0)));
+        let state = cached_state(&cache).unwrap();
+        assert_that(&state.on).is_false();
+        assert_that(&state.brightness).is_none();
+        assert_that(&state.scene.is_none()).is_true();
+    }
+
+    #[test]
+    fn it_does_not_cache_a_room_that_is_on_at_an_unknown_brightness() {
+        let (listener, cache) = listener("https://127.0.0.1/");
+        let event = |data: String| ServerSentEvent { id: None, data };
+
+        listener.handle_event(&event(scene_event("static")));
+        assert_that(&cached_state(&cache).is_none()).is_true();
+
+        listener.handle_event(&event(on_event(true)));
+        assert_that(&cached_state(&cache).is_none()).is_true();
+
+        // turning on a room we knew was off doesn't tell us its brightness either
+        listener.handle_event(&event(on_event(false)));
+        assert_that(&cached_state(&cache).unwrap().on).is_false();
+        listener.handle_event(&event(on_event(true)));
+        assert_that(&cached_state(&cache).is_none()).is_true();
+    }
+
+    #[tokio::test]
+    async fn it_resubscribes_when_the_stream_drops() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bridge_url = format!("http://{}/", server.local_addr().unwrap());
+        let (listener, cache) = listener(&bridge_url);
+
+        // the first connection gets one event and is then closed, so the second event can only
+        // arrive if the listener reconnects
+        tokio::spawn(async move {
+            let mut open_sockets = Vec::new();
+            for (events, close) in [
+                (vec![grouped_light_event(true, 40.0)], true),
+                (
+                    vec![grouped_light_event(true, 60.0), scene_event("static")],
+                    false,
+                ),
+            ] {
+                let (mut socket, _) = server.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let _ = socket.read(&mut request).await.unwrap();
+                let body = events
+                    .iter()
+                    .enumerate()
+                    .map(|(index, event)| format!("id: {}:0\ndata: {}\n\n", index + 1, event))
+                    .collect::<String>();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n: hi\n\n{}",
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                if close {
+                    socket.shutdown().await.unwrap();
+                } else {
+                    open_sockets.push(socket);
+                }
+            }
+            std::future::pending::<()>().await;
+        });
+        tokio::spawn(event_stream_loop(listener));
+
+        let scene_name = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(CurrentRoomState {
+                    scene: Some(scene), ..
+                }) = cached_state(&cache)
+                {
+                    return scene.name;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the listener never picked up the second event");
+
+        assert_that(&scene_name).is_equal_to("white_warmth".to_string());
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::{oneshot, Notify};
+use tokio::time::{self, Instant};
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::client::hue::HueClient;
+use crate::client::model::hue::{DimmingDeltaAction, LightGroupDimmingDelta};
+
+const DEFAULT_COMMAND_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+enum GroupedLightCommand {
+    SetBrightness(f32),
+    TurnOff,
+    RecallScene {
+        scene_id: Uuid,
+        brightness: Option<f32>,
+    },
+    DimmingDelta {
+        dimming_delta: LightGroupDimmingDelta,
+        transition: Option<Duration>,
+    },
+}
+
+impl GroupedLightCommand {
+    // a stop ends a ramp the bridge is already running. holding it back for the rest of the
+    // interval would let the ramp keep going long after the button was released
+    fn skips_interval(&self) -> bool {
+        matches!(
+            self,
+            GroupedLightCommand::DimmingDelta {
+                dimming_delta: LightGroupDimmingDelta {
+                    action: DimmingDeltaAction::Stop,
+                    ..
+                },
+                ..
+            }
+        )
+    }
+}
+
+type Responder = oneshot::Sender<Result<(), String>>;
+
+#[derive(Debug)]
+struct PendingCommand {
+    command: GroupedLightCommand,
+    responder: Option<Responder>,
+}
+
+#[derive(Debug, Default)]
+struct RoomQueue {
+    pending: Mutex<VecDeque<PendingCommand>>,
+    notify: Notify,
+}
+
+impl RoomQueue {
+    // a brightness update replaces one that's still waiting at the back of the queue. anything
+    // else waits its turn, so on/off and brightness changes go out in the order they came in
+    fn push(&self, command: GroupedLightCommand, responder: Option<Responder>) {
+        let mut pending = self.pending.lock().unwrap();
+        match (pending.back_mut(), &command) {
+            (
+                Some(PendingCommand {
+                    command: GroupedLightCommand::SetBrightness(pending_brightness),
+                    responder: None,
+                }),
+                GroupedLightCommand::SetBrightness(brightness),
+            ) if responder.is_none() => {
+                debug!(
+                    "replacing a pending brightness update of {} with {}",
+                    pending_brightness, brightness
+                );
+                *pending_brightness = *brightness;
+            }
+            _ => pending.push_back(PendingCommand { command, responder }),
+        }
+        self.notify.notify_one();
+    }
+
+    fn next_skips_interval(&self) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .front()
+            .is_some_and(|pending_command| pending_command.command.skips_interval())
+    }
+
+    /// waits until `deadline`, or until the next command doesn't need to wait
+    async fn wait_until(&self, deadline: Instant) {
+        let sleep = time::sleep_until(deadline);
+        tokio::pin!(sleep);
+        while !self.next_skips_interval() {
+            tokio::select! {
+                _ = &mut sleep => return,
+                _ = self.notify.notified() => {}
+            }
+        }
+    }
+
+    async fn pop(&self) -> PendingCommand {
+        loop {
+            if let Some(pending_command) = self.pending.lock().unwrap().pop_front() {
+                return pending_command;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// A per-room outbound queue for grouped light commands.
+///
+/// The bridge only handles about one group command per second, so each room's commands go out
+/// no faster than `command_interval`, one at a time, in order. Brightness updates are
+/// fire-and-forget and collapse into the latest target while they wait. Everything else resolves
+/// once the bridge answers. The one exception to the interval is stopping a brightness ramp,
+/// which goes out as soon as it's next in line.
+#[derive(Debug, Clone)]
+pub struct GroupedLightQueue {
+    hue_client: HueClient,
+    command_interval: Duration,
+    rooms: Arc<Mutex<HashMap<Uuid, Arc<RoomQueue>>>>,
+}
+
+impl GroupedLightQueue {
+    pub fn new(hue_client: HueClient) -> Self {
+        Self {
+            hue_client,
+            command_interval: DEFAULT_COMMAND_INTERVAL,
+            rooms: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_command_interval(mut self, command_interval: Duration) -> Self {
+        self.command_interval = command_interval;
+        self
+    }
+
+    /// queues a brightness update and returns right away. failures are only logged
+    pub fn set_brightness(&self, grouped_light_room_id: Uuid, brightness: f32) {
+        self.room_queue(grouped_light_room_id)
+            .push(GroupedLightCommand::SetBrightness(brightness), None);
+    }
+
+    /// the brightness a room is headed toward, if there's an update still waiting to go out
+    pub fn pending_brightness(&self, grouped_light_room_id: Uuid) -> Option<f32> {
+        let room_queue = self
+            .rooms
+            .lock()
+            .unwrap()
+            .get(&grouped_light_room_id)?
+            .clone();
+        let pending = room_queue.pending.lock().unwrap();
+        match pending.back() {
+            Some(PendingCommand {
+                command: GroupedLightCommand::SetBrightness(brightness),
+                ..
+            }) => Some(*brightness),
+            _ => None,
+        }
+    }
+
+    pub async fn turn_off(&self, grouped_light_room_id: Uuid) -> Result<()> {
+        self.submit(grouped_light_room_id, GroupedLightCommand::TurnOff)
+            .await
+    }
+
+    /// scene recalls change the room's lights too, so they wait in the room's queue
+    pub async fn recall_scene(
+        &self,
+        grouped_light_room_id: Uuid,
+        scene_id: Uuid,
+        brightness: Option<f32>,
+    ) -> Result<()> {
+        self.submit(
+            grouped_light_room_id,
+            GroupedLightCommand::RecallScene {
+                scene_id,
+                brightness,
+            },
+        )
+        .await
+    }
+
+    pub async fn update_dimming_delta(
+        &self,
+        grouped_light_room_id: Uuid,
+        dimming_delta: LightGroupDimmingDelta,
+        transition: Option<Duration>,
+    ) -> Result<()> {
+        self.submit(
+            grouped_light_room_id,
+            GroupedLightCommand::DimmingDelta {
+                dimming_delta,
+                transition,
+            },
+        )
+        .await
+    }
+
+    async fn submit(
+        &self,
+        grouped_light_room_id: Uuid,
+        command: GroupedLightCommand,
+    ) -> Result<()> {
+        let (responder, response) = oneshot::channel();
+        self.room_queue(grouped_light_room_id)
+            .push(command, Some(responder));
+        match response.await {
+            Ok(result) => result.map_err(|message| anyhow!(message)),
+            Err(_) => Err(anyhow!(
+                "the command queue for grouped light {} went away",
+                grouped_light_room_id
+            )),
+        }
+    }
+
+    fn room_queue(&self, grouped_light_room_id: Uuid) -> Arc<RoomQueue> {
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms
+            .entry(grouped_light_room_id)
+            .or_insert_with(|| {
+                let room_queue = Arc::new(RoomQueue::default());
+                tokio::spawn(Self::send_loop(
+                    self.hue_client.clone(),
+                    self.command_interval,
+                    grouped_light_room_id,
+                    room_queue.clone(),
+                ));
+                room_queue
+            })
+            .clone()
+    }
+
+    async fn send_loop(
+        hue_client: HueClient,
+        command_interval: Duration,
+        grouped_light_room_id: Uuid,
+        room_queue: Arc<RoomQueue>,
+    ) {
+        let mut last_sent: Option<Instant> = None;
+        loop {
+            // wait out the interval before taking the next command, so anything that shows up in
+            // the meantime can still be collapsed
+            if let Some(last_sent) = last_sent {
+                room_queue.wait_until(last_sent + command_interval).await;
+            }
+            let PendingCommand { command, responder } = room_queue.pop().await;
+            last_sent = Some(Instant::now());
+
+            let result = match command.clone() {
+                GroupedLightCommand::SetBrightness(brightness) => {
+                    hue_client
+                        .update_brightness(grouped_light_room_id, brightness)
+                        .await
+                }
+                GroupedLightCommand::TurnOff => hue_client.turn_off(grouped_light_room_id).await,
+                GroupedLightCommand::RecallScene {
+                    scene_id,
+                    brightness,
+                } => hue_client.recall_scene(&scene_id, brightness).await,
+                GroupedLightCommand::DimmingDelta {
+                    dimming_delta,
+                    transition,
+                } => {
+                    hue_client
+                        .update_dimming_delta(grouped_light_room_id, dimming_delta, transition)
+                        .await
+                }
+            };
+
+            match responder {
+                Some(responder) => {
+                    let _ = responder.send(result.map_err(|e| format!("{:#}", e)));
+                }
+                None => {
+                    if let Err(e) = result {
+                        error!(
+                            grouped_light_room_id=%grouped_light_room_id,
+                            command=?command,
+                            "a queued grouped light command failed: {:#}", e
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::Url;
+    use spectral::prelude::*;
+    use tokio::time::Instant;
+    use uuid::Uuid;
+
+    use crate::client::grouped_light_queue::GroupedLightQueue;
+    use crate::client::hue::HueClient;
+    use crate::client::hue_tls::HueTlsVerification;
+    use crate::client::model::hue::{DimmingDeltaAction, LightGroupDimmingDelta};
+    use crate::client::test_server::TestServer;
+
+    async fn queue(command_interval: Duration) -> (TestServer, GroupedLightQueue) {
+        let server = TestServer::start(200, r#"{"errors":[],"data":[]}"#).await;
+        let hue_client = HueClient::from_bridge_url(
+            Url::parse(&server.base_url).unwrap(),
+            String::from("key"),
+            &HueTlsVerification::bridge_id("001788fffe6a1b2c"),
+        );
+        let queue = GroupedLightQueue::new(hue_client).with_command_interval(command_interval);
+        (server, queue)
+    }
+
+    #[tokio::test]
+    async fn it_collapses_brightness_updates_without_reordering() {
+        let (server, queue) = queue(Duration::from_millis(50)).await;
+        let grouped_light_room_id = Uuid::new_v4();
+
+        queue.set_brightness(grouped_light_room_id, 10.0);
+        queue.set_brightness(grouped_light_room_id, 20.0);
+        queue.set_brightness(grouped_light_room_id, 30.0);
+        queue.turn_off(grouped_light_room_id).await.unwrap();
+
+        let bodies: Vec<String> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.body)
+            .collect();
+        assert_that(&bodies.len()).is_less_than(4);
+        assert_that(&bodies[bodies.len() - 2].contains(r#""brightness":30.0"#)).is_true();
+        assert_that(&bodies[bodies.len() - 1]).is_equal_to(r#"{"on":{"on":false}}"#.to_string());
+    }
+
+    #[tokio::test]
+    async fn it_spaces_out_commands_for_a_room() {
+        let (server, queue) = queue(Duration::from_millis(50)).await;
+        let grouped_light_room_id = Uuid::new_v4();
+
+        let start = Instant::now();
+        queue.turn_off(grouped_light_room_id).await.unwrap();
+        queue.turn_off(grouped_light_room_id).await.unwrap();
+        let same_room_elapsed = start.elapsed();
+
+        assert_that(&server.requests()).has_length(2);
+        assert_that(&same_room_elapsed).is_greater_than_or_equal_to(Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn it_stops_a_ramp_without_waiting_out_the_interval() {
+        let (server, queue) = queue(Duration::from_secs(1)).await;
+        let grouped_light_room_id = Uuid::new_v4();
+
+        let start = Instant::now();
+        queue
+            .update_dimming_delta(
+                grouped_light_room_id,
+                LightGroupDimmingDelta::new(DimmingDeltaAction::Up, 50.0),
+                Some(Duration::from_millis(2500)),
+            )
+            .await
+            .unwrap();
+        queue
+            .update_dimming_delta(grouped_light_room_id, LightGroupDimmingDelta::STOP, None)
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        let bodies: Vec<String> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.body)
+            .collect();
+        assert_that(&bodies).has_length(2);
+        assert_that(&bodies[1].contains(r#""action":"stop""#)).is_true();
+        assert_that(&elapsed).is_less_than(Duration::from_millis(500));
+    }
+}
@@ -1,46 +1,155 @@
 use anyhow::{anyhow, bail, Ok, Result};
 use log::error;
-use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::{Client, Url};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+use reqwest::{Client, RequestBuilder, Response, Url};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use tracing::{debug, instrument};
 use url::Host;
 
-use crate::client::model::hue::{HueResponse, HueRoom};
+use crate::client::hue_tls::HueTlsVerification;
+use crate::client::model::hue::{HueResponse, HueRoom, HueScene};
+use crate::client::request_policy::{RequestError, RequestPolicy};
+use crate::metrics::metrics;
 use uuid::Uuid;
 
 use super::model::hue::{
-    GroupedLight, GroupedLightPutBody, LightGroupDimming, LightGroupOn, RecallSceneBody,
+    GroupedLight, GroupedLightPutBody, LightDynamics, LightGroupDimming, LightGroupDimmingDelta,
+    LightGroupOn, RecallSceneBody,
 };
 
 const HUE_AUTH_KEY_HEADER: &str = "hue-application-key";
-#[derive(Debug)]
+const EVENT_STREAM_CONTENT_TYPE: &str = "text/event-stream";
+
+#[derive(Debug, Clone)]
 pub struct HueClient {
     base_url: Url,
+    event_stream_url: Url,
     http_client: Client,
+    request_policy: RequestPolicy,
 }
 
 impl HueClient {
-    pub fn new(host: Host, auth_key: String) -> HueClient {
+    pub fn new(host: Host, auth_key: String, tls_verification: &HueTlsVerification) -> HueClient {
+        let bridge_url = Url::parse(format!("https://{}/", host).as_str())
+            .expect("unable to parse the hue bridge URL");
+        Self::from_bridge_url(bridge_url, auth_key, tls_verification)
+    }
+
+    pub(crate) fn from_bridge_url(
+        bridge_url: Url,
+        auth_key: String,
+        tls_verification: &HueTlsVerification,
+    ) -> HueClient {
         let mut headers = HeaderMap::new();
         let mut header_val = HeaderValue::from_str(auth_key.as_str())
             .expect("there was a problem setting the hue-application-key header");
         header_val.set_sensitive(true);
         headers.insert(HUE_AUTH_KEY_HEADER, header_val);
-        let http_client = Client::builder()
-            .default_headers(headers)
-            .danger_accept_invalid_certs(true)
+        let http_client = tls_verification
+            .configure(Client::builder().default_headers(headers))
             .build()
             .expect("there was a problem building the http client");
 
-        let base_url = Url::parse(format!("https://{}/clip/v2/resource/", host).as_str())
+        let base_url = bridge_url
+            .join("clip/v2/resource/")
             .expect("unable to parse the hue base URL");
+        let event_stream_url = bridge_url
+            .join("eventstream/clip/v2")
+            .expect("unable to parse the hue event stream URL");
         HueClient {
             base_url,
+            event_stream_url,
             http_client,
+            request_policy: RequestPolicy::default(),
         }
     }
 
+    pub fn with_request_policy(mut self, request_policy: RequestPolicy) -> Self {
+        self.request_policy = request_policy;
+        self
+    }
+
+    // sends a request through the request policy, timing it for the hue request metrics
+    async fn send<F>(&self, endpoint: &str, build_request: F) -> Result<Response, RequestError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let started_at = Instant::now();
+        let response = self.request_policy.send(build_request).await;
+        metrics().record_hue_request(
+            endpoint,
+            &status_label(response.as_ref().ok()),
+            started_at.elapsed(),
+        );
+        response
+    }
+
+    // relative changes like dimming deltas would apply twice if a timed out attempt went through
+    async fn send_non_idempotent<F>(
+        &self,
+        endpoint: &str,
+        build_request: F,
+    ) -> Result<Response, RequestError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let started_at = Instant::now();
+        let response = self.request_policy.send_non_idempotent(build_request).await;
+        metrics().record_hue_request(
+            endpoint,
+            &status_label(response.as_ref().ok()),
+            started_at.elapsed(),
+        );
+        response
+    }
+
+    /// opens the bridge's server-sent event stream. the response body stays open and delivers
+    /// events as they happen until the bridge or the network drops it. it skips the request
+    /// policy, since the policy's timeout would cut the stream off. `event_stream_loop` does its
+    /// own reconnecting.
+    #[instrument(level = "debug")]
+    pub async fn open_event_stream(&self) -> Result<Response> {
+        let response = self
+            .http_client
+            .get(self.event_stream_url.clone())
+            .header(ACCEPT, EVENT_STREAM_CONTENT_TYPE)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let response_body = response.text().await?;
+            bail!(
+                "there was a problem opening the hue event stream. status: {}, body: {}",
+                status,
+                response_body
+            )
+        }
+        Ok(response)
+    }
+
+    /// asks the bridge for its own resource, skipping the request policy so an unreachable bridge
+    /// is reported within `timeout` instead of after every retry
+    #[instrument(level = "debug")]
+    pub async fn check_reachable(&self, timeout: Duration) -> Result<()> {
+        let url = self
+            .base_url
+            .join("bridge")
+            .expect("this should always be a well formed URL");
+        let started_at = Instant::now();
+        let response = self.http_client.get(url).timeout(timeout).send().await;
+        metrics().record_hue_request(
+            "check_reachable",
+            &status_label(response.as_ref().ok()),
+            started_at.elapsed(),
+        );
+        let status = response?.status();
+        if !status.is_success() {
+            bail!("the bridge answered with {}", status)
+        }
+        Ok(())
+    }
+
     #[instrument(level = "debug")]
     pub async fn get_grouped_light(
         &self,
@@ -51,8 +160,21 @@ impl HueClient {
             .join(format!("grouped_light/{}", grouped_light_room_id).as_str())
             .expect("unable to parse grouped_light url");
         debug!(request_url=?url, "calling out to {}", url.as_str());
-        let response = self.http_client.get(url).send().await?;
+        let response = self
+            .send("get_grouped_light", || self.http_client.get(url.clone()))
+            .await?;
         debug!("got get_grouped_light response: {:?}", response);
+        // a grouped light the bridge doesn't know about comes back as a 404 with empty `data`
+        let status = response.status();
+        if !status.is_success() {
+            let response_body = response.text().await?;
+            bail!(
+                "there was a problem getting the grouped light {}. status: {}, body: {}",
+                grouped_light_room_id,
+                status,
+                response_body
+            )
+        }
         response
             .json::<HueResponse<GroupedLight>>()
             .await
@@ -65,7 +187,9 @@ impl HueClient {
             .base_url
             .join("room")
             .expect("this should always be a well formed URL");
-        let response = self.http_client.get(url).send().await.unwrap();
+        let response = self
+            .send("get_rooms", || self.http_client.get(url.clone()))
+            .await?;
         debug!("got get_rooms response: {:?}", response);
 
         let rooms = response.json::<HueResponse<HueRoom>>().await?;
@@ -76,6 +200,21 @@ impl HueClient {
         Ok(rooms_by_id)
     }
 
+    #[instrument(level = "debug")]
+    pub async fn get_scenes(&self) -> Result<Vec<HueScene>> {
+        let url = self
+            .base_url
+            .join("scene")
+            .expect("this should always be a well formed URL");
+        let response = self
+            .send("get_scenes", || self.http_client.get(url.clone()))
+            .await?;
+        debug!("got get_scenes response: {:?}", response);
+
+        let scenes = response.json::<HueResponse<HueScene>>().await?;
+        Ok(scenes.data)
+    }
+
     fn build_grouped_light_url(&self, grouped_light_room_id: Uuid) -> Url {
         self.base_url
             .join(format!("grouped_light/{}", grouped_light_room_id).as_str())
@@ -93,7 +232,11 @@ impl HueClient {
             .dimming(LightGroupDimming::new(brightness))
             .on(LightGroupOn::ON)
             .build();
-        let response = self.http_client.put(url).json(&request_body).send().await?;
+        let response = self
+            .send("update_brightness", || {
+                self.http_client.put(url.clone()).json(&request_body)
+            })
+            .await?;
         debug!("got update_brightness response: {:?}", response);
         let status = response.status();
         if !status.is_success() {
@@ -113,12 +256,62 @@ impl HueClient {
         Ok(())
     }
 
+    /// start or stop a bridge-side brightness ramp. `transition` is how long the bridge should
+    /// take to cover the whole delta
+    #[instrument(level = "debug")]
+    pub async fn update_dimming_delta(
+        &self,
+        grouped_light_room_id: Uuid,
+        dimming_delta: LightGroupDimmingDelta,
+        transition: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        let url = self.build_grouped_light_url(grouped_light_room_id);
+        let request_body = match transition {
+            Some(transition) => GroupedLightPutBody::builder()
+                .dimming_delta(dimming_delta)
+                .dynamics(LightDynamics {
+                    duration: transition.as_millis() as u64,
+                })
+                .build(),
+            None => GroupedLightPutBody::builder()
+                .dimming_delta(dimming_delta)
+                .build(),
+        };
+
+        let response = self
+            .send_non_idempotent("update_dimming_delta", || {
+                self.http_client.put(url.clone()).json(&request_body)
+            })
+            .await?;
+        debug!("got update_dimming_delta response: {:?}", response);
+        let status = response.status();
+        if !status.is_success() {
+            let response_body = response.text().await?;
+            error!(
+                "there was a problem changing the brightness of the grouped light {}. status: {}, body: {}",
+                grouped_light_room_id, status, response_body
+            );
+            bail!(
+                "there was a problem changing the brightness of the grouped light {}. status: {}, body: {}",
+                grouped_light_room_id,
+                status,
+                response_body
+            )
+        }
+
+        Ok(())
+    }
+
     #[instrument(level = "debug")]
     pub async fn turn_off(&self, grouped_light_room_id: Uuid) -> anyhow::Result<()> {
         let url = self.build_grouped_light_url(grouped_light_room_id);
         let request_body = GroupedLightPutBody::builder().on(LightGroupOn::OFF).build();
 
-        let response = self.http_client.put(url).json(&request_body).send().await?;
+        let response = self
+            .send("turn_off", || {
+                self.http_client.put(url.clone()).json(&request_body)
+            })
+            .await?;
         debug!("got turn_off response: {:?}", response);
         let status = response.status();
         if !status.is_success() {
@@ -147,7 +340,11 @@ impl HueClient {
 
         let body = RecallSceneBody::new(brightness);
 
-        let response = self.http_client.put(url).json(&body).send().await?;
+        let response = self
+            .send("recall_scene", || {
+                self.http_client.put(url.clone()).json(&body)
+            })
+            .await?;
         debug!("got recall_scene response: {:?}", response);
         let status = response.status();
         if !status.is_success() {
@@ -166,3 +363,11 @@ impl HueClient {
         Ok(())
     }
 }
+
+// the status label for the hue request metrics
+fn status_label(response: Option<&Response>) -> String {
+    match response {
+        Some(response) => response.status().as_u16().to_string(),
+        None => "error".to_string(),
+    }
+}
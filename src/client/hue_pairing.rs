@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::{Client, Url};
+use tokio::time::{self, Instant};
+use tracing::{debug, info};
+use url::Host;
+
+use crate::client::hue_tls::HueTlsVerification;
+use crate::client::model::hue::{HueApiError, HueCredentials, PairingRequestBody, PairingResult};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Asks a hue bridge for a new application key. The bridge only hands one out within 30 seconds
+/// of someone pressing its link button, so [`HuePairingClient::pair`] keeps asking until then.
+#[derive(Debug, Clone)]
+pub struct HuePairingClient {
+    api_url: Url,
+    http_client: Client,
+    poll_interval: Duration,
+}
+
+impl HuePairingClient {
+    pub fn new(host: &Host, tls_verification: &HueTlsVerification) -> HuePairingClient {
+        let bridge_url = Url::parse(format!("https://{}/", host).as_str())
+            .expect("unable to parse the hue bridge URL");
+        Self::from_bridge_url(bridge_url, tls_verification)
+    }
+
+    pub(crate) fn from_bridge_url(
+        bridge_url: Url,
+        tls_verification: &HueTlsVerification,
+    ) -> HuePairingClient {
+        let api_url = bridge_url
+            .join("api")
+            .expect("unable to parse the hue pairing URL");
+        let http_client = tls_verification
+            .configure(Client::builder())
+            .build()
+            .expect("there was a problem building the http client");
+        HuePairingClient {
+            api_url,
+            http_client,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// waits up to `timeout` for the link button to be pressed, then returns the new key
+    pub async fn pair(&self, device_type: &str, timeout: Duration) -> Result<HueCredentials> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(credentials) = self.request_key(device_type).await? {
+                return Ok(credentials);
+            }
+            if Instant::now() + self.poll_interval > deadline {
+                bail!(
+                    "the hue bridge's link button wasn't pressed within {:?}",
+                    timeout
+                );
+            }
+            time::sleep(self.poll_interval).await;
+        }
+    }
+
+    // `None` means the link button hasn't been pressed yet
+    async fn request_key(&self, device_type: &str) -> Result<Option<HueCredentials>> {
+        let response = self
+            .http_client
+            .post(self.api_url.clone())
+            .json(&PairingRequestBody::new(device_type))
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!(
+                "there was a problem pairing with the hue bridge. status: {}, body: {}",
+                status,
+                response.text().await?
+            )
+        }
+
+        let results: Vec<PairingResult> = response.json().await?;
+        debug!("got pairing response: {:?}", results);
+        match results.into_iter().next() {
+            Some(PairingResult::Success(credentials)) => Ok(Some(credentials)),
+            Some(PairingResult::Error(HueApiError { error_type, .. }))
+                if error_type == HueApiError::LINK_BUTTON_NOT_PRESSED =>
+            {
+                info!("waiting for the hue bridge's link button to be pressed");
+                Ok(None)
+            }
+            Some(PairingResult::Error(error)) => Err(anyhow!(
+                "the hue bridge refused to pair: {} (error {})",
+                error.description,
+                error.error_type
+            )),
+            None => Err(anyhow!("the hue bridge sent an empty pairing response")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::Url;
+    use spectral::prelude::*;
+
+    use crate::client::hue_pairing::HuePairingClient;
+    use crate::client::hue_tls::HueTlsVerification;
+    use crate::client::model::hue::HueCredentials;
+    use crate::client::test_server::TestServer;
+
+    const LINK_BUTTON_NOT_PRESSED: &str =
+        r#"[{"error":{"type":101,"address":"","description":"link button not pressed"}}]"#;
+    const PAIRED: &str = r#"[{"success":{"username":"application-key","clientkey":"ABCDEF"}}]"#;
+
+    fn client(server: &TestServer) -> HuePairingClient {
+        HuePairingClient::from_bridge_url(
+            Url::parse(&server.base_url).unwrap(),
+            &HueTlsVerification::bridge_id("001788fffe6a1b2c"),
+        )
+        .with_poll_interval(Duration::from_millis(5))
+    }
+
+    #[tokio::test]
+    async fn it_polls_until_the_link_button_is_pressed() {
+        let server = TestServer::start_with_responses(vec![
+            (200, LINK_BUTTON_NOT_PRESSED),
+            (200, LINK_BUTTON_NOT_PRESSED),
+            (200, PAIRED),
+        ])
+        .await;
+
+        let credentials = client(&server)
+            .pair("caseta_listener#test", Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_that(&credentials).is_equal_to(HueCredentials {
+            application_key: "application-key".to_string(),
+            client_key: Some("ABCDEF".to_string()),
+        });
+        assert_that(&requests).has_length(3);
+        assert_that(&requests[0].method).is_equal_to("POST".to_string());
+        assert_that(&requests[0].path).is_equal_to("/api".to_string());
+        assert_that(&requests[0].body).is_equal_to(
+            r#"{"devicetype":"caseta_listener#test","generateclientkey":true}"#.to_string(),
+        );
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_when_the_link_button_is_never_pressed() {
+        let server = TestServer::start(200, LINK_BUTTON_NOT_PRESSED).await;
+
+        let result = client(&server)
+            .pair("caseta_listener#test", Duration::from_millis(20))
+            .await;
+
+        assert_that(&result.is_err()).is_true();
+        assert_that(&server.requests().len()).is_greater_than(1);
+    }
+}
@@ -0,0 +1,548 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use reqwest::ClientBuilder;
+use ring::digest::{digest, SHA256};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, ServerName};
+use tracing::warn;
+use webpki::{EndEntityCert, SignatureAlgorithm, TlsServerTrustAnchors, TrustAnchor};
+
+use crate::config::auth_configuration::HueTlsConfiguration;
+
+/// the root CA that signs hue bridge certificates. every bridge's certificate is issued to its
+/// bridge id, so trusting this root alone would also trust every other hue bridge.
+const HUE_ROOT_CA_PEM: &[u8] = include_bytes!("hue_root_ca.pem");
+const FINGERPRINT_LENGTH: usize = 32;
+// the hue root and bridges use P-256 keys
+static SUPPORTED_SIGNATURE_ALGORITHMS: &[&SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum HueTlsConfigurationError {
+    #[error("hue_tls.bridge_id is required to verify the hue bridge's certificate. set hue_tls.accept_invalid_certificates to skip verification")]
+    MissingBridgeId,
+    #[error("{0} isn't a sha-256 certificate fingerprint")]
+    InvalidFingerprint(String),
+}
+
+/// the sha-256 fingerprint of a DER certificate, written as hex with optional `:` separators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertificateFingerprint([u8; FINGERPRINT_LENGTH]);
+
+impl CertificateFingerprint {
+    pub fn of(certificate: &Certificate) -> Self {
+        let mut fingerprint = [0; FINGERPRINT_LENGTH];
+        fingerprint.copy_from_slice(digest(&SHA256, &certificate.0).as_ref());
+        Self(fingerprint)
+    }
+}
+
+impl FromStr for CertificateFingerprint {
+    type Err = HueTlsConfigurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || HueTlsConfigurationError::InvalidFingerprint(s.to_string());
+        let hex: String = s.chars().filter(|c| *c != ':').collect();
+        if hex.len() != FINGERPRINT_LENGTH * 2 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut fingerprint = [0; FINGERPRINT_LENGTH];
+        for (index, byte) in fingerprint.iter_mut().enumerate() {
+            *byte =
+                u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(fingerprint))
+    }
+}
+
+impl Display for CertificateFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let hex: Vec<String> = self.0.iter().map(|byte| format!("{:02X}", byte)).collect();
+        write!(f, "{}", hex.join(":"))
+    }
+}
+
+/// how to decide whether we're talking to our hue bridge
+#[derive(Debug, Clone)]
+pub enum HueTlsVerification {
+    /// the certificate has to chain to the hue root CA and be issued to `bridge_id`. when
+    /// there's a `pinned_fingerprint`, it also has to be that exact certificate.
+    BridgeId {
+        bridge_id: String,
+        pinned_fingerprint: Option<CertificateFingerprint>,
+    },
+    /// accept any certificate. anyone on the network can pose as the bridge and collect the
+    /// application key, so this only happens when the configuration asks for it.
+    AcceptInvalidCertificates,
+}
+
+impl HueTlsVerification {
+    pub fn bridge_id(bridge_id: &str) -> Self {
+        HueTlsVerification::BridgeId {
+            bridge_id: bridge_id.to_string(),
+            pinned_fingerprint: None,
+        }
+    }
+
+    pub fn configure(&self, client_builder: ClientBuilder) -> ClientBuilder {
+        match self {
+            HueTlsVerification::BridgeId {
+                bridge_id,
+                pinned_fingerprint,
+            } => {
+                let verifier = BridgeCertificateVerifier::new(
+                    hue_root_certificate(),
+                    bridge_id,
+                    *pinned_fingerprint,
+                )
+                .expect("the bundled hue root CA should be a valid trust anchor");
+                let tls_config = ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_custom_certificate_verifier(Arc::new(verifier))
+                    .with_no_client_auth();
+                client_builder.use_preconfigured_tls(tls_config)
+            }
+            HueTlsVerification::AcceptInvalidCertificates => {
+                warn!("hue bridge certificate verification is turned off. anyone on the network can pose as the bridge");
+                client_builder.danger_accept_invalid_certs(true)
+            }
+        }
+    }
+}
+
+impl TryFrom<&HueTlsConfiguration> for HueTlsVerification {
+    type Error = HueTlsConfigurationError;
+
+    fn try_from(configuration: &HueTlsConfiguration) -> Result<Self, Self::Error> {
+        if configuration.accept_invalid_certificates {
+            return Ok(HueTlsVerification::AcceptInvalidCertificates);
+        }
+        let bridge_id = configuration
+            .bridge_id
+            .as_ref()
+            .ok_or(HueTlsConfigurationError::MissingBridgeId)?;
+        let pinned_fingerprint = configuration
+            .certificate_fingerprint
+            .as_deref()
+            .map(CertificateFingerprint::from_str)
+            .transpose()?;
+        Ok(HueTlsVerification::BridgeId {
+            bridge_id: bridge_id.clone(),
+            pinned_fingerprint,
+        })
+    }
+}
+
+pub fn hue_root_certificate() -> Certificate {
+    let mut pem = HUE_ROOT_CA_PEM;
+    let certificate = rustls_pemfile::certs(&mut pem)
+        .ok()
+        .and_then(|certificates| certificates.into_iter().next())
+        .expect("the bundled hue root CA should be a valid certificate");
+    Certificate(certificate)
+}
+
+/// Checks the bridge's certificate against a root CA and the bridge id instead of the host we
+/// connected to, since bridges are usually reached by IP address.
+pub struct BridgeCertificateVerifier {
+    root_certificate: Certificate,
+    bridge_id: String,
+    pinned_fingerprint: Option<CertificateFingerprint>,
+}
+
+impl BridgeCertificateVerifier {
+    pub fn new(
+        root_certificate: Certificate,
+        bridge_id: &str,
+        pinned_fingerprint: Option<CertificateFingerprint>,
+    ) -> Result<Self, webpki::Error> {
+        TrustAnchor::try_from_cert_der(&root_certificate.0)?;
+        Ok(Self {
+            root_certificate,
+            bridge_id: bridge_id.to_string(),
+            pinned_fingerprint,
+        })
+    }
+
+    fn verify(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<(), rustls::Error> {
+        if let Some(pinned_fingerprint) = self.pinned_fingerprint {
+            let fingerprint = CertificateFingerprint::of(end_entity);
+            if fingerprint != pinned_fingerprint {
+                return Err(rustls::Error::InvalidCertificateData(format!(
+                    "the bridge's certificate fingerprint is {}, not the pinned {}",
+                    fingerprint, pinned_fingerprint
+                )));
+            }
+        }
+
+        let certificate = EndEntityCert::try_from(end_entity.0.as_slice())
+            .map_err(|_| rustls::Error::InvalidCertificateEncoding)?;
+        let trust_anchor = TrustAnchor::try_from_cert_der(&self.root_certificate.0)
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+        let intermediates: Vec<&[u8]> = intermediates
+            .iter()
+            .map(|intermediate| intermediate.0.as_slice())
+            .collect();
+        let now = webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+        certificate
+            .verify_is_valid_tls_server_cert(
+                SUPPORTED_SIGNATURE_ALGORITHMS,
+                &TlsServerTrustAnchors(&[trust_anchor]),
+                &intermediates,
+                now,
+            )
+            .map_err(|e| {
+                rustls::Error::InvalidCertificateData(format!(
+                    "the bridge's certificate doesn't chain to the hue root CA: {}",
+                    e
+                ))
+            })?;
+
+        if !self.is_issued_to_bridge(end_entity) {
+            return Err(rustls::Error::InvalidCertificateData(format!(
+                "the bridge's certificate wasn't issued to bridge {}",
+                self.bridge_id
+            )));
+        }
+        Ok(())
+    }
+
+    // webpki only matches subjectAltName dns names, but bridge certificates carry their id in
+    // the subject's common name
+    fn is_issued_to_bridge(&self, certificate: &Certificate) -> bool {
+        certificate_names(&certificate.0).is_some_and(|names| {
+            names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&self.bridge_id))
+        })
+    }
+}
+
+impl ServerCertVerifier for BridgeCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verify(end_entity, intermediates, now)?;
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+const SUBJECT_ALT_NAME_OID: &[u8] = &[0x55, 0x1d, 0x11];
+const VERSION_TAG: u8 = 0xa0;
+const EXTENSIONS_TAG: u8 = 0xa3;
+const DNS_NAME_TAG: u8 = 0x82;
+
+/// the subject's common names and the subjectAltName dns names of a DER certificate, or `None`
+/// if it isn't well formed
+fn certificate_names(certificate: &[u8]) -> Option<Vec<String>> {
+    let (_tag, certificate, _rest) = der::read(certificate)?;
+    let (_tag, tbs_certificate, _rest) = der::read(certificate)?;
+    let mut fields = der::elements(tbs_certificate).peekable();
+    if fields.peek()?.0 == VERSION_TAG {
+        fields.next();
+    }
+    // the serial number, signature algorithm, issuer and validity come before the subject
+    let (_tag, subject) = fields.nth(4)?;
+
+    let mut names = Vec::new();
+    for (_tag, relative_name) in der::elements(subject) {
+        for (_tag, attribute) in der::elements(relative_name) {
+            let mut attribute = der::elements(attribute);
+            let (_tag, oid) = attribute.next()?;
+            let (_tag, value) = attribute.next()?;
+            if oid == COMMON_NAME_OID {
+                names.push(String::from_utf8_lossy(value).to_string());
+            }
+        }
+    }
+
+    if let Some((_tag, extensions)) = fields.find(|(tag, _value)| *tag == EXTENSIONS_TAG) {
+        let (_tag, extensions, _rest) = der::read(extensions)?;
+        for (_tag, extension) in der::elements(extensions) {
+            // the value is last, after an optional `critical` flag
+            let (_tag, oid) = der::elements(extension).next()?;
+            let (_tag, value) = der::elements(extension).last()?;
+            if oid == SUBJECT_ALT_NAME_OID {
+                let (_tag, alt_names, _rest) = der::read(value)?;
+                names.extend(
+                    der::elements(alt_names)
+                        .filter(|(tag, _value)| *tag == DNS_NAME_TAG)
+                        .map(|(_tag, dns_name)| String::from_utf8_lossy(dns_name).to_string()),
+                );
+            }
+        }
+    }
+    Some(names)
+}
+
+/// just enough DER to find a certificate's names
+mod der {
+    /// splits the first tag, length and value off of `input`
+    pub fn read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, rest) = input.split_first()?;
+        let (&length, rest) = rest.split_first()?;
+        let (length, rest) = match length {
+            0..=0x7f => (length as usize, rest),
+            // long lengths are a count of big-endian length bytes that follow
+            0x81..=0x84 => {
+                let (length_bytes, rest) = split_at(rest, (length & 0x7f) as usize)?;
+                let length = length_bytes
+                    .iter()
+                    .fold(0, |length, byte| (length << 8) | *byte as usize);
+                (length, rest)
+            }
+            _ => return None,
+        };
+        let (value, rest) = split_at(rest, length)?;
+        Some((tag, value, rest))
+    }
+
+    fn split_at(input: &[u8], length: usize) -> Option<(&[u8], &[u8])> {
+        (length <= input.len()).then(|| input.split_at(length))
+    }
+
+    /// the tag and value of each element in a constructed value, stopping at anything malformed
+    pub fn elements(mut input: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+        std::iter::from_fn(move || {
+            let (tag, value, rest) = read(input)?;
+            input = rest;
+            Some((tag, value))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::SystemTime;
+
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::{BasicConstraints, KeyUsage, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509NameBuilder, X509};
+    use rustls::Certificate;
+    use spectral::prelude::*;
+
+    use crate::client::hue_tls::{
+        certificate_names, hue_root_certificate, BridgeCertificateVerifier, CertificateFingerprint,
+        HueTlsVerification,
+    };
+    use crate::config::auth_configuration::HueTlsConfiguration;
+
+    const BRIDGE_ID: &str = "001788fffe6a1b2c";
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    // a certificate for `common_name`, signed by `issuer` or self-signed as a CA
+    fn certificate(
+        common_name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+            .unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial_number = BigNum::from_u32(fastrand::u32(1..)).unwrap();
+        builder
+            .set_serial_number(&serial_number.to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let signing_key = match issuer {
+            Some((issuer_certificate, issuer_key)) => {
+                builder
+                    .set_issuer_name(issuer_certificate.subject_name())
+                    .unwrap();
+                // webpki won't parse a certificate without extensions
+                builder
+                    .append_extension(BasicConstraints::new().build().unwrap())
+                    .unwrap();
+                issuer_key
+            }
+            None => {
+                builder.set_issuer_name(&name).unwrap();
+                builder
+                    .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                builder
+                    .append_extension(KeyUsage::new().key_cert_sign().build().unwrap())
+                    .unwrap();
+                key
+            }
+        };
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn der(certificate: &X509) -> Certificate {
+        Certificate(certificate.to_der().unwrap())
+    }
+
+    #[test]
+    fn it_bundles_the_hue_root_ca() {
+        let root = hue_root_certificate();
+
+        assert_that(&certificate_names(&root.0))
+            .is_equal_to(Some(vec![String::from("root-bridge")]));
+    }
+
+    #[test]
+    fn it_reads_subject_alt_names() {
+        let root_key = key();
+        let root = certificate("root-bridge", &root_key, None);
+        let mut bridge_certificate = X509Builder::new().unwrap();
+        bridge_certificate.set_version(2).unwrap();
+        bridge_certificate
+            .set_issuer_name(root.subject_name())
+            .unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "Philips Hue")
+            .unwrap();
+        bridge_certificate.set_subject_name(&name.build()).unwrap();
+        bridge_certificate.set_pubkey(&root_key).unwrap();
+        let alt_names = SubjectAlternativeName::new()
+            .dns(BRIDGE_ID)
+            .ip("192.168.1.2")
+            .build(&bridge_certificate.x509v3_context(Some(&root), None))
+            .unwrap();
+        bridge_certificate.append_extension(alt_names).unwrap();
+        bridge_certificate
+            .sign(&root_key, MessageDigest::sha256())
+            .unwrap();
+
+        assert_that(&certificate_names(&der(&bridge_certificate.build()).0)).is_equal_to(Some(
+            vec![String::from("Philips Hue"), String::from(BRIDGE_ID)],
+        ));
+        assert_that(&certificate_names(&[0x30, 0x05, 0x30])).is_none();
+    }
+
+    #[test]
+    fn it_verifies_the_chain_and_the_bridge_id() {
+        let root_key = key();
+        let root = certificate("root-bridge", &root_key, None);
+        let bridge_key = key();
+        let bridge_certificate = certificate(BRIDGE_ID, &bridge_key, Some((&root, &root_key)));
+        let other_bridge_certificate =
+            certificate("001788fffe000000", &bridge_key, Some((&root, &root_key)));
+        let rogue_root_key = key();
+        let rogue_root = certificate("root-bridge", &rogue_root_key, None);
+        let rogue_certificate =
+            certificate(BRIDGE_ID, &bridge_key, Some((&rogue_root, &rogue_root_key)));
+
+        let verifier =
+            BridgeCertificateVerifier::new(der(&root), &BRIDGE_ID.to_uppercase(), None).unwrap();
+
+        assert_that(
+            &verifier
+                .verify(&der(&bridge_certificate), &[], SystemTime::now())
+                .is_ok(),
+        )
+        .is_true();
+        assert_that(
+            &verifier
+                .verify(&der(&other_bridge_certificate), &[], SystemTime::now())
+                .is_err(),
+        )
+        .is_true();
+        assert_that(
+            &verifier
+                .verify(&der(&rogue_certificate), &[], SystemTime::now())
+                .is_err(),
+        )
+        .is_true();
+    }
+
+    #[test]
+    fn it_checks_a_pinned_fingerprint() {
+        let root_key = key();
+        let root = certificate("root-bridge", &root_key, None);
+        let bridge_key = key();
+        let bridge_certificate = certificate(BRIDGE_ID, &bridge_key, Some((&root, &root_key)));
+        let reissued_certificate = certificate(BRIDGE_ID, &bridge_key, Some((&root, &root_key)));
+        let fingerprint = CertificateFingerprint::of(&der(&bridge_certificate));
+
+        let verifier =
+            BridgeCertificateVerifier::new(der(&root), BRIDGE_ID, Some(fingerprint)).unwrap();
+
+        assert_that(
+            &verifier
+                .verify(&der(&bridge_certificate), &[], SystemTime::now())
+                .is_ok(),
+        )
+        .is_true();
+        assert_that(
+            &verifier
+                .verify(&der(&reissued_certificate), &[], SystemTime::now())
+                .is_err(),
+        )
+        .is_true();
+    }
+
+    #[test]
+    fn it_builds_verification_from_configuration() {
+        let fingerprint =
+            "F0:BD:8E:65:09:E8:2F:77:4D:63:BC:00:9D:53:88:C9:69:FE:3D:CF:7D:6D:54:1D:63:51:B7:2B:89:8D:8A:CF";
+        let configuration = |bridge_id: Option<&str>, fingerprint: Option<&str>, insecure| {
+            HueTlsVerification::try_from(&HueTlsConfiguration {
+                bridge_id: bridge_id.map(String::from),
+                certificate_fingerprint: fingerprint.map(String::from),
+                accept_invalid_certificates: insecure,
+            })
+        };
+
+        let pinned = configuration(Some(BRIDGE_ID), Some(fingerprint), false).unwrap();
+
+        assert!(matches!(
+            pinned,
+            HueTlsVerification::BridgeId {
+                pinned_fingerprint: Some(pinned_fingerprint),
+                ..
+            } if pinned_fingerprint == CertificateFingerprint::from_str(&fingerprint.replace(':', "").to_lowercase()).unwrap()
+        ));
+        assert!(matches!(
+            configuration(None, None, true),
+            Ok(HueTlsVerification::AcceptInvalidCertificates)
+        ));
+        assert_that(&configuration(None, None, false).is_err()).is_true();
+        assert_that(&configuration(Some(BRIDGE_ID), Some("F0:BD"), false).is_err()).is_true();
+        assert_that(&CertificateFingerprint::of(&hue_root_certificate()).to_string())
+            .is_equal_to(fingerprint.to_string());
+    }
+}
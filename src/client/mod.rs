@@ -1,4 +1,15 @@
 pub mod dispatcher;
+pub mod driver;
+pub mod event_stream;
+pub mod grouped_light_queue;
 pub mod hue;
+pub mod hue_pairing;
+pub mod hue_tls;
 pub mod model;
+pub mod mqtt;
+pub mod nanoleaf;
+pub mod request_policy;
 pub mod room_state;
+#[cfg(test)]
+pub(crate) mod test_server;
+pub mod wemo;
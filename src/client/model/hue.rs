@@ -1,3 +1,5 @@
+use std::fmt::{self, Debug, Formatter};
+
 use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
 use uuid::Uuid;
@@ -28,10 +30,18 @@ impl LightGroupOn {
 
 #[derive(TypedBuilder, Serialize, Debug)]
 pub struct GroupedLightPutBody {
-    on: LightGroupOn,
+    #[builder(default=None, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on: Option<LightGroupOn>,
     #[builder(default=None, setter(strip_option))]
     #[serde(skip_serializing_if = "Option::is_none")]
     dimming: Option<LightGroupDimming>,
+    #[builder(default=None, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimming_delta: Option<LightGroupDimmingDelta>,
+    #[builder(default=None, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dynamics: Option<LightDynamics>,
 }
 
 #[derive(Serialize, Debug)]
@@ -79,6 +89,43 @@ impl LightGroupDimming {
     }
 }
 
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DimmingDeltaAction {
+    Up,
+    Down,
+    Stop,
+}
+
+/// moves brightness relative to where it is now. the bridge keeps moving toward the target
+/// until it gets there or it gets a `stop`.
+#[derive(Serialize, Debug, Clone)]
+pub struct LightGroupDimmingDelta {
+    pub action: DimmingDeltaAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness_delta: Option<f32>,
+}
+
+impl LightGroupDimmingDelta {
+    pub fn new(action: DimmingDeltaAction, brightness_delta: f32) -> Self {
+        Self {
+            action,
+            brightness_delta: Some(brightness_delta),
+        }
+    }
+
+    pub const STOP: LightGroupDimmingDelta = LightGroupDimmingDelta {
+        action: DimmingDeltaAction::Stop,
+        brightness_delta: None,
+    };
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LightDynamics {
+    /// transition time in milliseconds
+    pub duration: u64,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct ActionPut {
     target: HueReference,
@@ -100,9 +147,31 @@ pub struct HueRoom {
     pub metadata: HueObjectMetadata,
 }
 
+impl HueRoom {
+    pub fn grouped_light_id(&self) -> Option<Uuid> {
+        self.services.iter().find_map(|service| match service {
+            HueReference::GroupedLight(id) => Some(*id),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HueScene {
+    pub id: Uuid,
+    pub metadata: HueSceneMetadata,
+    pub group: HueReference,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HueSceneMetadata {
+    pub name: String,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct HueObjectMetadata {
     pub name: String,
+    #[serde(rename = "archetype")]
     pub archtype: String,
 }
 
@@ -113,6 +182,7 @@ pub enum HueReference {
     Device(Uuid),
     GroupedLight(Uuid),
     Room(Uuid),
+    Zone(Uuid),
 
     #[serde(rename = "")]
     Empty(String),
@@ -122,11 +192,129 @@ impl HueReference {
     pub const EMPTY: HueReference = HueReference::Empty(String::new());
 }
 
+/// a single message from the bridge's `/eventstream/clip/v2` server-sent event stream.
+///
+/// `data` is left as raw json so one resource type we don't understand doesn't keep us from
+/// reading the rest of the event.
+#[derive(Deserialize, Debug)]
+pub struct HueEvent {
+    #[serde(rename = "type")]
+    pub event_type: HueEventType,
+    pub data: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HueEventType {
+    Add,
+    Update,
+    Delete,
+    Error,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HueResourceUpdate {
+    GroupedLight(GroupedLightUpdate),
+    Scene(SceneUpdate),
+    #[serde(other)]
+    Other,
+}
+
+/// update events only carry the fields that changed
+#[derive(Deserialize, Debug, Clone)]
+pub struct GroupedLightUpdate {
+    pub id: Uuid,
+    pub on: Option<LightGroupOn>,
+    pub dimming: Option<LightGroupDimming>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SceneUpdate {
+    pub id: Uuid,
+    pub status: Option<SceneStatus>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SceneStatus {
+    pub active: SceneActivity,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SceneActivity {
+    Inactive,
+    Static,
+    DynamicPalette,
+    #[serde(other)]
+    Other,
+}
+
+/// the body for creating an application key with the bridge's v1 `/api` endpoint
+#[derive(Serialize, Debug)]
+pub struct PairingRequestBody {
+    devicetype: String,
+    generateclientkey: bool,
+}
+
+impl PairingRequestBody {
+    pub fn new(device_type: &str) -> Self {
+        PairingRequestBody {
+            devicetype: device_type.to_string(),
+            generateclientkey: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PairingResult {
+    Success(HueCredentials),
+    Error(HueApiError),
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+pub struct HueCredentials {
+    #[serde(rename = "username")]
+    pub application_key: String,
+    #[serde(rename = "clientkey")]
+    pub client_key: Option<String>,
+}
+
+// the keys are as good as a password for the bridge, so keep them out of the logs
+impl Debug for HueCredentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HueCredentials")
+            .field("application_key", &"<redacted>")
+            .field(
+                "client_key",
+                &self.client_key.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HueApiError {
+    #[serde(rename = "type")]
+    pub error_type: u32,
+    pub description: String,
+}
+
+impl HueApiError {
+    pub const LINK_BUTTON_NOT_PRESSED: u32 = 101;
+}
+
 #[cfg(test)]
 mod tests {
     use uuid::Uuid;
 
-    use crate::client::model::hue::HueReference;
+    use crate::client::model::hue::{
+        DimmingDeltaAction, GroupedLightPutBody, HueCredentials, HueReference, LightDynamics,
+        LightGroupDimmingDelta,
+    };
 
     #[test]
     fn it_deserializes_a_hue_reference() {
@@ -134,8 +322,8 @@ mod tests {
         let hue_reference_text = r#"{"rid": "RID", "rtype": "device"}"#;
         let json = hue_reference_text.replace("RID", reference_id.to_string().as_str());
 
-        let deserialized_reference: HueReference =
-            serde_json::from_str(&json).expect(format!("unable to deserialize {}", json).as_str());
+        let deserialized_reference: HueReference = serde_json::from_str(&json)
+            .unwrap_or_else(|_| panic!("unable to deserialize {}", json));
 
         match deserialized_reference {
             HueReference::Device(id) => {
@@ -146,4 +334,37 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn it_serializes_a_dimming_delta_request() {
+        let ramp = GroupedLightPutBody::builder()
+            .dimming_delta(LightGroupDimmingDelta::new(DimmingDeltaAction::Up, 40.0))
+            .dynamics(LightDynamics { duration: 2000 })
+            .build();
+        let stop = GroupedLightPutBody::builder()
+            .dimming_delta(LightGroupDimmingDelta::STOP)
+            .build();
+
+        assert_eq!(
+            serde_json::to_string(&ramp).unwrap(),
+            r#"{"dimming_delta":{"action":"up","brightness_delta":40.0},"dynamics":{"duration":2000}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&stop).unwrap(),
+            r#"{"dimming_delta":{"action":"stop"}}"#
+        );
+    }
+
+    #[test]
+    fn it_keeps_credentials_out_of_debug_output() {
+        let credentials = HueCredentials {
+            application_key: "application-key".to_string(),
+            client_key: Some("ABCDEF".to_string()),
+        };
+
+        let debug = format!("{:?}", credentials);
+
+        assert!(!debug.contains("application-key"), "{}", debug);
+        assert!(!debug.contains("ABCDEF"), "{}", debug);
+    }
 }
@@ -0,0 +1,470 @@
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, Publish, QoS};
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use tokio::time;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::backoff::ExponentialBackoff;
+use crate::client::dispatcher::{DeviceAction, DeviceActionMessage};
+use crate::config::auth_configuration::{MqttConfiguration, MqttTopicConfiguration};
+use crate::config::bindings::RoomAction;
+use crate::config::caseta_remote::{ButtonAction, ButtonId, RemoteId};
+
+const DEFAULT_INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_MAXIMUM_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+// messages wait here while the broker is away. once it fills up, new events are dropped instead
+// of holding up the caseta connection
+const REQUEST_CAPACITY: usize = 64;
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+#[derive(Serialize, Debug)]
+struct ButtonEventPayload {
+    remote_id: RemoteId,
+    button_id: ButtonId,
+    button_action: ButtonAction,
+}
+
+#[derive(Serialize, Debug)]
+struct DeviceActionPayload {
+    remote_id: RemoteId,
+    button_id: ButtonId,
+    device_action: DeviceAction,
+}
+
+/// a room action published to the command topic, like
+/// `{"room": "living room", "type": "recall_scene", "scene": "bright"}`
+#[derive(Deserialize, Debug, PartialEq)]
+struct RoomCommand {
+    room: String,
+    #[serde(flatten)]
+    action: RoomAction,
+}
+
+/// Publishes caseta events to the mqtt broker.
+///
+/// Publishing never waits on the broker, so a slow or missing broker can't hold up the caseta
+/// connection or the dispatcher. [`mqtt_loop`] does the actual sending.
+#[derive(Debug, Clone)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topics: MqttTopicConfiguration,
+}
+
+impl MqttPublisher {
+    pub fn publish_button_event(
+        &self,
+        remote_id: RemoteId,
+        button_id: ButtonId,
+        button_action: ButtonAction,
+    ) {
+        let topic = button_topic(&self.topics.button_events, remote_id, button_id);
+        self.publish(
+            topic,
+            &ButtonEventPayload {
+                remote_id,
+                button_id,
+                button_action,
+            },
+        );
+    }
+
+    pub fn publish_device_action(
+        &self,
+        remote_id: RemoteId,
+        button_id: ButtonId,
+        device_action: DeviceAction,
+    ) {
+        let topic = button_topic(&self.topics.device_actions, remote_id, button_id);
+        self.publish(
+            topic,
+            &DeviceActionPayload {
+                remote_id,
+                button_id,
+                device_action,
+            },
+        );
+    }
+
+    fn publish<T: serde::Serialize>(&self, topic: String, payload: &T) {
+        let payload = serde_json::to_vec(payload).expect("mqtt payloads should always serialize");
+        if let Err(e) = self
+            .client
+            .try_publish(&topic, QoS::AtLeastOnce, false, payload)
+        {
+            warn!(topic=%topic, error=%e, "dropping an mqtt message");
+        }
+    }
+}
+
+/// Keeps the connection to the mqtt broker going and hands room actions from the command topic
+/// to the dispatcher.
+///
+/// The broker forgets our subscription whenever the connection drops, so every (re)connection
+/// subscribes again and marks the listener `online`. The broker publishes `offline` for us when
+/// the connection drops without a goodbye.
+pub struct MqttListener {
+    client: AsyncClient,
+    event_loop: EventLoop,
+    topics: MqttTopicConfiguration,
+    action_sender: Sender<DeviceActionMessage>,
+    backoff: ExponentialBackoff,
+}
+
+/// the two halves of the mqtt bridge. nothing connects until [`mqtt_loop`] runs the listener
+pub fn mqtt_bridge(
+    configuration: &MqttConfiguration,
+    action_sender: Sender<DeviceActionMessage>,
+) -> (MqttPublisher, MqttListener) {
+    let topics = configuration.topics.clone();
+    let mut options = MqttOptions::new(
+        &configuration.client_id,
+        configuration.host.to_string(),
+        configuration.port,
+    );
+    options
+        .set_keep_alive(configuration.keep_alive())
+        .set_last_will(LastWill::new(
+            &topics.availability,
+            OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+    if let (Some(username), Some(password)) = (&configuration.username, &configuration.password) {
+        options.set_credentials(username, password);
+    }
+    let (client, event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+    let publisher = MqttPublisher {
+        client: client.clone(),
+        topics: topics.clone(),
+    };
+    let listener = MqttListener {
+        client,
+        event_loop,
+        topics,
+        action_sender,
+        backoff: ExponentialBackoff::new(
+            DEFAULT_INITIAL_RECONNECT_DELAY,
+            DEFAULT_MAXIMUM_RECONNECT_DELAY,
+        ),
+    };
+    (publisher, listener)
+}
+
+impl MqttListener {
+    pub fn with_backoff(mut self, backoff: ExponentialBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    fn on_connect(&self) {
+        if let Err(e) = self
+            .client
+            .try_subscribe(&self.topics.commands, QoS::AtLeastOnce)
+        {
+            warn!(error=%e, "unable to subscribe to the mqtt command topic");
+        }
+        if let Err(e) =
+            self.client
+                .try_publish(&self.topics.availability, QoS::AtLeastOnce, true, ONLINE)
+        {
+            warn!(error=%e, "unable to publish the listener's availability");
+        }
+    }
+
+    /// `false` once the dispatcher has gone away and there's nothing left to do. this runs in the
+    /// loop that keeps the connection alive, so it never waits on the dispatcher
+    fn handle_publish(&self, publish: Publish) -> bool {
+        if !rumqttc::matches(&publish.topic, &self.topics.commands) {
+            debug!(topic=%publish.topic, "ignoring an mqtt message from an unexpected topic");
+            return true;
+        }
+        let command = match serde_json::from_slice::<RoomCommand>(&publish.payload) {
+            Ok(command) => command,
+            Err(e) => {
+                warn!(
+                    error=%e,
+                    payload=%String::from_utf8_lossy(&publish.payload),
+                    "ignoring an mqtt command that isn't a room action"
+                );
+                return true;
+            }
+        };
+        debug!(room=%command.room, room_action=?command.action, "received a room action over mqtt");
+        let message = DeviceActionMessage::room_action(&command.room, command.action);
+        match self.action_sender.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(message)) => {
+                warn!(message=?message, "the dispatcher is busy, so dropping an mqtt command");
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// stay connected to the mqtt broker forever, reconnecting whenever it goes away
+#[instrument(skip(listener))]
+pub async fn mqtt_loop(mut listener: MqttListener) {
+    loop {
+        match listener.event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("connected to the mqtt broker");
+                listener.backoff.reset();
+                listener.on_connect();
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                if !listener.handle_publish(publish) {
+                    error!("the dispatcher stopped, so mqtt commands have nowhere to go");
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let delay = listener.backoff.next_delay();
+                warn!(
+                    error=%e,
+                    attempt = listener.backoff.attempt(),
+                    "the mqtt connection failed. reconnecting in {:?}", delay
+                );
+                time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+// the snake_case button names from the configuration files, so topics read like
+// `caseta_listener/button_events/2/power_on`
+fn button_topic(prefix: &str, remote_id: RemoteId, button_id: ButtonId) -> String {
+    let button_name = serde_json::to_value(button_id).expect("button ids should always serialize");
+    format!(
+        "{}/{}/{}",
+        prefix,
+        remote_id,
+        button_name
+            .as_str()
+            .expect("button ids serialize to strings")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, Packet, Publish, QoS};
+    use serde_json::{json, Value};
+    use spectral::prelude::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+
+    use crate::backoff::ExponentialBackoff;
+    use crate::client::dispatcher::{DeviceAction, DeviceActionMessage};
+    use crate::client::mqtt::{mqtt_bridge, mqtt_loop, RoomCommand};
+    use crate::config::auth_configuration::{MqttConfiguration, MqttTopicConfiguration};
+    use crate::config::bindings::RoomAction;
+    use crate::config::caseta_remote::{ButtonAction, ButtonId};
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// just enough of a broker to accept one client at a time
+    struct FakeBroker {
+        listener: TcpListener,
+    }
+
+    struct BrokerConnection {
+        stream: TcpStream,
+        buffer: BytesMut,
+    }
+
+    impl FakeBroker {
+        async fn start() -> Self {
+            FakeBroker {
+                listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            }
+        }
+
+        fn port(&self) -> u16 {
+            self.listener.local_addr().unwrap().port()
+        }
+
+        async fn accept(&self) -> BrokerConnection {
+            let (stream, _) = timeout(TEST_TIMEOUT, self.listener.accept())
+                .await
+                .expect("the client should connect")
+                .unwrap();
+            let mut connection = BrokerConnection {
+                stream,
+                buffer: BytesMut::new(),
+            };
+            assert_that(&matches!(
+                connection.next_packet().await,
+                Packet::Connect(_)
+            ))
+            .is_true();
+            let mut connack = BytesMut::new();
+            ConnAck::new(ConnectReturnCode::Success, false)
+                .write(&mut connack)
+                .unwrap();
+            connection.stream.write_all(&connack).await.unwrap();
+            connection
+        }
+    }
+
+    impl BrokerConnection {
+        async fn next_packet(&mut self) -> Packet {
+            loop {
+                if let Ok(packet) = rumqttc::mqttbytes::v4::read(&mut self.buffer, 10 * 1024) {
+                    return packet;
+                }
+                let read = timeout(TEST_TIMEOUT, self.stream.read_buf(&mut self.buffer))
+                    .await
+                    .expect("the client should send another packet")
+                    .unwrap();
+                assert_that(&read).is_greater_than(0);
+            }
+        }
+
+        async fn next_publish(&mut self) -> Publish {
+            loop {
+                if let Packet::Publish(publish) = self.next_packet().await {
+                    return publish;
+                }
+            }
+        }
+
+        async fn expect_subscription(&mut self) -> String {
+            loop {
+                if let Packet::Subscribe(subscribe) = self.next_packet().await {
+                    return subscribe.filters[0].path.clone();
+                }
+            }
+        }
+
+        async fn send(&mut self, publish: Publish) {
+            let mut buffer = BytesMut::new();
+            publish.write(&mut buffer).unwrap();
+            self.stream.write_all(&buffer).await.unwrap();
+        }
+    }
+
+    fn configuration(port: u16) -> MqttConfiguration {
+        MqttConfiguration {
+            host: url::Host::parse("127.0.0.1").unwrap(),
+            port,
+            client_id: "caseta_listener_test".to_string(),
+            username: None,
+            password: None,
+            keep_alive_secs: 30,
+            topics: MqttTopicConfiguration::default(),
+        }
+    }
+
+    fn payload(publish: &Publish) -> Value {
+        serde_json::from_slice(&publish.payload).unwrap()
+    }
+
+    #[test]
+    fn it_parses_room_commands() {
+        let command: RoomCommand =
+            serde_json::from_value(json!({"room": "Living Room", "type": "brightness_up"}))
+                .unwrap();
+
+        assert_that(&command).is_equal_to(RoomCommand {
+            room: "Living Room".to_string(),
+            action: RoomAction::BrightnessUp { steps: 1 },
+        });
+        assert_that(&serde_json::from_value::<RoomCommand>(json!({"type": "toggle"})).is_err())
+            .is_true();
+    }
+
+    #[tokio::test]
+    async fn it_drops_commands_instead_of_waiting_on_a_busy_dispatcher() {
+        let mut configuration = configuration(1883);
+        configuration.topics.commands = "caseta_listener/commands/+".to_string();
+        let (action_sender, mut action_receiver) = mpsc::channel(1);
+        let (_publisher, listener) = mqtt_bridge(&configuration, action_sender);
+        let command = |topic: &str| {
+            Publish::new(
+                topic,
+                QoS::AtMostOnce,
+                r#"{"room": "Living Room", "type": "toggle"}"#,
+            )
+        };
+
+        assert_that(&listener.handle_publish(command("caseta_listener/commands/living_room")))
+            .is_true();
+        assert_that(&listener.handle_publish(command("caseta_listener/commands/office"))).is_true();
+        assert_that(&listener.handle_publish(command("caseta_listener/other/office"))).is_true();
+
+        assert_that(&action_receiver.try_recv().unwrap()).is_equal_to(
+            DeviceActionMessage::room_action("Living Room", RoomAction::Toggle),
+        );
+        assert_that(&action_receiver.try_recv().is_err()).is_true();
+        drop(action_receiver);
+        assert_that(&listener.handle_publish(command("caseta_listener/commands/living_room")))
+            .is_false();
+    }
+
+    #[tokio::test]
+    async fn it_publishes_events_and_forwards_commands_across_reconnects() {
+        let broker = FakeBroker::start().await;
+        let (action_sender, mut action_receiver) = mpsc::channel(8);
+        let (publisher, listener) = mqtt_bridge(&configuration(broker.port()), action_sender);
+        let listener = listener.with_backoff(ExponentialBackoff::new(
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+        ));
+        tokio::spawn(mqtt_loop(listener));
+
+        let mut connection = broker.accept().await;
+        assert_that(&connection.expect_subscription().await)
+            .is_equal_to("caseta_listener/commands".to_string());
+        let availability = connection.next_publish().await;
+        assert_that(&availability.topic).is_equal_to("caseta_listener/availability".to_string());
+        assert_that(&availability.retain).is_true();
+
+        publisher.publish_button_event(2, ButtonId::PowerOn, ButtonAction::Press);
+        publisher.publish_device_action(2, ButtonId::Up, DeviceAction::DoublePressComplete);
+        let button_event = connection.next_publish().await;
+        let device_action = connection.next_publish().await;
+        assert_that(&button_event.topic)
+            .is_equal_to("caseta_listener/button_events/2/power_on".to_string());
+        assert_that(&payload(&button_event)).is_equal_to(
+            json!({"remote_id": 2, "button_id": "power_on", "button_action": "press"}),
+        );
+        assert_that(&device_action.topic)
+            .is_equal_to("caseta_listener/device_actions/2/up".to_string());
+        assert_that(&payload(&device_action)).is_equal_to(
+            json!({"remote_id": 2, "button_id": "up", "device_action": "double_press_complete"}),
+        );
+
+        // the broker goes away, and the listener should come back and subscribe again
+        drop(connection);
+        let mut connection = broker.accept().await;
+        assert_that(&connection.expect_subscription().await)
+            .is_equal_to("caseta_listener/commands".to_string());
+        connection
+            .send(Publish::new(
+                "caseta_listener/commands",
+                QoS::AtMostOnce,
+                r#"{"room": "Living Room", "type": "recall_scene", "scene": "bright"}"#,
+            ))
+            .await;
+
+        let message = timeout(TEST_TIMEOUT, action_receiver.recv())
+            .await
+            .expect("the command should reach the dispatcher");
+        assert_that(&message).is_equal_to(Some(DeviceActionMessage::room_action(
+            "Living Room",
+            RoomAction::RecallScene {
+                scene: "bright".to_string(),
+            },
+        )));
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, instrument};
+use url::Host;
+
+const MAXIMUM_NANOLEAF_BRIGHTNESS: f32 = 100.0;
+// the dispatcher holds the room while it waits on every device, so an unplugged set of panels
+// shouldn't be able to hold it for long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug)]
+struct NanoleafValue<T> {
+    value: T,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct NanoleafStatePutBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on: Option<NanoleafValue<bool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness: Option<NanoleafValue<u8>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NanoleafStateResponse {
+    on: NanoleafValue<bool>,
+    brightness: NanoleafValue<u8>,
+}
+
+/// whether the panels are on, and their brightness as a percentage
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NanoleafState {
+    pub on: bool,
+    pub brightness: f32,
+}
+
+#[derive(Serialize, Debug)]
+struct NanoleafEffectPutBody<'a> {
+    select: &'a str,
+}
+
+/// A client for a single set of Nanoleaf light panels, using the local OpenAPI.
+///
+/// Every request path includes the auth token, so there's no auth header to set.
+#[derive(Debug, Clone)]
+pub struct NanoleafClient {
+    base_url: Url,
+    http_client: Client,
+}
+
+impl NanoleafClient {
+    pub fn new(host: &Host, port: u16, auth_token: &str) -> NanoleafClient {
+        let device_url = Url::parse(format!("http://{}:{}/", host, port).as_str())
+            .expect("unable to parse the nanoleaf URL");
+        Self::from_device_url(device_url, auth_token)
+    }
+
+    pub(crate) fn from_device_url(device_url: Url, auth_token: &str) -> NanoleafClient {
+        let base_url = device_url
+            .join(format!("api/v1/{}/", auth_token).as_str())
+            .expect("unable to build the nanoleaf base URL");
+        NanoleafClient {
+            base_url,
+            http_client: Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("there was a problem building the http client"),
+        }
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    pub async fn set_on(&self, on: bool) -> Result<()> {
+        self.put_state(NanoleafStatePutBody {
+            on: Some(NanoleafValue { value: on }),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// `brightness` is a percentage, like hue brightness
+    #[instrument(level = "debug", skip(self))]
+    pub async fn set_brightness(&self, brightness: f32) -> Result<()> {
+        let brightness = brightness.clamp(0.0, MAXIMUM_NANOLEAF_BRIGHTNESS).round() as u8;
+        self.put_state(NanoleafStatePutBody {
+            brightness: Some(NanoleafValue { value: brightness }),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// selecting an effect also turns the panels on
+    #[instrument(level = "debug", skip(self))]
+    pub async fn select_effect(&self, effect: &str) -> Result<()> {
+        let url = self
+            .base_url
+            .join("effects")
+            .expect("unable to build the nanoleaf effects URL");
+        let body = NanoleafEffectPutBody { select: effect };
+        let response = self.http_client.put(url).json(&body).send().await?;
+        // the response's debug output includes the url, which includes the auth token
+        debug!(status=%response.status(), "got select_effect response");
+        Self::ensure_success(response, "selecting a nanoleaf effect").await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_state(&self) -> Result<NanoleafState> {
+        let url = self
+            .base_url
+            .join("state")
+            .expect("unable to build the nanoleaf state URL");
+        let response = self.http_client.get(url).send().await?;
+        debug!(status=%response.status(), "got nanoleaf state response");
+        let state: NanoleafStateResponse =
+            Self::ensure_success(response, "getting the nanoleaf state")
+                .await?
+                .json()
+                .await?;
+        Ok(NanoleafState {
+            on: state.on.value,
+            brightness: state.brightness.value.into(),
+        })
+    }
+
+    async fn put_state(&self, body: NanoleafStatePutBody) -> Result<()> {
+        let url = self
+            .base_url
+            .join("state")
+            .expect("unable to build the nanoleaf state URL");
+        let response = self.http_client.put(url).json(&body).send().await?;
+        debug!(status=%response.status(), "got nanoleaf state response");
+        Self::ensure_success(response, "updating the nanoleaf state").await?;
+        Ok(())
+    }
+
+    async fn ensure_success(
+        response: reqwest::Response,
+        description: &str,
+    ) -> Result<reqwest::Response> {
+        let status = response.status();
+        if !status.is_success() {
+            let response_body = response.text().await?;
+            error!(
+                "there was a problem {}. status: {}, body: {}",
+                description, status, response_body
+            );
+            bail!(
+                "there was a problem {}. status: {}, body: {}",
+                description,
+                status,
+                response_body
+            )
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use spectral::prelude::*;
+
+    use crate::client::nanoleaf::{NanoleafClient, NanoleafState};
+    use crate::client::test_server::TestServer;
+
+    #[tokio::test]
+    async fn it_sends_openapi_requests() {
+        let server = TestServer::start(204, "").await;
+        let client =
+            NanoleafClient::from_device_url(Url::parse(&server.base_url).unwrap(), "token");
+
+        client.select_effect("cozy red").await.unwrap();
+        client.set_brightness(42.6).await.unwrap();
+        client.set_on(false).await.unwrap();
+
+        let requests = server.requests();
+        assert_that(&requests).has_length(3);
+        assert_that(&requests[0].method).is_equal_to("PUT".to_string());
+        assert_that(&requests[0].path).is_equal_to("/api/v1/token/effects".to_string());
+        assert_that(&requests[0].body).is_equal_to(r#"{"select":"cozy red"}"#.to_string());
+        assert_that(&requests[0].header("content-type")).is_equal_to(Some("application/json"));
+        assert_that(&requests[1].path).is_equal_to("/api/v1/token/state".to_string());
+        assert_that(&requests[1].body).is_equal_to(r#"{"brightness":{"value":43}}"#.to_string());
+        assert_that(&requests[2].body).is_equal_to(r#"{"on":{"value":false}}"#.to_string());
+    }
+
+    #[tokio::test]
+    async fn it_reads_the_panel_state() {
+        let server = TestServer::start(
+            200,
+            r#"{"on":{"value":true},"brightness":{"value":40,"max":100,"min":0},"hue":{"value":0}}"#,
+        )
+        .await;
+        let client =
+            NanoleafClient::from_device_url(Url::parse(&server.base_url).unwrap(), "token");
+
+        let state = client.get_state().await.unwrap();
+
+        assert_that(&state).is_equal_to(NanoleafState {
+            on: true,
+            brightness: 40.0,
+        });
+        assert_that(&server.requests()[0].method).is_equal_to("GET".to_string());
+    }
+
+    #[tokio::test]
+    async fn it_reports_rejected_requests() {
+        let server = TestServer::start(401, "").await;
+        let client =
+            NanoleafClient::from_device_url(Url::parse(&server.base_url).unwrap(), "bad-token");
+
+        let result = client.set_on(true).await;
+
+        assert_that(&result.is_err()).is_true();
+    }
+}
@@ -0,0 +1,358 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use tokio::time;
+use tracing::warn;
+
+use crate::backoff::ExponentialBackoff;
+
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_MAXIMUM_RETRIES: u32 = 3;
+const DEFAULT_INITIAL_RETRY_DELAY: Duration = Duration::from_millis(250);
+const DEFAULT_MAXIMUM_RETRY_DELAY: Duration = Duration::from_secs(2);
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_RESET_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(thiserror::Error, Debug)]
+pub enum RequestError {
+    #[error("the circuit breaker is open, so we're not sending requests for another {0:?}")]
+    CircuitOpen(Duration),
+    #[error("the request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+}
+
+/// Stops sending requests to a device that keeps failing.
+///
+/// After `failure_threshold` requests in a row fail, the breaker opens and requests fail
+/// immediately for `reset_timeout`. Once that passes, requests go out again, but the next failure
+/// reopens the breaker right away. A success closes it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    state: Mutex<CircuitBreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitBreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+            state: Mutex::new(CircuitBreakerState::default()),
+        }
+    }
+
+    pub fn check(&self) -> Result<(), RequestError> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(open_until) => {
+                let now = Instant::now();
+                if now < open_until {
+                    Err(RequestError::CircuitOpen(open_until - now))
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.check().is_err()
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.open_until = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.consecutive_failures >= self.failure_threshold {
+            warn!(
+                consecutive_failures = state.consecutive_failures,
+                "opening the circuit breaker for {:?}", self.reset_timeout
+            );
+            state.open_until = Some(Instant::now() + self.reset_timeout);
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_RESET_TIMEOUT)
+    }
+}
+
+/// How to send requests to a device on the local network: every attempt gets a timeout,
+/// connection errors and busy responses (429 and 503) are retried with backoff, and a
+/// [`CircuitBreaker`] shared by every clone of the policy fails fast while the device is down.
+#[derive(Debug, Clone)]
+pub struct RequestPolicy {
+    request_timeout: Duration,
+    max_retries: u32,
+    initial_retry_delay: Duration,
+    max_retry_delay: Duration,
+    circuit_breaker: Arc<CircuitBreaker>,
+}
+
+impl RequestPolicy {
+    pub fn new(
+        request_timeout: Duration,
+        max_retries: u32,
+        initial_retry_delay: Duration,
+        max_retry_delay: Duration,
+    ) -> Self {
+        Self {
+            request_timeout,
+            max_retries,
+            initial_retry_delay,
+            max_retry_delay,
+            circuit_breaker: Arc::new(CircuitBreaker::default()),
+        }
+    }
+
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Arc::new(circuit_breaker);
+        self
+    }
+
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        self.circuit_breaker.clone()
+    }
+
+    /// Sends the request `build_request` makes, building a fresh one for every attempt. Once the
+    /// retries run out, the last busy response is returned like any other response.
+    pub async fn send<F>(&self, build_request: F) -> Result<Response, RequestError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        self.send_with_retries(build_request, true).await
+    }
+
+    /// Like [`send`](Self::send), for requests that mustn't be applied twice, like a relative
+    /// dimming delta. An attempt that timed out might still have reached the device, so it isn't
+    /// retried. Connection errors and busy responses still are, since the device didn't act on them.
+    pub async fn send_non_idempotent<F>(&self, build_request: F) -> Result<Response, RequestError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        self.send_with_retries(build_request, false).await
+    }
+
+    async fn send_with_retries<F>(
+        &self,
+        build_request: F,
+        retry_timeouts: bool,
+    ) -> Result<Response, RequestError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        self.circuit_breaker.check()?;
+        let mut backoff = ExponentialBackoff::new(self.initial_retry_delay, self.max_retry_delay);
+        loop {
+            let result = build_request().timeout(self.request_timeout).send().await;
+            let retry_after = match &result {
+                Ok(response) if Self::is_transient_status(response.status()) => {
+                    Self::retry_after(response)
+                }
+                Ok(_) => {
+                    self.circuit_breaker.record_success();
+                    return result.map_err(RequestError::from);
+                }
+                Err(error) if error.is_connect() || (error.is_timeout() && retry_timeouts) => None,
+                Err(_) => {
+                    self.circuit_breaker.record_failure();
+                    return result.map_err(RequestError::from);
+                }
+            };
+
+            if backoff.attempt() >= self.max_retries {
+                self.circuit_breaker.record_failure();
+                return result.map_err(RequestError::from);
+            }
+            let delay = backoff
+                .next_delay()
+                .max(retry_after.unwrap_or_default().min(self.max_retry_delay));
+            match &result {
+                Ok(response) => warn!(
+                    status=%response.status(),
+                    attempt=backoff.attempt(),
+                    "the request got a busy response. retrying in {:?}", delay
+                ),
+                Err(error) => warn!(
+                    error=%error,
+                    attempt=backoff.attempt(),
+                    "the request failed. retrying in {:?}", delay
+                ),
+            }
+            time::sleep(delay).await;
+        }
+    }
+
+    fn is_transient_status(status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    // we only handle the delay-seconds form. the bridge doesn't send http dates
+    fn retry_after(response: &Response) -> Option<Duration> {
+        response
+            .headers()
+            .get(RETRY_AFTER)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .map(Duration::from_secs)
+    }
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_REQUEST_TIMEOUT,
+            DEFAULT_MAXIMUM_RETRIES,
+            DEFAULT_INITIAL_RETRY_DELAY,
+            DEFAULT_MAXIMUM_RETRY_DELAY,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use reqwest::{Client, StatusCode};
+    use spectral::prelude::*;
+    use tokio::net::TcpListener;
+
+    use crate::client::request_policy::{CircuitBreaker, RequestError, RequestPolicy};
+    use crate::client::test_server::TestServer;
+
+    fn fast_policy(max_retries: u32) -> RequestPolicy {
+        RequestPolicy::new(
+            Duration::from_millis(500),
+            max_retries,
+            Duration::from_millis(1),
+            Duration::from_millis(5),
+        )
+    }
+
+    // accepts connections and counts them, then either hangs up or never answers
+    async fn unhelpful_server(hang_up: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counted_connections = connections.clone();
+        tokio::spawn(async move {
+            let mut open_sockets = Vec::new();
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                counted_connections.fetch_add(1, Ordering::SeqCst);
+                if !hang_up {
+                    open_sockets.push(socket);
+                }
+            }
+        });
+        (url, connections)
+    }
+
+    #[tokio::test]
+    async fn it_counts_every_transport_error_toward_the_circuit_breaker() {
+        let (url, _connections) = unhelpful_server(true).await;
+        let client = Client::new();
+        let policy =
+            fast_policy(3).with_circuit_breaker(CircuitBreaker::new(1, Duration::from_secs(30)));
+
+        let result = policy.send(|| client.get(url.as_str())).await;
+
+        assert!(matches!(result, Err(RequestError::Transport(_))));
+        assert_that(&policy.circuit_breaker().is_open()).is_true();
+    }
+
+    #[tokio::test]
+    async fn it_does_not_retry_non_idempotent_requests_that_timed_out() {
+        let (url, connections) = unhelpful_server(false).await;
+        let client = Client::new();
+        let policy = RequestPolicy::new(
+            Duration::from_millis(50),
+            3,
+            Duration::from_millis(1),
+            Duration::from_millis(5),
+        );
+
+        let result = policy
+            .send_non_idempotent(|| client.put(url.as_str()))
+            .await;
+
+        assert!(matches!(result, Err(RequestError::Transport(ref e)) if e.is_timeout()));
+        assert_that(&connections.load(Ordering::SeqCst)).is_equal_to(1);
+    }
+
+    #[tokio::test]
+    async fn it_retries_busy_responses() {
+        let server =
+            TestServer::start_with_responses(vec![(429, ""), (503, ""), (200, "ok")]).await;
+        let client = Client::new();
+
+        let response = fast_policy(3)
+            .send(|| client.put(server.base_url.as_str()))
+            .await
+            .unwrap();
+
+        assert_that(&response.status()).is_equal_to(StatusCode::OK);
+        assert_that(&server.requests()).has_length(3);
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_after_the_last_retry() {
+        let server = TestServer::start(503, "").await;
+        let client = Client::new();
+
+        let response = fast_policy(2)
+            .send(|| client.get(server.base_url.as_str()))
+            .await
+            .unwrap();
+
+        assert_that(&response.status()).is_equal_to(StatusCode::SERVICE_UNAVAILABLE);
+        assert_that(&server.requests()).has_length(3);
+    }
+
+    #[tokio::test]
+    async fn it_fails_fast_once_the_circuit_breaker_opens() {
+        // grab a free port and close it, so connections get refused
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let client = Client::new();
+        let policy =
+            fast_policy(0).with_circuit_breaker(CircuitBreaker::new(2, Duration::from_millis(50)));
+
+        for _ in 0..2 {
+            let result = policy.send(|| client.get(url.as_str())).await;
+            assert!(matches!(result, Err(RequestError::Transport(_))));
+        }
+        let while_open = policy.send(|| client.get(url.as_str())).await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let server = TestServer::start(200, "").await;
+        let after_reset = policy.send(|| client.get(server.base_url.as_str())).await;
+
+        assert!(matches!(while_open, Err(RequestError::CircuitOpen(_))));
+        assert_that(&after_reset.is_ok()).is_true();
+        assert_that(&policy.circuit_breaker().is_open()).is_false();
+    }
+}
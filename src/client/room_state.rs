@@ -1,9 +1,10 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use mini_moka::sync::Cache;
 use uuid::Uuid;
 
 use crate::config::scene::Scene;
+use crate::metrics::metrics;
 
 const MAXIMUM_CACHE_SIZE: u16 = 1000;
 const MAXIMUM_CACHE_DURATION: Duration = Duration::from_secs(120);
@@ -25,12 +26,43 @@ impl CurrentRoomState {
     }
 }
 
-pub type CurrentRoomStateCache = Cache<Uuid, CurrentRoomState>;
+/// what we think each room is doing, and when we last found out
+#[derive(Debug, Clone)]
+pub struct CurrentRoomStateCache(Cache<Uuid, (CurrentRoomState, Instant)>);
+
+impl CurrentRoomStateCache {
+    pub fn get(&self, room_id: &Uuid) -> Option<CurrentRoomState> {
+        let state = self.get_with_age(room_id).map(|(state, _age)| state);
+        metrics().record_room_state_cache_lookup(state.is_some());
+        state
+    }
+
+    /// the cached state along with how long ago it was cached
+    pub fn get_with_age(&self, room_id: &Uuid) -> Option<(CurrentRoomState, Duration)> {
+        self.0
+            .get(room_id)
+            .map(|(state, cached_at)| (state, cached_at.elapsed()))
+    }
+
+    pub fn insert(&self, room_id: Uuid, state: CurrentRoomState) {
+        self.0.insert(room_id, (state, Instant::now()))
+    }
+
+    pub fn invalidate(&self, room_id: &Uuid) {
+        self.0.invalidate(room_id)
+    }
+
+    pub fn invalidate_all(&self) {
+        self.0.invalidate_all()
+    }
+}
 
 pub fn new_cache() -> CurrentRoomStateCache {
-    Cache::builder()
-        .max_capacity(MAXIMUM_CACHE_SIZE.into())
-        .time_to_live(MAXIMUM_CACHE_DURATION)
-        .time_to_idle(MAXIMUM_CACHE_DURATION)
-        .build()
+    CurrentRoomStateCache(
+        Cache::builder()
+            .max_capacity(MAXIMUM_CACHE_SIZE.into())
+            .time_to_live(MAXIMUM_CACHE_DURATION)
+            .time_to_idle(MAXIMUM_CACHE_DURATION)
+            .build(),
+    )
 }
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// a request the stand-in server received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+type Responses = Arc<Mutex<VecDeque<(u16, String)>>>;
+
+/// A bare-bones HTTP/1.1 server for tests. It records every request and answers them with a
+/// scripted list of responses, repeating the last one once the rest run out.
+#[derive(Debug)]
+pub struct TestServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl TestServer {
+    pub async fn start(status: u16, response_body: &str) -> TestServer {
+        Self::start_with_responses(vec![(status, response_body)]).await
+    }
+
+    pub async fn start_with_responses(responses: Vec<(u16, &str)>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let responses: Responses = Arc::new(Mutex::new(
+            responses
+                .into_iter()
+                .map(|(status, body)| (status, body.to_string()))
+                .collect(),
+        ));
+
+        let recorded_requests = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(Self::serve_connection(
+                    socket,
+                    responses.clone(),
+                    recorded_requests.clone(),
+                ));
+            }
+        });
+
+        TestServer { base_url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    async fn serve_connection(
+        mut socket: TcpStream,
+        responses: Responses,
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    ) {
+        let mut buffer = Vec::new();
+        loop {
+            let request = match Self::read_request(&mut socket, &mut buffer).await {
+                Some(request) => request,
+                None => return,
+            };
+            requests.lock().unwrap().push(request);
+
+            let (status, response_body) = {
+                let mut responses = responses.lock().unwrap();
+                match responses.len() {
+                    1 => responses[0].clone(),
+                    _ => responses.pop_front().unwrap(),
+                }
+            };
+            let response = format!(
+                "HTTP/1.1 {} Test\r\ncontent-length: {}\r\n\r\n{}",
+                status,
+                response_body.len(),
+                response_body
+            );
+            if socket.write_all(response.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn read_request(socket: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<RecordedRequest> {
+        let header_end = loop {
+            if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                break position + 4;
+            }
+            Self::read_more(socket, buffer).await?;
+        };
+
+        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?.to_string();
+        let path = request_line.next()?.to_string();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        let content_length = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.parse::<usize>().ok())
+            .unwrap_or(0);
+
+        while buffer.len() < header_end + content_length {
+            Self::read_more(socket, buffer).await?;
+        }
+        let body =
+            String::from_utf8_lossy(&buffer[header_end..header_end + content_length]).to_string();
+        buffer.drain(..header_end + content_length);
+
+        Some(RecordedRequest {
+            method,
+            path,
+            headers,
+            body,
+        })
+    }
+
+    async fn read_more(socket: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<()> {
+        let mut chunk = [0; 4096];
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => None,
+            Ok(read) => {
+                buffer.extend_from_slice(&chunk[..read]);
+                Some(())
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+use tracing::{debug, error, instrument};
+use url::Host;
+
+const BASIC_EVENT_CONTROL_PATH: &str = "upnp/control/basicevent1";
+const BASIC_EVENT_SERVICE: &str = "urn:Belkin:service:basicevent:1";
+const SOAP_ACTION_HEADER: &str = "SOAPACTION";
+const SOAP_CONTENT_TYPE: &str = "text/xml; charset=\"utf-8\"";
+const BINARY_STATE_OPEN_TAG: &str = "<BinaryState>";
+const BINARY_STATE_CLOSE_TAG: &str = "</BinaryState>";
+// like the nanoleaf client, an outlet that's unplugged shouldn't hold up the rest of the room
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A client for a single WeMo outlet, using the `basicevent` UPnP service.
+#[derive(Debug, Clone)]
+pub struct WemoClient {
+    control_url: Url,
+    http_client: Client,
+}
+
+impl WemoClient {
+    pub fn new(host: &Host, port: u16) -> WemoClient {
+        let device_url = Url::parse(format!("http://{}:{}/", host, port).as_str())
+            .expect("unable to parse the wemo URL");
+        Self::from_device_url(device_url)
+    }
+
+    pub(crate) fn from_device_url(device_url: Url) -> WemoClient {
+        let control_url = device_url
+            .join(BASIC_EVENT_CONTROL_PATH)
+            .expect("unable to build the wemo control URL");
+        WemoClient {
+            control_url,
+            http_client: Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("there was a problem building the http client"),
+        }
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn set_binary_state(&self, on: bool) -> Result<()> {
+        let arguments = format!(
+            "{}{}{}",
+            BINARY_STATE_OPEN_TAG,
+            u8::from(on),
+            BINARY_STATE_CLOSE_TAG
+        );
+        self.call("SetBinaryState", &arguments).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn get_binary_state(&self) -> Result<bool> {
+        let response_body = self.call("GetBinaryState", "").await?;
+        Self::parse_binary_state(&response_body)
+    }
+
+    async fn call(&self, action: &str, arguments: &str) -> Result<String> {
+        let envelope = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+                r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+                r#"<s:Body><u:{action} xmlns:u="{service}">{arguments}</u:{action}></s:Body>"#,
+                r#"</s:Envelope>"#
+            ),
+            action = action,
+            service = BASIC_EVENT_SERVICE,
+            arguments = arguments
+        );
+
+        let response = self
+            .http_client
+            .post(self.control_url.clone())
+            .header(CONTENT_TYPE, SOAP_CONTENT_TYPE)
+            .header(
+                SOAP_ACTION_HEADER,
+                format!("\"{}#{}\"", BASIC_EVENT_SERVICE, action),
+            )
+            .body(envelope)
+            .send()
+            .await?;
+        debug!("got {} response: {:?}", action, response);
+        let status = response.status();
+        let response_body = response.text().await?;
+        if !status.is_success() {
+            error!(
+                "there was a problem calling {} on the wemo outlet. status: {}, body: {}",
+                action, status, response_body
+            );
+            bail!(
+                "there was a problem calling {} on the wemo outlet. status: {}, body: {}",
+                action,
+                status,
+                response_body
+            )
+        }
+        Ok(response_body)
+    }
+
+    // insight outlets report `8` for on-but-idle and may append `|`-separated usage data
+    fn parse_binary_state(response_body: &str) -> Result<bool> {
+        let state = response_body
+            .split_once(BINARY_STATE_OPEN_TAG)
+            .and_then(|(_, rest)| rest.split_once(BINARY_STATE_CLOSE_TAG))
+            .map(|(state, _)| state.split('|').next().unwrap_or(state).trim())
+            .ok_or_else(|| anyhow!("the wemo response didn't include a binary state"))?;
+
+        match state {
+            "0" => Ok(false),
+            "1" | "8" => Ok(true),
+            other => bail!("unexpected wemo binary state {}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use spectral::prelude::*;
+
+    use crate::client::test_server::TestServer;
+    use crate::client::wemo::WemoClient;
+
+    const GET_BINARY_STATE_RESPONSE: &str = r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body>
+<u:GetBinaryStateResponse xmlns:u="urn:Belkin:service:basicevent:1">
+<BinaryState>8|1679952093|0|0|0|1209600|0|0|0|0</BinaryState>
+</u:GetBinaryStateResponse>
+</s:Body> </s:Envelope>"#;
+
+    #[tokio::test]
+    async fn it_sends_soap_requests() {
+        let server = TestServer::start(200, GET_BINARY_STATE_RESPONSE).await;
+        let client = WemoClient::from_device_url(Url::parse(&server.base_url).unwrap());
+
+        client.set_binary_state(true).await.unwrap();
+        let on = client.get_binary_state().await.unwrap();
+
+        let requests = server.requests();
+        assert_that(&on).is_true();
+        assert_that(&requests).has_length(2);
+        assert_that(&requests[0].method).is_equal_to("POST".to_string());
+        assert_that(&requests[0].path).is_equal_to("/upnp/control/basicevent1".to_string());
+        assert_that(&requests[0].header("soapaction"))
+            .is_equal_to(Some(r#""urn:Belkin:service:basicevent:1#SetBinaryState""#));
+        assert_that(&requests[0].body.contains(
+            r#"<u:SetBinaryState xmlns:u="urn:Belkin:service:basicevent:1"><BinaryState>1</BinaryState></u:SetBinaryState>"#,
+        ))
+        .is_true();
+        assert_that(&requests[1].header("soapaction"))
+            .is_equal_to(Some(r#""urn:Belkin:service:basicevent:1#GetBinaryState""#));
+    }
+
+    #[test]
+    fn it_parses_binary_states() {
+        let parse = |state: &str| {
+            WemoClient::parse_binary_state(&format!("<BinaryState>{}</BinaryState>", state))
+        };
+
+        assert_that(&parse("0").unwrap()).is_false();
+        assert_that(&parse("1").unwrap()).is_true();
+        assert_that(&parse("8|1679952093|0").unwrap()).is_true();
+        assert_that(&parse("Error").is_err()).is_true();
+        assert_that(&WemoClient::parse_binary_state("<s:Envelope/>").is_err()).is_true();
+    }
+}
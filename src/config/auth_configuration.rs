@@ -1,10 +1,32 @@
 use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
 
+use config::builder::{ConfigBuilder, DefaultState};
 use url::Host;
 const AUTH_CONFIGURATION_FILE_NAME_ENV_VAR: &str = "CASETA_LISTENER_AUTH_CONFIGURATION_FILE";
 const NON_SENSITIVE_CONFIGURATION_FILE_NAME_ENV_VAR: &str =
     "CASETA_LISTENER_NON_SENSITIVE_CONFIGURATION_FILE";
 const CASETA_LISTENER_ENV_VAR_PREFIX: &str = "CASETA_LISTENER";
+const DEFAULT_INITIAL_RECONNECT_DELAY_MILLIS: u64 = 500;
+const DEFAULT_MAXIMUM_RECONNECT_DELAY_SECS: u64 = 60;
+const DEFAULT_KEEP_ALIVE_INTERVAL_SECS: u64 = 60;
+const DEFAULT_KEEP_ALIVE_RESPONSE_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_NANOLEAF_PORT: u16 = 16021;
+const DEFAULT_WEMO_PORT: u16 = 49153;
+const DEFAULT_HUE_REQUEST_TIMEOUT_MILLIS: u64 = 5000;
+const DEFAULT_HUE_MAXIMUM_RETRIES: u32 = 3;
+const DEFAULT_HUE_INITIAL_RETRY_DELAY_MILLIS: u64 = 250;
+const DEFAULT_HUE_MAXIMUM_RETRY_DELAY_MILLIS: u64 = 2000;
+const DEFAULT_HUE_CIRCUIT_BREAKER_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_HUE_CIRCUIT_BREAKER_RESET_SECS: u64 = 30;
+const DEFAULT_HUE_GROUPED_LIGHT_COMMAND_INTERVAL_MILLIS: u64 = 1000;
+const DEFAULT_LIVENESS_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_KEEP_ALIVE_MAXIMUM_AGE_SECS: u64 = 150;
+const DEFAULT_HEALTH_CHECK_HUE_TIMEOUT_MILLIS: u64 = 2000;
+const DEFAULT_MQTT_PORT: u16 = 1883;
+const DEFAULT_MQTT_CLIENT_ID: &str = "caseta_listener";
+const DEFAULT_MQTT_KEEP_ALIVE_SECS: u64 = 30;
 
 #[derive(serde::Deserialize)]
 pub struct AuthConfiguration {
@@ -16,27 +38,360 @@ pub struct AuthConfiguration {
     #[serde(deserialize_with = "crate::config::serde_util::deserialize_host")]
     pub hue_host: Host<String>,
     pub hue_application_key: String,
+    #[serde(default)]
+    pub hue_requests: HueRequestConfiguration,
+    #[serde(default)]
+    pub hue_tls: HueTlsConfiguration,
+    /// compare the scene configuration's hue ids with the bridge on startup and log mismatches
+    #[serde(default)]
+    pub check_hue_ids_on_startup: bool,
+    #[serde(default)]
+    pub http_api: HttpApiConfiguration,
+    #[serde(default)]
+    pub health_checks: HealthCheckConfiguration,
+    #[serde(default)]
+    pub mqtt: Option<MqttConfiguration>,
+    #[serde(default)]
+    pub caseta_reconnect: ReconnectConfiguration,
+    #[serde(default)]
+    pub caseta_keep_alive: KeepAliveConfiguration,
+    #[serde(default)]
+    pub nanoleaf_devices: Vec<NanoleafDeviceConfiguration>,
+    #[serde(default)]
+    pub wemo_devices: Vec<WemoDeviceConfiguration>,
+}
+
+/// connection details for a set of nanoleaf light panels. `name` matches the name of the
+/// `nanoleaf_light_panels` devices in the scene configuration.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct NanoleafDeviceConfiguration {
+    pub name: String,
+    #[serde(deserialize_with = "crate::config::serde_util::deserialize_host")]
+    pub host: Host<String>,
+    #[serde(default = "default_nanoleaf_port")]
+    pub port: u16,
+    pub auth_token: String,
+}
+
+fn default_nanoleaf_port() -> u16 {
+    DEFAULT_NANOLEAF_PORT
+}
+
+/// where to find a wemo outlet. `name` matches the name of the `wemo_outlet` devices in the
+/// scene configuration. wemo devices pick their port from 49152-49155, but tend to stick with one.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct WemoDeviceConfiguration {
+    pub name: String,
+    #[serde(deserialize_with = "crate::config::serde_util::deserialize_host")]
+    pub host: Host<String>,
+    #[serde(default = "default_wemo_port")]
+    pub port: u16,
+}
+
+fn default_wemo_port() -> u16 {
+    DEFAULT_WEMO_PORT
+}
+
+/// how long to wait between attempts to reconnect to the caseta hub. the delay doubles after
+/// every failed attempt until it reaches `max_delay_secs`.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ReconnectConfiguration {
+    pub initial_delay_millis: u64,
+    pub max_delay_secs: u64,
+}
+
+impl ReconnectConfiguration {
+    pub fn initial_delay(&self) -> Duration {
+        Duration::from_millis(self.initial_delay_millis)
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_secs(self.max_delay_secs)
+    }
+}
+
+impl Default for ReconnectConfiguration {
+    fn default() -> Self {
+        Self {
+            initial_delay_millis: DEFAULT_INITIAL_RECONNECT_DELAY_MILLIS,
+            max_delay_secs: DEFAULT_MAXIMUM_RECONNECT_DELAY_SECS,
+        }
+    }
+}
+
+/// how patient to be with the hue bridge. each request gets `timeout_millis`, and busy (429/503)
+/// responses and connection errors are retried up to `max_retries` times with backoff. after
+/// `circuit_breaker_failure_threshold` failed requests in a row, requests fail immediately for
+/// `circuit_breaker_reset_secs`. each room sends at most one grouped light command every
+/// `grouped_light_command_interval_millis`.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HueRequestConfiguration {
+    pub timeout_millis: u64,
+    pub max_retries: u32,
+    pub initial_retry_delay_millis: u64,
+    pub max_retry_delay_millis: u64,
+    pub circuit_breaker_failure_threshold: u32,
+    pub circuit_breaker_reset_secs: u64,
+    pub grouped_light_command_interval_millis: u64,
+}
+
+impl HueRequestConfiguration {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_millis)
+    }
+
+    pub fn initial_retry_delay(&self) -> Duration {
+        Duration::from_millis(self.initial_retry_delay_millis)
+    }
+
+    pub fn max_retry_delay(&self) -> Duration {
+        Duration::from_millis(self.max_retry_delay_millis)
+    }
+
+    pub fn circuit_breaker_reset(&self) -> Duration {
+        Duration::from_secs(self.circuit_breaker_reset_secs)
+    }
+
+    pub fn grouped_light_command_interval(&self) -> Duration {
+        Duration::from_millis(self.grouped_light_command_interval_millis)
+    }
+}
+
+impl Default for HueRequestConfiguration {
+    fn default() -> Self {
+        Self {
+            timeout_millis: DEFAULT_HUE_REQUEST_TIMEOUT_MILLIS,
+            max_retries: DEFAULT_HUE_MAXIMUM_RETRIES,
+            initial_retry_delay_millis: DEFAULT_HUE_INITIAL_RETRY_DELAY_MILLIS,
+            max_retry_delay_millis: DEFAULT_HUE_MAXIMUM_RETRY_DELAY_MILLIS,
+            circuit_breaker_failure_threshold: DEFAULT_HUE_CIRCUIT_BREAKER_FAILURE_THRESHOLD,
+            circuit_breaker_reset_secs: DEFAULT_HUE_CIRCUIT_BREAKER_RESET_SECS,
+            grouped_light_command_interval_millis:
+                DEFAULT_HUE_GROUPED_LIGHT_COMMAND_INTERVAL_MILLIS,
+        }
+    }
+}
+
+/// how to check the hue bridge's certificate. it has to chain to the hue root CA and be issued
+/// to `bridge_id` (the bridge's id, like `001788fffe6a1b2c`). `certificate_fingerprint` pins the
+/// bridge's exact certificate on top of that. `accept_invalid_certificates` turns verification off
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct HueTlsConfiguration {
+    pub bridge_id: Option<String>,
+    pub certificate_fingerprint: Option<String>,
+    pub accept_invalid_certificates: bool,
+}
+
+/// where to serve the http control api. it's off unless `port` is set, and only listens on
+/// loopback unless `bind_address` says otherwise
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HttpApiConfiguration {
+    pub port: Option<u16>,
+    pub bind_address: IpAddr,
+}
+
+impl HttpApiConfiguration {
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.port
+            .map(|port| SocketAddr::new(self.bind_address, port))
+    }
+}
+
+impl Default for HttpApiConfiguration {
+    fn default() -> Self {
+        Self {
+            port: None,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+/// when the health checks give up on the listener. `/healthz` fails once the caseta connection
+/// loop has been quiet for `liveness_timeout_secs` (it wakes up for every keep alive). `/readyz`
+/// also fails when the hub hasn't sent anything (a keep alive answer or any other message) in
+/// `keep_alive_max_age_secs`, or the hue bridge doesn't answer within `hue_timeout_millis`
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HealthCheckConfiguration {
+    pub liveness_timeout_secs: u64,
+    pub keep_alive_max_age_secs: u64,
+    pub hue_timeout_millis: u64,
+}
+
+impl HealthCheckConfiguration {
+    pub fn liveness_timeout(&self) -> Duration {
+        Duration::from_secs(self.liveness_timeout_secs)
+    }
+
+    pub fn keep_alive_max_age(&self) -> Duration {
+        Duration::from_secs(self.keep_alive_max_age_secs)
+    }
+
+    pub fn hue_timeout(&self) -> Duration {
+        Duration::from_millis(self.hue_timeout_millis)
+    }
+}
+
+impl Default for HealthCheckConfiguration {
+    fn default() -> Self {
+        Self {
+            liveness_timeout_secs: DEFAULT_LIVENESS_TIMEOUT_SECS,
+            keep_alive_max_age_secs: DEFAULT_KEEP_ALIVE_MAXIMUM_AGE_SECS,
+            hue_timeout_millis: DEFAULT_HEALTH_CHECK_HUE_TIMEOUT_MILLIS,
+        }
+    }
+}
+
+/// where to find the mqtt broker that caseta events are shared with (home assistant, node-red).
+/// `username` and `password` are only sent when both are set.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct MqttConfiguration {
+    #[serde(deserialize_with = "crate::config::serde_util::deserialize_host")]
+    pub host: Host<String>,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_mqtt_keep_alive_secs")]
+    pub keep_alive_secs: u64,
+    #[serde(default)]
+    pub topics: MqttTopicConfiguration,
+}
+
+impl MqttConfiguration {
+    pub fn keep_alive(&self) -> Duration {
+        Duration::from_secs(self.keep_alive_secs)
+    }
+}
+
+fn default_mqtt_port() -> u16 {
+    DEFAULT_MQTT_PORT
+}
+
+fn default_mqtt_client_id() -> String {
+    DEFAULT_MQTT_CLIENT_ID.to_string()
+}
+
+fn default_mqtt_keep_alive_secs() -> u64 {
+    DEFAULT_MQTT_KEEP_ALIVE_SECS
+}
+
+/// raw button events go to `button_events/{remote id}/{button}` and classified presses go to
+/// `device_actions/{remote id}/{button}`. room actions published to `commands` are carried out,
+/// and `availability` is `online` while the listener is connected to the broker
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MqttTopicConfiguration {
+    pub button_events: String,
+    pub device_actions: String,
+    pub commands: String,
+    pub availability: String,
+}
+
+impl Default for MqttTopicConfiguration {
+    fn default() -> Self {
+        Self {
+            button_events: "caseta_listener/button_events".to_string(),
+            device_actions: "caseta_listener/device_actions".to_string(),
+            commands: "caseta_listener/commands".to_string(),
+            availability: "caseta_listener/availability".to_string(),
+        }
+    }
 }
 
 pub fn get_auth_configuration() -> Result<AuthConfiguration, config::ConfigError> {
+    configuration_sources().build().unwrap().try_deserialize()
+}
+
+/// just the http api settings, for commands that don't need (or have) the rest of the configuration
+pub fn get_http_api_configuration() -> Result<HttpApiConfiguration, config::ConfigError> {
+    match configuration_sources().build()?.get("http_api") {
+        Err(config::ConfigError::NotFound(_)) => Ok(HttpApiConfiguration::default()),
+        http_api_configuration => http_api_configuration,
+    }
+}
+
+fn configuration_sources() -> ConfigBuilder<DefaultState> {
     let mut settings = config::Config::builder();
 
-    match env::var(AUTH_CONFIGURATION_FILE_NAME_ENV_VAR) {
-        Ok(filename) => {
-            settings = settings.add_source(config::File::with_name(filename.as_str()));
-        }
-        Err(..) => {} // no-op. don't try to add a file without an env var pointing to it
+    // don't try to add a file without an env var pointing to it
+    if let Ok(filename) = env::var(AUTH_CONFIGURATION_FILE_NAME_ENV_VAR) {
+        settings = settings.add_source(config::File::with_name(filename.as_str()));
+    }
+
+    if let Ok(filename) = env::var(NON_SENSITIVE_CONFIGURATION_FILE_NAME_ENV_VAR) {
+        settings = settings.add_source(config::File::with_name(filename.as_str()));
+    }
+
+    settings.add_source(environment())
+}
+
+// a double underscore reaches into a section, so `CASETA_LISTENER_HTTP_API__PORT` sets
+// `http_api.port` while `CASETA_LISTENER_HUE_APPLICATION_KEY` is still `hue_application_key`
+fn environment() -> config::Environment {
+    config::Environment::with_prefix(CASETA_LISTENER_ENV_VAR_PREFIX)
+        .prefix_separator("_")
+        .separator("__")
+}
+
+/// how often to poke an idle caseta connection, and how long to wait for the hub to answer
+/// before deciding the connection is dead.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KeepAliveConfiguration {
+    pub interval_secs: u64,
+    pub response_timeout_secs: u64,
+}
+
+impl KeepAliveConfiguration {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
     }
 
-    match env::var(NON_SENSITIVE_CONFIGURATION_FILE_NAME_ENV_VAR) {
-        Ok(filename) => {
-            settings = settings.add_source(config::File::with_name(filename.as_str()));
+    pub fn response_timeout(&self) -> Duration {
+        Duration::from_secs(self.response_timeout_secs)
+    }
+}
+
+impl Default for KeepAliveConfiguration {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_KEEP_ALIVE_INTERVAL_SECS,
+            response_timeout_secs: DEFAULT_KEEP_ALIVE_RESPONSE_TIMEOUT_SECS,
         }
-        Err(..) => {} // no-op. don't try to add a file without an env var pointing to it
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::config::auth_configuration::{environment, HttpApiConfiguration};
 
-    settings = settings.add_source(config::Environment::with_prefix(
-        CASETA_LISTENER_ENV_VAR_PREFIX,
-    ));
-    settings.build().unwrap().try_deserialize()
+    #[test]
+    fn it_reads_nested_settings_from_the_environment() {
+        let variables = [
+            ("CASETA_LISTENER_HTTP_API__PORT", "8080"),
+            ("CASETA_LISTENER_HUE_APPLICATION_KEY", "application-key"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let configuration = config::Config::builder()
+            .add_source(environment().source(Some(variables)))
+            .build()
+            .unwrap();
+
+        let http_api: HttpApiConfiguration = configuration.get("http_api").unwrap();
+
+        assert_that(&http_api.port).is_equal_to(Some(8080));
+        assert_that(&configuration.get_string("hue_application_key").unwrap())
+            .is_equal_to("application-key".to_string());
+    }
 }
@@ -0,0 +1,352 @@
+use serde_derive::Deserialize;
+
+use crate::client::dispatcher::DeviceAction;
+use crate::config::caseta_remote::{ButtonId, CasetaRemote, RemoteId};
+
+/// what to do to a room when a binding matches
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomAction {
+    /// turn the room on to its last scene, or its first scene if we don't know the last one
+    TurnOn,
+    TurnOff,
+    Toggle,
+    BrightnessUp {
+        #[serde(default = "default_brightness_steps")]
+        steps: u8,
+    },
+    BrightnessDown {
+        #[serde(default = "default_brightness_steps")]
+        steps: u8,
+    },
+    /// start the bridge smoothly raising brightness until it's stopped or reaches 100%
+    RampBrightnessUp,
+    RampBrightnessDown,
+    StopBrightnessRamp,
+    NextScene,
+    PreviousScene,
+    FirstScene,
+    /// switch to the named scene, turning the room on if it's off
+    RecallScene {
+        scene: String,
+    },
+    DoNothing,
+}
+
+fn default_brightness_steps() -> u8 {
+    1
+}
+
+/// which room states a binding applies to
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomCondition {
+    #[default]
+    Any,
+    RoomOn,
+    RoomOff,
+}
+
+impl RoomCondition {
+    fn matches(&self, room_on: bool) -> bool {
+        match self {
+            RoomCondition::Any => true,
+            RoomCondition::RoomOn => room_on,
+            RoomCondition::RoomOff => !room_on,
+        }
+    }
+}
+
+/// maps a button and press type to a [`RoomAction`], e.g.
+///
+/// ```yaml
+/// - button: favorite
+///   press: double_press_complete
+///   when: room_on
+///   remote: 3
+///   action:
+///     type: recall_scene
+///     scene: movie_night
+/// ```
+///
+/// `when` defaults to `any`, and a binding without a `remote` applies to every remote in the room.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ButtonBinding {
+    #[serde(default)]
+    pub remote: Option<RemoteId>,
+    pub button: ButtonId,
+    pub press: DeviceAction,
+    #[serde(default)]
+    pub when: RoomCondition,
+    pub action: RoomAction,
+}
+
+impl ButtonBinding {
+    pub fn new(
+        button: ButtonId,
+        press: DeviceAction,
+        when: RoomCondition,
+        action: RoomAction,
+    ) -> Self {
+        Self {
+            remote: None,
+            button,
+            press,
+            when,
+            action,
+        }
+    }
+
+    pub fn matches(
+        &self,
+        remote: &CasetaRemote,
+        button: ButtonId,
+        press: DeviceAction,
+        room_on: bool,
+    ) -> bool {
+        // Option::is_none_or needs rust 1.82, which is newer than the rest of the crate needs
+        let remote_matches = match self.remote {
+            Some(remote_id) => remote_id == remote.id(),
+            None => true,
+        };
+        remote_matches && self.button == button && self.press == press && self.when.matches(room_on)
+    }
+}
+
+/// Finds what a press should do. The room's configured bindings are checked in order before the
+/// remote's default profile, and the first match wins. Presses that nothing matches are ignored.
+pub fn find_action(
+    room_bindings: &[ButtonBinding],
+    remote: &CasetaRemote,
+    button: ButtonId,
+    press: DeviceAction,
+    room_on: bool,
+) -> Option<RoomAction> {
+    let matching_action = |bindings: &[ButtonBinding]| {
+        bindings
+            .iter()
+            .find(|binding| binding.matches(remote, button, press, room_on))
+            .map(|binding| binding.action.clone())
+    };
+    matching_action(room_bindings).or_else(|| matching_action(&default_bindings(remote)))
+}
+
+/// the bindings a remote gets when nothing in the scene configuration overrides them
+pub fn default_bindings(remote: &CasetaRemote) -> Vec<ButtonBinding> {
+    match remote {
+        CasetaRemote::FiveButtonPico { .. } => five_button_pico_bindings(),
+        CasetaRemote::TwoButtonPico { .. } => two_button_pico_bindings(),
+    }
+}
+
+fn five_button_pico_bindings() -> Vec<ButtonBinding> {
+    use DeviceAction::*;
+    use RoomAction::*;
+    use RoomCondition::*;
+
+    let mut bindings = vec![
+        ButtonBinding::new(ButtonId::PowerOn, SinglePressComplete, Any, TurnOn),
+        // a single press of any button turns an off room on
+        ButtonBinding::new(ButtonId::PowerOff, SinglePressComplete, RoomOff, TurnOn),
+        ButtonBinding::new(ButtonId::Up, SinglePressComplete, RoomOff, TurnOn),
+        ButtonBinding::new(ButtonId::Down, SinglePressComplete, RoomOff, TurnOn),
+        ButtonBinding::new(ButtonId::Favorite, SinglePressComplete, RoomOff, TurnOn),
+        ButtonBinding::new(ButtonId::Favorite, SinglePressComplete, RoomOn, NextScene),
+        ButtonBinding::new(
+            ButtonId::Favorite,
+            DoublePressComplete,
+            RoomOn,
+            PreviousScene,
+        ),
+        ButtonBinding::new(ButtonId::Favorite, LongPressComplete, RoomOn, FirstScene),
+    ];
+    for press in [SinglePressComplete, DoublePressComplete, LongPressComplete] {
+        bindings.push(ButtonBinding::new(ButtonId::PowerOff, press, Any, TurnOff));
+    }
+    bindings.extend(brightness_bindings(
+        ButtonId::Up,
+        |steps| BrightnessUp { steps },
+        RampBrightnessUp,
+    ));
+    bindings.extend(brightness_bindings(
+        ButtonId::Down,
+        |steps| BrightnessDown { steps },
+        RampBrightnessDown,
+    ));
+    bindings
+}
+
+// a double press moves twice as far as a single press, and holding the button ramps brightness
+// until it's released
+fn brightness_bindings(
+    button: ButtonId,
+    step: fn(u8) -> RoomAction,
+    ramp: RoomAction,
+) -> Vec<ButtonBinding> {
+    use DeviceAction::*;
+    use RoomCondition::*;
+
+    vec![
+        ButtonBinding::new(button, SinglePressComplete, RoomOn, step(1)),
+        ButtonBinding::new(button, DoublePressComplete, RoomOn, step(2)),
+        ButtonBinding::new(button, LongPressStart, RoomOn, ramp),
+        ButtonBinding::new(
+            button,
+            LongPressComplete,
+            RoomOn,
+            RoomAction::StopBrightnessRamp,
+        ),
+    ]
+}
+
+fn two_button_pico_bindings() -> Vec<ButtonBinding> {
+    use DeviceAction::*;
+    use RoomAction::*;
+    use RoomCondition::*;
+
+    // there's no other way to turn the room on from a two button pico, so the off button does
+    // nothing while the room is off
+    vec![
+        ButtonBinding::new(ButtonId::PowerOn, SinglePressComplete, RoomOff, TurnOn),
+        ButtonBinding::new(ButtonId::PowerOn, DoublePressComplete, RoomOff, TurnOn),
+        ButtonBinding::new(ButtonId::PowerOn, DoublePressComplete, RoomOn, NextScene),
+        ButtonBinding::new(ButtonId::PowerOn, LongPressStart, RoomOn, RampBrightnessUp),
+        ButtonBinding::new(
+            ButtonId::PowerOn,
+            LongPressComplete,
+            RoomOn,
+            StopBrightnessRamp,
+        ),
+        ButtonBinding::new(ButtonId::PowerOff, SinglePressComplete, RoomOn, TurnOff),
+        ButtonBinding::new(
+            ButtonId::PowerOff,
+            DoublePressComplete,
+            RoomOn,
+            PreviousScene,
+        ),
+        ButtonBinding::new(
+            ButtonId::PowerOff,
+            LongPressStart,
+            RoomOn,
+            RampBrightnessDown,
+        ),
+        ButtonBinding::new(
+            ButtonId::PowerOff,
+            LongPressComplete,
+            RoomOn,
+            StopBrightnessRamp,
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use spectral::prelude::*;
+
+    use crate::client::dispatcher::DeviceAction;
+    use crate::config::bindings::{find_action, ButtonBinding, RoomAction, RoomCondition};
+    use crate::config::caseta_remote::{ButtonId, CasetaRemote};
+
+    fn five_button_pico(id: u8) -> CasetaRemote {
+        CasetaRemote::FiveButtonPico {
+            id,
+            name: String::from("Office Pico"),
+        }
+    }
+
+    #[test]
+    fn it_deserializes_bindings() {
+        let bindings_text = r#"
+            - button: favorite
+              press: double_press_complete
+              when: room_on
+              remote: 3
+              action:
+                type: recall_scene
+                scene: movie_night
+            - button: up
+              press: single_press_complete
+              action:
+                type: brightness_up
+        "#;
+
+        let bindings: Vec<ButtonBinding> =
+            serde_yaml::from_str(bindings_text).expect("unable to deserialize bindings");
+
+        assert_that(&bindings).has_length(2);
+        assert_that(&bindings[0].remote).is_equal_to(Some(3));
+        assert_that(&bindings[0].when).is_equal_to(RoomCondition::RoomOn);
+        assert_that(&bindings[0].action).is_equal_to(RoomAction::RecallScene {
+            scene: String::from("movie_night"),
+        });
+        assert_that(&bindings[1].when).is_equal_to(RoomCondition::Any);
+        assert_that(&bindings[1].action).is_equal_to(RoomAction::BrightnessUp { steps: 1 });
+    }
+
+    #[test]
+    fn it_falls_back_to_the_default_profile() {
+        let remote = five_button_pico(2);
+
+        let favorite = |room_on| {
+            find_action(
+                &[],
+                &remote,
+                ButtonId::Favorite,
+                DeviceAction::SinglePressComplete,
+                room_on,
+            )
+        };
+        let two_button_off = find_action(
+            &[],
+            &CasetaRemote::TwoButtonPico {
+                id: 4,
+                name: String::from("Fireplace Pico"),
+            },
+            ButtonId::PowerOff,
+            DeviceAction::SinglePressComplete,
+            false,
+        );
+
+        let hold_up = find_action(
+            &[],
+            &remote,
+            ButtonId::Up,
+            DeviceAction::LongPressStart,
+            true,
+        );
+
+        assert_that(&favorite(false)).is_equal_to(Some(RoomAction::TurnOn));
+        assert_that(&hold_up).is_equal_to(Some(RoomAction::RampBrightnessUp));
+        assert_that(&favorite(true)).is_equal_to(Some(RoomAction::NextScene));
+        assert_that(&two_button_off).is_none();
+    }
+
+    #[test]
+    fn it_prefers_configured_bindings_for_the_matching_remote() {
+        let mut movie_night = ButtonBinding::new(
+            ButtonId::Favorite,
+            DeviceAction::SinglePressComplete,
+            RoomCondition::Any,
+            RoomAction::RecallScene {
+                scene: String::from("movie_night"),
+            },
+        );
+        movie_night.remote = Some(3);
+        let bindings = vec![movie_night];
+
+        let press = |remote_id| {
+            find_action(
+                &bindings,
+                &five_button_pico(remote_id),
+                ButtonId::Favorite,
+                DeviceAction::SinglePressComplete,
+                true,
+            )
+        };
+
+        assert_that(&press(3)).is_equal_to(Some(RoomAction::RecallScene {
+            scene: String::from("movie_night"),
+        }));
+        assert_that(&press(2)).is_equal_to(Some(RoomAction::NextScene));
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::anyhow;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::env;
 use std::fmt::{Display, Formatter};
 
@@ -8,7 +8,8 @@ const DEFAULT_CASETA_REMOTE_CONFIGURATION_FILE_NAME: &str = "caseta_remote_confi
 
 pub type RemoteId = u8;
 
-#[derive(Debug, Hash, Eq, PartialEq, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, Hash, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum ButtonId {
     PowerOn,
     Up,
@@ -23,6 +24,11 @@ impl Display for ButtonId {
     }
 }
 
+// component numbers from the pico table in lutron's integration protocol guide (document
+// 040249): a five button pico (PJ2-3BRL) reports on=2, favorite=3, off=4, raise=5 and lower=6,
+// and a two button pico (PJ2-2B) reports on=2 and off=4. the two layouts only differ in the LEAP
+// api, which numbers buttons from 0 (0 and 2 on a two button pico). the hub's telnet integration
+// uses the component numbers, so one mapping covers both remote types.
 impl TryFrom<u8> for ButtonId {
     type Error = anyhow::Error;
 
@@ -38,7 +44,8 @@ impl TryFrom<u8> for ButtonId {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Serialize, Debug, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum ButtonAction {
     Press,
     Release,
@@ -61,23 +68,44 @@ impl TryFrom<u8> for ButtonAction {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum CasetaRemote {
     TwoButtonPico { id: RemoteId, name: String },
     FiveButtonPico { id: RemoteId, name: String },
 }
 
+impl CasetaRemote {
+    pub fn id(&self) -> RemoteId {
+        match self {
+            CasetaRemote::TwoButtonPico { id, .. } | CasetaRemote::FiveButtonPico { id, .. } => *id,
+        }
+    }
+
+    pub fn has_button(&self, button_id: &ButtonId) -> bool {
+        match self {
+            CasetaRemote::TwoButtonPico { .. } => {
+                matches!(button_id, ButtonId::PowerOn | ButtonId::PowerOff)
+            }
+            CasetaRemote::FiveButtonPico { .. } => true,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct RemoteConfiguration {
     pub remotes: Vec<CasetaRemote>,
 }
 
-pub fn get_caseta_remote_configuration() -> Result<RemoteConfiguration, config::ConfigError> {
-    let configuration_file_name = match env::var(CASETA_REMOTE_CONFIG_FILE_NAME_ENV_VAR) {
+pub fn remote_configuration_file_name() -> String {
+    match env::var(CASETA_REMOTE_CONFIG_FILE_NAME_ENV_VAR) {
         Ok(filename) => filename,
         _ => String::from(DEFAULT_CASETA_REMOTE_CONFIGURATION_FILE_NAME),
-    };
+    }
+}
+
+pub fn get_caseta_remote_configuration() -> Result<RemoteConfiguration, config::ConfigError> {
+    let configuration_file_name = remote_configuration_file_name();
 
     let settings = config::Config::builder()
         .add_source(config::File::with_name(configuration_file_name.as_str()))
@@ -88,7 +116,7 @@ pub fn get_caseta_remote_configuration() -> Result<RemoteConfiguration, config::
 
 #[cfg(test)]
 mod tests {
-    use crate::config::caseta_remote::{CasetaRemote, RemoteConfiguration};
+    use crate::config::caseta_remote::{ButtonId, CasetaRemote, RemoteConfiguration};
     use spectral::assert_that;
     use spectral::prelude::*;
 
@@ -118,4 +146,27 @@ mod tests {
             CasetaRemote::TwoButtonPico { .. }
         ));
     }
+
+    #[test]
+    fn it_converts_two_button_pico_button_ids() {
+        let two_button_pico = CasetaRemote::TwoButtonPico {
+            id: 3,
+            name: String::from("Fireplace Pico"),
+        };
+
+        let power_on = ButtonId::try_from(2).expect("2 should be a valid button id");
+        let power_off = ButtonId::try_from(4).expect("4 should be a valid button id");
+
+        assert_that(&power_on).is_equal_to(ButtonId::PowerOn);
+        assert_that(&power_off).is_equal_to(ButtonId::PowerOff);
+        assert!(two_button_pico.has_button(&power_on));
+        assert!(two_button_pico.has_button(&power_off));
+        assert!(!two_button_pico.has_button(&ButtonId::Favorite));
+    }
+
+    #[test]
+    fn it_rejects_unknown_button_ids() {
+        assert!(ButtonId::try_from(1).is_err());
+        assert!(ButtonId::try_from(7).is_err());
+    }
 }
@@ -0,0 +1,133 @@
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde_yaml::{Mapping, Value};
+
+// the file holds the hue application key, so only its owner should be able to read it
+const NEW_FILE_MODE: u32 = 0o600;
+
+/// Changes a yaml configuration file in place, creating it if it doesn't exist yet. Settings
+/// `update` doesn't touch are kept, but comments and formatting are not. Nothing is written if
+/// `update` fails, and the new contents replace the file in one rename so a crash can't leave it
+/// half written.
+pub fn update_yaml_file<F>(path: &Path, update: F) -> Result<()>
+where
+    F: FnOnce(&mut Mapping) -> Result<()>,
+{
+    let mut configuration = match fs::read_to_string(path) {
+        Ok(contents) if contents.trim().is_empty() => Mapping::new(),
+        Ok(contents) => match serde_yaml::from_str(&contents)
+            .with_context(|| format!("unable to parse {}", path.display()))?
+        {
+            Value::Mapping(mapping) => mapping,
+            _ => return Err(anyhow!("{} isn't a yaml mapping", path.display())),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Mapping::new(),
+        Err(e) => return Err(e).with_context(|| format!("unable to read {}", path.display())),
+    };
+
+    update(&mut configuration)?;
+
+    let contents = serde_yaml::to_string(&configuration)?;
+    let mode = match fs::metadata(path) {
+        Ok(metadata) => metadata.permissions().mode(),
+        Err(e) if e.kind() == ErrorKind::NotFound => NEW_FILE_MODE,
+        Err(e) => return Err(e).with_context(|| format!("unable to read {}", path.display())),
+    };
+    let temp_path = temp_path(path);
+    write_file(&temp_path, contents.as_bytes(), mode)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })
+        .with_context(|| format!("unable to write {}", path.display()))
+}
+
+// a hidden file next to `path`, so the rename stays on one filesystem
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", file_name, fastrand::u64(..)))
+}
+
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)?;
+    // the umask may have taken bits off of `mode`
+    file.set_permissions(Permissions::from_mode(mode))?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    use serde_yaml::Value;
+    use spectral::prelude::*;
+
+    use crate::config::config_file::update_yaml_file;
+
+    #[test]
+    fn it_keeps_existing_settings() {
+        let path = std::env::temp_dir().join(format!("caseta_listener_{}.yaml", fastrand::u64(..)));
+        fs::write(&path, "caseta_port: 8081\nhue_application_key: old\n").unwrap();
+
+        update_yaml_file(&path, |configuration| {
+            configuration.insert(Value::from("hue_application_key"), Value::from("new"));
+            Ok(())
+        })
+        .unwrap();
+        let contents: Value = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_that(&contents["caseta_port"]).is_equal_to(Value::from(8081));
+        assert_that(&contents["hue_application_key"]).is_equal_to(Value::from("new"));
+    }
+
+    #[test]
+    fn it_creates_new_files_readable_only_by_their_owner() {
+        let path = std::env::temp_dir().join(format!("caseta_listener_{}.yaml", fastrand::u64(..)));
+
+        update_yaml_file(&path, |configuration| {
+            configuration.insert(Value::from("hue_application_key"), Value::from("new"));
+            Ok(())
+        })
+        .unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        fs::remove_file(&path).unwrap();
+
+        assert_that(&mode).is_equal_to(0o600);
+    }
+
+    #[test]
+    fn it_keeps_the_mode_of_existing_files() {
+        let directory = std::env::temp_dir().join(format!("caseta_listener_{}", fastrand::u64(..)));
+        fs::create_dir(&directory).unwrap();
+        let path = directory.join("config.yaml");
+        fs::write(&path, "caseta_port: 8081\n").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+
+        update_yaml_file(&path, |configuration| {
+            configuration.insert(Value::from("hue_application_key"), Value::from("new"));
+            Ok(())
+        })
+        .unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        let files = fs::read_dir(&directory).unwrap().count();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_that(&mode).is_equal_to(0o640);
+        // the temp file was renamed over the original
+        assert_that(&files).is_equal_to(1);
+    }
+}
@@ -1,4 +1,8 @@
 pub mod auth_configuration;
+pub mod bindings;
 pub mod caseta_remote;
+pub mod config_file;
+pub mod reload;
 pub mod scene;
 mod serde_util;
+pub mod validation;
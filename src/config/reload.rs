@@ -0,0 +1,200 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{error, info, instrument, warn};
+
+use crate::config::caseta_remote::{
+    get_caseta_remote_configuration, remote_configuration_file_name,
+};
+use crate::config::scene::{
+    build_topology, get_raw_room_configurations, scene_configuration_file_name, SharedTopology,
+    Topology,
+};
+use crate::config::validation::validate_topology;
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+// the config crate finds files without their extension, so we have to look for them the same way
+const CONFIGURATION_FILE_EXTENSIONS: [&str; 2] = ["yaml", "yml"];
+
+/// reads and validates the remote and scene configuration files
+pub fn load_topology() -> Result<Topology> {
+    let remote_configuration = get_caseta_remote_configuration()?;
+    let home_configuration =
+        validate_topology(&remote_configuration, get_raw_room_configurations()?)?;
+    Ok(build_topology(&remote_configuration, &home_configuration))
+}
+
+/// the remote and scene configuration files, wherever the config crate would find them
+pub fn topology_files() -> Vec<PathBuf> {
+    [
+        remote_configuration_file_name(),
+        scene_configuration_file_name(),
+    ]
+    .iter()
+    .map(|name| configuration_file_path(name))
+    .collect()
+}
+
+fn configuration_file_path(name: &str) -> PathBuf {
+    let path = PathBuf::from(name);
+    if path.exists() {
+        return path;
+    }
+    CONFIGURATION_FILE_EXTENSIONS
+        .iter()
+        .map(|extension| PathBuf::from(format!("{}.{}", name, extension)))
+        .find(|path| path.exists())
+        .unwrap_or(path)
+}
+
+/// Reloads the topology whenever one of `watched_files` changes or the process gets a SIGHUP.
+///
+/// `load_topology` is only swapped in once it succeeds. When it fails, the current topology stays
+/// in place and the reason gets logged.
+#[instrument(skip(shared_topology, load_topology))]
+pub async fn topology_reload_loop<F>(
+    shared_topology: SharedTopology,
+    watched_files: Vec<PathBuf>,
+    poll_interval: Duration,
+    load_topology: F,
+) where
+    F: Fn() -> Result<Topology>,
+{
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            warn!(error=%e, "unable to listen for SIGHUP. only file changes will reload the configuration");
+            None
+        }
+    };
+    let mut last_modified = modification_times(&watched_files);
+    let mut poll = time::interval(poll_interval);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let reason = tokio::select! {
+            _ = poll.tick() => {
+                let modified = modification_times(&watched_files);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                "a configuration file changed"
+            }
+            _ = next_hangup(&mut hangup) => "got a SIGHUP",
+        };
+
+        match load_topology() {
+            Ok(topology) => {
+                info!(
+                    remotes = topology.len(),
+                    "reloaded the remote and scene configuration because {}", reason
+                );
+                shared_topology.replace(topology);
+            }
+            Err(e) => error!(
+                "{}, but the new remote and scene configuration has problems, so we're keeping the current one: {:#}",
+                reason, e
+            ),
+        }
+    }
+}
+
+async fn next_hangup(hangup: &mut Option<Signal>) {
+    match hangup {
+        Some(hangup) => {
+            hangup.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|file| modified(file)).collect()
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    use anyhow::anyhow;
+    use spectral::prelude::*;
+
+    use crate::config::caseta_remote::CasetaRemote;
+    use crate::config::reload::topology_reload_loop;
+    use crate::config::scene::{Room, SharedTopology, Topology};
+
+    fn topology(remote_ids: &[u8]) -> Topology {
+        remote_ids
+            .iter()
+            .map(|id| {
+                let remote = CasetaRemote::TwoButtonPico {
+                    id: *id,
+                    name: format!("pico {}", id),
+                };
+                let room = Room {
+                    name: "Office".to_string(),
+                    room_id: uuid::Uuid::new_v4(),
+                    grouped_light_room_id: uuid::Uuid::new_v4(),
+                    scenes: vec![],
+                    remotes: vec![*id],
+                    bindings: vec![],
+                };
+                (*id, (remote, room))
+            })
+            .collect::<HashMap<_, _>>()
+    }
+
+    #[tokio::test]
+    async fn it_swaps_in_valid_configuration_and_keeps_the_old_one_otherwise() {
+        let path = std::env::temp_dir().join(format!("caseta_listener_{}.yaml", fastrand::u64(..)));
+        fs::write(&path, "2").unwrap();
+        let shared_topology = SharedTopology::new(topology(&[2]));
+        let watched_file = path.clone();
+        let reload = tokio::spawn(topology_reload_loop(
+            shared_topology.clone(),
+            vec![path.clone()],
+            Duration::from_millis(10),
+            move || {
+                let contents = fs::read_to_string(&watched_file)?;
+                let remote_id = contents
+                    .trim()
+                    .parse::<u8>()
+                    .map_err(|_| anyhow!("{} isn't a remote id", contents))?;
+                Ok(topology(&[remote_id]))
+            },
+        ));
+        let touch = |contents: &str, seconds_later: u64| {
+            fs::write(&path, contents).unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() + Duration::from_secs(seconds_later))
+                .unwrap();
+        };
+        // let the loop note the starting modification time
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        touch("3", 1);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let after_valid_edit: Vec<u8> = shared_topology.current().keys().copied().collect();
+        touch("not a remote", 2);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let after_invalid_edit: Vec<u8> = shared_topology.current().keys().copied().collect();
+        reload.abort();
+        fs::remove_file(&path).unwrap();
+
+        assert_that(&after_valid_edit).is_equal_to(vec![3]);
+        assert_that(&after_invalid_edit).is_equal_to(vec![3]);
+    }
+}
@@ -1,7 +1,10 @@
-use crate::config::caseta_remote::{CasetaRemote, RemoteId};
+use crate::caseta::message::IntegrationId;
+use crate::config::bindings::ButtonBinding;
+use crate::config::caseta_remote::{CasetaRemote, RemoteConfiguration, RemoteId};
 use config::{Config, ConfigError};
 use std::collections::HashMap;
 use std::env;
+use std::sync::{Arc, RwLock};
 
 use serde_derive::Deserialize;
 use uuid::Uuid;
@@ -11,6 +14,58 @@ const DEFAULT_SCENE_CONFIGURATION_FILE_NAME: &str = "caseta_listener_scenes.yaml
 
 pub type Topology = HashMap<RemoteId, (CasetaRemote, Room)>;
 
+/// The topology everything is currently working from. Reloading the configuration swaps in a new
+/// one for every clone at once, and anyone partway through handling an event keeps the
+/// [`Topology`] they started with.
+#[derive(Debug, Clone, Default)]
+pub struct SharedTopology(Arc<RwLock<Arc<Topology>>>);
+
+impl SharedTopology {
+    pub fn new(topology: Topology) -> Self {
+        SharedTopology(Arc::new(RwLock::new(Arc::new(topology))))
+    }
+
+    pub fn current(&self) -> Arc<Topology> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, topology: Topology) {
+        *self.0.write().unwrap() = Arc::new(topology);
+    }
+
+    /// finds a room with at least one remote by name, ignoring case
+    pub fn room_named(&self, name: &str) -> Option<Room> {
+        self.current()
+            .values()
+            .map(|(_remote, room)| room)
+            .find(|room| room.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+}
+
+/// maps each remote to its room. `validate_topology` should have already made sure every remote
+/// exists and is only in one room
+pub fn build_topology(
+    caseta_remote_configuration: &RemoteConfiguration,
+    home_configuration: &HomeConfiguration,
+) -> Topology {
+    let remotes_by_remote_id: HashMap<RemoteId, &CasetaRemote> = caseta_remote_configuration
+        .remotes
+        .iter()
+        .map(|remote| (remote.id(), remote))
+        .collect();
+
+    let mut topology: Topology = HashMap::new();
+    for room in home_configuration.rooms.iter() {
+        for remote_id in room.remotes.iter() {
+            if let Some(remote) = remotes_by_remote_id.get(remote_id) {
+                topology.insert(*remote_id, ((*remote).clone(), room.clone()));
+            }
+        }
+    }
+    topology
+}
+
 #[derive(Deserialize, Debug)]
 pub struct HomeConfiguration {
     pub rooms: Vec<Room>,
@@ -23,6 +78,23 @@ pub struct Room {
     pub grouped_light_room_id: Uuid,
     pub scenes: Vec<Scene>,
     pub remotes: Vec<RemoteId>,
+    /// overrides for the remotes' default button bindings
+    #[serde(default)]
+    pub bindings: Vec<ButtonBinding>,
+}
+
+impl Room {
+    /// every device that shows up in any of the room's scenes, once each. hue scenes all drive the
+    /// room's grouped light, so they count as a single device.
+    pub fn devices(&self) -> Vec<&Device> {
+        let mut devices: Vec<&Device> = Vec::new();
+        for device in self.scenes.iter().flat_map(|scene| scene.devices.iter()) {
+            if !devices.iter().any(|seen| seen.is_same_device(device)) {
+                devices.push(device);
+            }
+        }
+        devices
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -47,18 +119,93 @@ pub enum Device {
         name: String,
         on: bool,
     },
+    CasetaDimmer {
+        id: IntegrationId,
+        name: String,
+        level: f32,
+    },
 }
-pub fn get_room_configurations() -> Result<HomeConfiguration, ConfigError> {
-    let configuration_file_name = match env::var(SCENE_CONFIGURATION_FILE_NAME_ENV_VAR) {
+
+/// the `type` tags [`Device`] understands
+pub const DEVICE_TYPES: [&str; 4] = [
+    "hue_scene",
+    "nanoleaf_light_panels",
+    "wemo_outlet",
+    "caseta_dimmer",
+];
+
+/// which driver handles a [`Device`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    HueScene,
+    NanoleafLightPanels,
+    WemoOutlet,
+    CasetaDimmer,
+}
+
+impl Device {
+    pub fn kind(&self) -> DeviceKind {
+        match self {
+            Device::HueScene { .. } => DeviceKind::HueScene,
+            Device::NanoleafLightPanels { .. } => DeviceKind::NanoleafLightPanels,
+            Device::WemoOutlet { .. } => DeviceKind::WemoOutlet,
+            Device::CasetaDimmer { .. } => DeviceKind::CasetaDimmer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Device::HueScene { name, .. }
+            | Device::NanoleafLightPanels { name, .. }
+            | Device::WemoOutlet { name, .. }
+            | Device::CasetaDimmer { name, .. } => name,
+        }
+    }
+
+    fn is_same_device(&self, other: &Device) -> bool {
+        match (self, other) {
+            (Device::HueScene { .. }, Device::HueScene { .. }) => true,
+            (
+                Device::NanoleafLightPanels { name, .. },
+                Device::NanoleafLightPanels {
+                    name: other_name, ..
+                },
+            )
+            | (
+                Device::WemoOutlet { name, .. },
+                Device::WemoOutlet {
+                    name: other_name, ..
+                },
+            ) => name == other_name,
+            (Device::CasetaDimmer { id, .. }, Device::CasetaDimmer { id: other_id, .. }) => {
+                id == other_id
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn scene_configuration_file_name() -> String {
+    match env::var(SCENE_CONFIGURATION_FILE_NAME_ENV_VAR) {
         Ok(filename) => filename,
         _ => String::from(DEFAULT_SCENE_CONFIGURATION_FILE_NAME),
-    };
-    let home_configuration_builder =
-        Config::builder().add_source(config::File::with_name(configuration_file_name.as_str()));
-    home_configuration_builder
+    }
+}
+
+pub fn get_room_configurations() -> Result<HomeConfiguration, ConfigError> {
+    room_configuration_source()?.try_deserialize()
+}
+
+/// the scene configuration file as plain yaml, so it can be checked before it's deserialized
+pub fn get_raw_room_configurations() -> Result<serde_yaml::Value, ConfigError> {
+    room_configuration_source()?.try_deserialize()
+}
+
+fn room_configuration_source() -> Result<Config, ConfigError> {
+    let configuration_file_name = scene_configuration_file_name();
+    Config::builder()
+        .add_source(config::File::with_name(configuration_file_name.as_str()))
         .build()
-        .unwrap()
-        .try_deserialize()
 }
 
 #[cfg(test)]
@@ -73,6 +220,13 @@ mod tests {
             room_id: 0c329b86-a7fb-4765-8fdd-2e87f37da685
             grouped_light_room_id: ba8c44e4-0229-4888-8eeb-ce4a3d48cca8
             remotes: [2, 3]
+            bindings:
+            - button: favorite
+              press: long_press_complete
+              when: room_on
+              action:
+                type: recall_scene
+                scene: white_warmth
             scenes:
             - devices:
               - id: a3011bb2-dd50-4fd9-b143-7ea03f367088
@@ -81,6 +235,10 @@ mod tests {
               - name: Fireplace
                 'on': true
                 type: wemo_outlet
+              - id: 7
+                name: Reading Lamp
+                level: 60
+                type: caseta_dimmer
               - name: "Office Shapes"
                 internal_name: LightPanels 01:23:AF
                 'on': true
@@ -93,7 +251,8 @@ mod tests {
         assert_that(&room.name).is_equal_to(String::from("Living Room"));
         assert_that(&room.scenes).has_length(1);
         assert_that(&room.scenes[0].name).is_equal_to(String::from("white_warmth"));
-        assert_that(&room.scenes[0].devices).has_length(3);
+        assert_that(&room.scenes[0].devices).has_length(4);
+        assert_that(&room.bindings).has_length(1);
 
         assert!(matches!(room.scenes[0].devices[0], Device::HueScene { .. }));
         assert!(matches!(
@@ -102,6 +261,10 @@ mod tests {
         ));
         assert!(matches!(
             room.scenes[0].devices[2],
+            Device::CasetaDimmer { id: 7, .. }
+        ));
+        assert!(matches!(
+            room.scenes[0].devices[3],
             Device::NanoleafLightPanels { .. }
         ));
     }
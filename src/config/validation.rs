@@ -0,0 +1,605 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use anyhow::Result;
+use serde_yaml::Value;
+use uuid::Uuid;
+
+use crate::client::hue::HueClient;
+use crate::client::model::hue::{HueReference, HueRoom, HueScene};
+use crate::config::caseta_remote::{RemoteConfiguration, RemoteId};
+use crate::config::scene::{Device, HomeConfiguration, DEVICE_TYPES};
+
+/// something in the remote or scene configuration that would keep the listener from working
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopologyProblem {
+    UnknownRemote {
+        room: String,
+        remote_id: RemoteId,
+    },
+    RemoteInSeveralRooms {
+        remote_id: RemoteId,
+        rooms: Vec<String>,
+    },
+    NoScenes {
+        room: String,
+    },
+    DuplicateSceneName {
+        room: String,
+        scene: String,
+    },
+    UnknownDeviceType {
+        room: String,
+        scene: String,
+        device_type: String,
+    },
+    InvalidConfiguration(String),
+}
+
+impl Display for TopologyProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyProblem::UnknownRemote { room, remote_id } => write!(
+                f,
+                "room `{}` uses remote {}, but there's no remote with that id in the remote configuration",
+                room, remote_id
+            ),
+            TopologyProblem::RemoteInSeveralRooms { remote_id, rooms } => write!(
+                f,
+                "remote {} is in more than one room: {}. each remote can only control one room",
+                remote_id,
+                rooms.join(", ")
+            ),
+            TopologyProblem::NoScenes { room } => {
+                write!(f, "room `{}` needs at least one scene", room)
+            }
+            TopologyProblem::DuplicateSceneName { room, scene } => write!(
+                f,
+                "room `{}` has more than one scene named `{}`",
+                room, scene
+            ),
+            TopologyProblem::UnknownDeviceType {
+                room,
+                scene,
+                device_type,
+            } => write!(
+                f,
+                "scene `{}` in room `{}` has a device with unknown type `{}`. the known types are {}",
+                scene,
+                room,
+                device_type,
+                DEVICE_TYPES.join(", ")
+            ),
+            TopologyProblem::InvalidConfiguration(message) => {
+                write!(f, "the scene configuration can't be read: {}", message)
+            }
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("found {} problem(s) with the remote and scene configuration:{}", .problems.len(), ProblemList(.problems))]
+pub struct TopologyErrors {
+    pub problems: Vec<TopologyProblem>,
+}
+
+struct ProblemList<'a>(&'a [TopologyProblem]);
+
+impl Display for ProblemList<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for problem in self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the scene configuration against the remote configuration and reports every problem at
+/// once, so they can all be fixed before the listener connects to anything.
+///
+/// `home_configuration` is the raw scene configuration. Devices with an unknown type are reported
+/// and left out, so the rest of the configuration can still be checked.
+pub fn validate_topology(
+    remote_configuration: &RemoteConfiguration,
+    mut home_configuration: Value,
+) -> Result<HomeConfiguration, TopologyErrors> {
+    let mut problems = remove_unknown_devices(&mut home_configuration);
+    let home_configuration = match serde_yaml::from_value::<HomeConfiguration>(home_configuration) {
+        Ok(home_configuration) => home_configuration,
+        Err(e) => {
+            problems.push(TopologyProblem::InvalidConfiguration(e.to_string()));
+            return Err(TopologyErrors { problems });
+        }
+    };
+
+    let remote_ids: HashSet<RemoteId> = remote_configuration
+        .remotes
+        .iter()
+        .map(|remote| remote.id())
+        .collect();
+    let mut rooms_by_remote_id: HashMap<RemoteId, Vec<String>> = HashMap::new();
+    for room in home_configuration.rooms.iter() {
+        for remote_id in room.remotes.iter() {
+            if !remote_ids.contains(remote_id) {
+                problems.push(TopologyProblem::UnknownRemote {
+                    room: room.name.clone(),
+                    remote_id: *remote_id,
+                });
+            }
+            rooms_by_remote_id
+                .entry(*remote_id)
+                .or_default()
+                .push(room.name.clone());
+        }
+
+        if room.scenes.is_empty() {
+            problems.push(TopologyProblem::NoScenes {
+                room: room.name.clone(),
+            });
+        }
+        let mut scene_names = HashSet::new();
+        for scene in room.scenes.iter() {
+            if !scene_names.insert(scene.name.as_str()) {
+                problems.push(TopologyProblem::DuplicateSceneName {
+                    room: room.name.clone(),
+                    scene: scene.name.clone(),
+                });
+            }
+        }
+    }
+
+    let mut shared_remotes: Vec<(RemoteId, Vec<String>)> = rooms_by_remote_id
+        .into_iter()
+        .filter(|(_, rooms)| rooms.len() > 1)
+        .collect();
+    shared_remotes.sort_by_key(|(remote_id, _)| *remote_id);
+    problems.extend(
+        shared_remotes
+            .into_iter()
+            .map(|(remote_id, rooms)| TopologyProblem::RemoteInSeveralRooms { remote_id, rooms }),
+    );
+
+    if problems.is_empty() {
+        Ok(home_configuration)
+    } else {
+        Err(TopologyErrors { problems })
+    }
+}
+
+fn remove_unknown_devices(home_configuration: &mut Value) -> Vec<TopologyProblem> {
+    let mut problems = Vec::new();
+    let rooms = match home_configuration
+        .get_mut("rooms")
+        .and_then(Value::as_sequence_mut)
+    {
+        Some(rooms) => rooms,
+        None => return problems,
+    };
+    for room in rooms.iter_mut() {
+        let room_name = name_of(room);
+        let scenes = match room.get_mut("scenes").and_then(Value::as_sequence_mut) {
+            Some(scenes) => scenes,
+            None => continue,
+        };
+        for scene in scenes.iter_mut() {
+            let scene_name = name_of(scene);
+            if let Some(devices) = scene.get_mut("devices").and_then(Value::as_sequence_mut) {
+                devices.retain(|device| {
+                    let device_type = device
+                        .get("type")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    let known = DEVICE_TYPES.contains(&device_type);
+                    if !known {
+                        problems.push(TopologyProblem::UnknownDeviceType {
+                            room: room_name.clone(),
+                            scene: scene_name.clone(),
+                            device_type: device_type.to_string(),
+                        });
+                    }
+                    known
+                });
+            }
+        }
+    }
+    problems
+}
+
+fn name_of(value: &Value) -> String {
+    value
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("(unnamed)")
+        .to_string()
+}
+
+/// a hue id in the scene configuration that doesn't match what's on the bridge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BridgeProblem {
+    MissingRoom {
+        room: String,
+        room_id: Uuid,
+    },
+    MissingGroupedLight {
+        room: String,
+        grouped_light_room_id: Uuid,
+    },
+    GroupedLightInAnotherRoom {
+        room: String,
+        grouped_light_room_id: Uuid,
+        bridge_room: String,
+    },
+    MissingScene {
+        room: String,
+        scene: String,
+        scene_id: Uuid,
+    },
+    SceneInAnotherRoom {
+        room: String,
+        scene: String,
+        scene_id: Uuid,
+        bridge_room: String,
+    },
+}
+
+impl Display for BridgeProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BridgeProblem::MissingRoom { room, room_id } => write!(
+                f,
+                "room `{}` has room_id {}, but the bridge has no room with that id",
+                room, room_id
+            ),
+            BridgeProblem::MissingGroupedLight {
+                room,
+                grouped_light_room_id,
+            } => write!(
+                f,
+                "room `{}` has grouped_light_room_id {}, but no room on the bridge has that grouped light",
+                room, grouped_light_room_id
+            ),
+            BridgeProblem::GroupedLightInAnotherRoom {
+                room,
+                grouped_light_room_id,
+                bridge_room,
+            } => write!(
+                f,
+                "room `{}` has grouped_light_room_id {}, but that grouped light belongs to {} on the bridge",
+                room, grouped_light_room_id, bridge_room
+            ),
+            BridgeProblem::MissingScene {
+                room,
+                scene,
+                scene_id,
+            } => write!(
+                f,
+                "scene `{}` in room `{}` uses hue scene {}, which isn't on the bridge anymore",
+                scene, room, scene_id
+            ),
+            BridgeProblem::SceneInAnotherRoom {
+                room,
+                scene,
+                scene_id,
+                bridge_room,
+            } => write!(
+                f,
+                "scene `{}` in room `{}` uses hue scene {}, which belongs to {} on the bridge",
+                scene, room, scene_id, bridge_room
+            ),
+        }
+    }
+}
+
+/// a hue scene on the bridge that no configured scene uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnconfiguredScene {
+    pub bridge_room: String,
+    pub scene: String,
+    pub scene_id: Uuid,
+}
+
+impl Display for UnconfiguredScene {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hue scene `{}` ({}) in {}",
+            self.scene, self.scene_id, self.bridge_room
+        )
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BridgeReport {
+    pub problems: Vec<BridgeProblem>,
+    pub unconfigured_scenes: Vec<UnconfiguredScene>,
+}
+
+/// fetches the bridge's rooms and scenes and compares the configuration's hue ids against them
+pub async fn check_against_bridge(
+    home_configuration: &HomeConfiguration,
+    hue_client: &HueClient,
+) -> Result<BridgeReport> {
+    let bridge_rooms = hue_client.get_rooms().await?;
+    let bridge_scenes = hue_client.get_scenes().await?;
+    Ok(compare_with_bridge(
+        home_configuration,
+        &bridge_rooms,
+        &bridge_scenes,
+    ))
+}
+
+pub fn compare_with_bridge(
+    home_configuration: &HomeConfiguration,
+    bridge_rooms: &HashMap<Uuid, HueRoom>,
+    bridge_scenes: &[HueScene],
+) -> BridgeReport {
+    let bridge_room_name = |group: &HueReference| match group {
+        HueReference::Room(room_id) => bridge_rooms
+            .get(room_id)
+            .map(|room| format!("room `{}`", room.metadata.name))
+            .unwrap_or_else(|| format!("room {}", room_id)),
+        HueReference::Zone(zone_id) => format!("zone {}", zone_id),
+        other => format!("{:?}", other),
+    };
+    let rooms_by_grouped_light: HashMap<Uuid, &HueRoom> = bridge_rooms
+        .values()
+        .filter_map(|room| room.grouped_light_id().map(|id| (id, room)))
+        .collect();
+    let scenes_by_id: HashMap<Uuid, &HueScene> = bridge_scenes
+        .iter()
+        .map(|scene| (scene.id, scene))
+        .collect();
+
+    let mut report = BridgeReport::default();
+    let mut configured_scene_ids = HashSet::new();
+    for room in home_configuration.rooms.iter() {
+        if !bridge_rooms.contains_key(&room.room_id) {
+            report.problems.push(BridgeProblem::MissingRoom {
+                room: room.name.clone(),
+                room_id: room.room_id,
+            });
+        }
+        match rooms_by_grouped_light.get(&room.grouped_light_room_id) {
+            None => report.problems.push(BridgeProblem::MissingGroupedLight {
+                room: room.name.clone(),
+                grouped_light_room_id: room.grouped_light_room_id,
+            }),
+            Some(bridge_room) if bridge_room.id != room.room_id => {
+                report
+                    .problems
+                    .push(BridgeProblem::GroupedLightInAnotherRoom {
+                        room: room.name.clone(),
+                        grouped_light_room_id: room.grouped_light_room_id,
+                        bridge_room: bridge_room_name(&HueReference::Room(bridge_room.id)),
+                    })
+            }
+            Some(_) => {}
+        }
+
+        for scene in room.scenes.iter() {
+            for device in scene.devices.iter() {
+                let scene_id = match device {
+                    Device::HueScene { id, .. } => *id,
+                    _ => continue,
+                };
+                if !configured_scene_ids.insert(scene_id) {
+                    continue;
+                }
+                match scenes_by_id.get(&scene_id) {
+                    None => report.problems.push(BridgeProblem::MissingScene {
+                        room: room.name.clone(),
+                        scene: scene.name.clone(),
+                        scene_id,
+                    }),
+                    Some(bridge_scene) if !matches!(bridge_scene.group, HueReference::Room(id) if id == room.room_id) => {
+                        report.problems.push(BridgeProblem::SceneInAnotherRoom {
+                            room: room.name.clone(),
+                            scene: scene.name.clone(),
+                            scene_id,
+                            bridge_room: bridge_room_name(&bridge_scene.group),
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    report.unconfigured_scenes = bridge_scenes
+        .iter()
+        .filter(|scene| !configured_scene_ids.contains(&scene.id))
+        .map(|scene| UnconfiguredScene {
+            bridge_room: bridge_room_name(&scene.group),
+            scene: scene.metadata.name.clone(),
+            scene_id: scene.id,
+        })
+        .collect();
+    report
+        .unconfigured_scenes
+        .sort_by(|a, b| (&a.bridge_room, &a.scene).cmp(&(&b.bridge_room, &b.scene)));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_yaml::Value;
+    use spectral::prelude::*;
+    use uuid::Uuid;
+
+    use crate::client::model::hue::{HueRoom, HueScene};
+    use crate::config::caseta_remote::RemoteConfiguration;
+    use crate::config::scene::HomeConfiguration;
+    use crate::config::validation::{
+        compare_with_bridge, validate_topology, BridgeProblem, TopologyProblem, UnconfiguredScene,
+    };
+
+    const REMOTES: &str = r#"
+        remotes:
+        - id: 2
+          name: Living Room Pico
+          type: five_button_pico
+        - id: 3
+          name: Office Pico
+          type: two_button_pico
+    "#;
+
+    fn validate(home_configuration: &str) -> Result<usize, Vec<TopologyProblem>> {
+        let remotes: RemoteConfiguration = serde_yaml::from_str(REMOTES).unwrap();
+        let home_configuration: Value = serde_yaml::from_str(home_configuration).unwrap();
+        validate_topology(&remotes, home_configuration)
+            .map(|home_configuration| home_configuration.rooms.len())
+            .map_err(|errors| errors.problems)
+    }
+
+    #[test]
+    fn it_accepts_a_valid_configuration() {
+        let rooms = validate(
+            r#"
+            rooms:
+            - name: Living Room
+              room_id: 0c329b86-a7fb-4765-8fdd-2e87f37da685
+              grouped_light_room_id: ba8c44e4-0229-4888-8eeb-ce4a3d48cca8
+              remotes: [2]
+              scenes:
+              - name: warm
+                devices:
+                - type: hue_scene
+                  id: a3011bb2-dd50-4fd9-b143-7ea03f367088
+                  name: warm
+            "#,
+        );
+
+        assert_that(&rooms).is_equal_to(Ok(1));
+    }
+
+    #[test]
+    fn it_reports_every_problem_together() {
+        let problems = validate(
+            r#"
+            rooms:
+            - name: Living Room
+              room_id: 0c329b86-a7fb-4765-8fdd-2e87f37da685
+              grouped_light_room_id: ba8c44e4-0229-4888-8eeb-ce4a3d48cca8
+              remotes: [2, 7]
+              scenes:
+              - name: warm
+                devices:
+                - type: hue_scen
+                  id: a3011bb2-dd50-4fd9-b143-7ea03f367088
+                  name: warm
+              - name: warm
+                devices: []
+            - name: Office
+              room_id: 5f2a3c1e-6b1d-4c59-9a4b-0e1f2d3c4b5a
+              grouped_light_room_id: 9d8c7b6a-5f4e-4d3c-8b2a-1f0e9d8c7b6a
+              remotes: [2]
+              scenes: []
+            "#,
+        )
+        .unwrap_err();
+
+        assert_that(&problems).is_equal_to(vec![
+            TopologyProblem::UnknownDeviceType {
+                room: "Living Room".to_string(),
+                scene: "warm".to_string(),
+                device_type: "hue_scen".to_string(),
+            },
+            TopologyProblem::UnknownRemote {
+                room: "Living Room".to_string(),
+                remote_id: 7,
+            },
+            TopologyProblem::DuplicateSceneName {
+                room: "Living Room".to_string(),
+                scene: "warm".to_string(),
+            },
+            TopologyProblem::NoScenes {
+                room: "Office".to_string(),
+            },
+            TopologyProblem::RemoteInSeveralRooms {
+                remote_id: 2,
+                rooms: vec!["Living Room".to_string(), "Office".to_string()],
+            },
+        ]);
+    }
+
+    #[test]
+    fn it_compares_hue_ids_with_the_bridge() {
+        let living_room_id = Uuid::new_v4();
+        let office_id = Uuid::new_v4();
+        let office_grouped_light_id = Uuid::new_v4();
+        let warm_scene_id = Uuid::new_v4();
+        let focus_scene_id = Uuid::new_v4();
+        let stale_scene_id = Uuid::new_v4();
+        let bright_scene_id = Uuid::new_v4();
+        let home_configuration: HomeConfiguration = serde_yaml::from_str(&format!(
+            r#"
+            rooms:
+            - name: Living Room
+              room_id: {living_room_id}
+              grouped_light_room_id: {office_grouped_light_id}
+              remotes: [2]
+              scenes:
+              - name: warm
+                devices:
+                - {{type: hue_scene, id: {warm_scene_id}, name: warm}}
+                - {{type: hue_scene, id: {stale_scene_id}, name: stale}}
+              - name: focus
+                devices:
+                - {{type: hue_scene, id: {focus_scene_id}, name: focus}}
+            "#,
+        ))
+        .unwrap();
+        let room = |id: Uuid, name: &str, grouped_light_id: Uuid| -> (Uuid, HueRoom) {
+            let room = serde_json::from_str(&format!(
+                r#"{{"id":"{}","children":[],"services":[{{"rid":"{}","rtype":"grouped_light"}}],"metadata":{{"name":"{}","archetype":"other"}}}}"#,
+                id, grouped_light_id, name
+            ))
+            .unwrap();
+            (id, room)
+        };
+        let scene = |id: Uuid, name: &str, room_id: Uuid| -> HueScene {
+            serde_json::from_str(&format!(
+                r#"{{"id":"{}","metadata":{{"name":"{}"}},"group":{{"rid":"{}","rtype":"room"}}}}"#,
+                id, name, room_id
+            ))
+            .unwrap()
+        };
+        let bridge_rooms = HashMap::from([
+            room(living_room_id, "Living Room", Uuid::new_v4()),
+            room(office_id, "Office", office_grouped_light_id),
+        ]);
+        let bridge_scenes = vec![
+            scene(warm_scene_id, "warm", living_room_id),
+            scene(bright_scene_id, "bright", living_room_id),
+            scene(focus_scene_id, "focus", office_id),
+        ];
+
+        let report = compare_with_bridge(&home_configuration, &bridge_rooms, &bridge_scenes);
+
+        assert_that(&report.problems).is_equal_to(vec![
+            BridgeProblem::GroupedLightInAnotherRoom {
+                room: "Living Room".to_string(),
+                grouped_light_room_id: office_grouped_light_id,
+                bridge_room: "room `Office`".to_string(),
+            },
+            BridgeProblem::MissingScene {
+                room: "Living Room".to_string(),
+                scene: "warm".to_string(),
+                scene_id: stale_scene_id,
+            },
+            BridgeProblem::SceneInAnotherRoom {
+                room: "Living Room".to_string(),
+                scene: "focus".to_string(),
+                scene_id: focus_scene_id,
+                bridge_room: "room `Office`".to_string(),
+            },
+        ]);
+        assert_that(&report.unconfigured_scenes).is_equal_to(vec![UnconfiguredScene {
+            bridge_room: "room `Living Room`".to_string(),
+            scene: "bright".to_string(),
+            scene_id: bright_scene_id,
+        }]);
+    }
+}
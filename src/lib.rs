@@ -1,3 +1,7 @@
+pub mod api;
+pub mod backoff;
 pub mod caseta;
+pub mod cli;
 pub mod client;
 pub mod config;
+pub mod metrics;
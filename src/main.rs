@@ -1,36 +1,70 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::env;
+use std::process;
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Result};
+use caseta_listener::api::health::HealthChecks;
+use caseta_listener::api::{serve, HttpApi};
+use caseta_listener::backoff::ExponentialBackoff;
 use caseta_listener::caseta::connection::{
-    CasetaConnectionProvider, DefaultCasetaConnectionProvider, DefaultTcpSocketProvider,
-    DelegatingCasetaConnectionManager, ReadOnlyConnection,
+    DefaultCasetaConnectionProvider, DefaultTcpSocketProvider, DelegatingCasetaConnectionManager,
+    ReadOnlyConnection,
 };
+use caseta_listener::cli::{check_config, export_config, healthcheck, hue_pair};
+use caseta_listener::cli::{parse_arguments, Command, USAGE};
 use caseta_listener::client::room_state::new_cache;
 use tokio::sync::mpsc;
 use tracing::subscriber::set_global_default;
 use tracing::{debug, error, info, instrument, warn};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{EnvFilter, Registry};
 
 use caseta_listener::caseta::message::Message;
-use caseta_listener::caseta::remote::{remote_watcher_loop, RemoteWatcher};
+use caseta_listener::caseta::remote::{remote_watcher_loop, RemoteWatcher, RemoteWatchers};
 use caseta_listener::client::dispatcher::{dispatcher_loop, DeviceActionDispatcher};
+use caseta_listener::client::driver::{
+    CasetaDimmerDriver, DeviceDrivers, HueSceneDriver, NanoleafDriver, WemoDriver,
+};
+use caseta_listener::client::event_stream::{event_stream_loop, HueEventStreamListener};
+use caseta_listener::client::grouped_light_queue::GroupedLightQueue;
 use caseta_listener::client::hue::HueClient;
+use caseta_listener::client::hue_tls::HueTlsVerification;
+use caseta_listener::client::mqtt::{mqtt_bridge, mqtt_loop};
+use caseta_listener::client::nanoleaf::NanoleafClient;
+use caseta_listener::client::request_policy::{CircuitBreaker, RequestPolicy};
+use caseta_listener::client::wemo::WemoClient;
 use caseta_listener::config::auth_configuration::get_auth_configuration;
-use caseta_listener::config::caseta_remote::{
-    get_caseta_remote_configuration, ButtonAction, CasetaRemote, RemoteConfiguration, RemoteId,
+use caseta_listener::config::caseta_remote::{get_caseta_remote_configuration, ButtonAction};
+use caseta_listener::config::reload::{
+    load_topology, topology_files, topology_reload_loop, DEFAULT_POLL_INTERVAL,
 };
-use caseta_listener::config::scene::{get_room_configurations, HomeConfiguration, Topology};
-
-type RemoteWatcherDb = HashMap<RemoteId, Arc<RemoteWatcher>>;
+use caseta_listener::config::scene::{
+    build_topology, get_raw_room_configurations, DeviceKind, SharedTopology,
+};
+use caseta_listener::config::validation::{check_against_bridge, validate_topology};
+use caseta_listener::metrics::metrics;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let command = match parse_arguments(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    // subcommands print their results to stdout, so their logs go to stderr
+    let log_writer = match command {
+        Command::Listen => BoxMakeWriter::new(std::io::stdout),
+        _ => BoxMakeWriter::new(std::io::stderr),
+    };
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    let formatting_layer = BunyanFormattingLayer::new("caseta_listener".into(), std::io::stdout);
+    let formatting_layer = BunyanFormattingLayer::new("caseta_listener".into(), log_writer);
 
     let subscriber = Registry::default()
         .with(env_filter)
@@ -38,17 +72,36 @@ async fn main() -> Result<()> {
         .with(formatting_layer);
 
     set_global_default(subscriber).expect("Failed to set subscriber");
-    watch_caseta_events().await
+    match command {
+        Command::Listen => watch_caseta_events().await,
+        Command::HuePair(arguments) => hue_pair::run(arguments).await,
+        Command::ExportConfig(arguments) => export_config::run(arguments).await,
+        Command::CheckConfig(arguments) => check_config::run(arguments).await,
+        Command::Healthcheck(arguments) => healthcheck::run(arguments).await,
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
 }
 
 #[instrument]
 async fn watch_caseta_events() -> Result<()> {
     let auth_configuration = get_auth_configuration().unwrap();
     let caseta_remote_configuration = get_caseta_remote_configuration().unwrap();
-    let home_scene_configuration = get_room_configurations().unwrap();
-    let topology = Arc::new(build_topology(
-        caseta_remote_configuration,
-        home_scene_configuration,
+    let home_scene_configuration = validate_topology(
+        &caseta_remote_configuration,
+        get_raw_room_configurations().unwrap(),
+    )?;
+    let topology = SharedTopology::new(build_topology(
+        &caseta_remote_configuration,
+        &home_scene_configuration,
+    ));
+    tokio::spawn(topology_reload_loop(
+        topology.clone(),
+        topology_files(),
+        DEFAULT_POLL_INTERVAL,
+        load_topology,
     ));
 
     let caseta_address = auth_configuration.caseta_host.clone();
@@ -60,19 +113,127 @@ async fn watch_caseta_events() -> Result<()> {
         auth_configuration.caseta_password,
         tcp_socket_provider,
     );
+    let reconnect_configuration = &auth_configuration.caseta_reconnect;
     let mut connection =
-        DelegatingCasetaConnectionManager::new(Box::new(connection_manager_provider));
+        DelegatingCasetaConnectionManager::new(Box::new(connection_manager_provider))
+            .with_backoff(ExponentialBackoff::new(
+                reconnect_configuration.initial_delay(),
+                reconnect_configuration.max_delay(),
+            ))
+            .with_keep_alive(
+                auth_configuration.caseta_keep_alive.interval(),
+                auth_configuration.caseta_keep_alive.response_timeout(),
+            );
 
     let (action_sender, action_receiver) = mpsc::channel(64);
-    let mut remote_watchers: RemoteWatcherDb = HashMap::new();
+    let remote_watchers = RemoteWatchers::default();
     let hue_host = auth_configuration.hue_host;
     let hue_application_key = auth_configuration.hue_application_key;
-    let hue_client = HueClient::new(hue_host, hue_application_key);
-    let dispatcher = Arc::new(DeviceActionDispatcher::new(
+    let hue_request_configuration = &auth_configuration.hue_requests;
+    let hue_tls_verification = HueTlsVerification::try_from(&auth_configuration.hue_tls)?;
+    let hue_client = HueClient::new(hue_host, hue_application_key, &hue_tls_verification)
+        .with_request_policy(
+            RequestPolicy::new(
+                hue_request_configuration.timeout(),
+                hue_request_configuration.max_retries,
+                hue_request_configuration.initial_retry_delay(),
+                hue_request_configuration.max_retry_delay(),
+            )
+            .with_circuit_breaker(CircuitBreaker::new(
+                hue_request_configuration.circuit_breaker_failure_threshold,
+                hue_request_configuration.circuit_breaker_reset(),
+            )),
+        );
+    if auth_configuration.check_hue_ids_on_startup {
+        match check_against_bridge(&home_scene_configuration, &hue_client).await {
+            Ok(report) => {
+                for problem in report.problems.iter() {
+                    warn!("{}", problem);
+                }
+                for scene in report.unconfigured_scenes.iter() {
+                    info!("{} isn't used by any configured scene", scene);
+                }
+            }
+            Err(e) => warn!(error=%e, "unable to check the configured hue ids against the bridge"),
+        }
+    }
+    let current_room_state_cache = Arc::new(new_cache());
+    let mqtt_publisher = auth_configuration.mqtt.as_ref().map(|mqtt_configuration| {
+        let (publisher, listener) = mqtt_bridge(mqtt_configuration, action_sender.clone());
+        tokio::spawn(mqtt_loop(listener));
+        publisher
+    });
+    let nanoleaf_clients = auth_configuration
+        .nanoleaf_devices
+        .iter()
+        .map(|nanoleaf| {
+            (
+                nanoleaf.name.clone(),
+                NanoleafClient::new(&nanoleaf.host, nanoleaf.port, &nanoleaf.auth_token),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let wemo_clients = auth_configuration
+        .wemo_devices
+        .iter()
+        .map(|wemo| (wemo.name.clone(), WemoClient::new(&wemo.host, wemo.port)))
+        .collect::<HashMap<_, _>>();
+    let grouped_light_queue = GroupedLightQueue::new(hue_client.clone())
+        .with_command_interval(hue_request_configuration.grouped_light_command_interval());
+    let event_stream_listener = HueEventStreamListener::new(
+        hue_client.clone(),
+        topology.clone(),
+        current_room_state_cache.clone(),
+    )
+    .with_grouped_light_queue(grouped_light_queue.clone());
+    tokio::spawn(event_stream_loop(event_stream_listener));
+    let device_drivers = DeviceDrivers::new()
+        .with_driver(
+            DeviceKind::HueScene,
+            Arc::new(HueSceneDriver::new(
+                hue_client.clone(),
+                grouped_light_queue.clone(),
+            )),
+        )
+        .with_driver(
+            DeviceKind::CasetaDimmer,
+            Arc::new(CasetaDimmerDriver::new(connection.command_client())),
+        )
+        .with_driver(
+            DeviceKind::NanoleafLightPanels,
+            Arc::new(NanoleafDriver::new(nanoleaf_clients)),
+        )
+        .with_driver(
+            DeviceKind::WemoOutlet,
+            Arc::new(WemoDriver::new(wemo_clients)),
+        );
+    if let Some(address) = auth_configuration.http_api.address() {
+        let health_checks = HealthChecks::new(
+            connection.stats(),
+            hue_client.clone(),
+            auth_configuration.health_checks.clone(),
+        );
+        let http_api = HttpApi::new(
+            topology.clone(),
+            action_sender.clone(),
+            current_room_state_cache.clone(),
+            remote_watchers.clone(),
+        )
+        .with_health_checks(health_checks)
+        .with_device_drivers(device_drivers.clone());
+        serve(address, http_api)?;
+    }
+    let mut dispatcher = DeviceActionDispatcher::new(
         hue_client,
+        grouped_light_queue,
+        device_drivers,
         topology.clone(),
-        Arc::new(new_cache()),
-    ));
+        current_room_state_cache,
+    );
+    if let Some(mqtt_publisher) = mqtt_publisher.clone() {
+        dispatcher = dispatcher.with_mqtt_publisher(mqtt_publisher);
+    }
+    let dispatcher = Arc::new(dispatcher);
     tokio::spawn(dispatcher_loop(dispatcher, action_receiver));
     loop {
         let contents = connection.await_message().await;
@@ -82,16 +243,31 @@ async fn watch_caseta_events() -> Result<()> {
                 button_id,
                 button_action,
             })) => {
+                metrics().record_button_event(remote_id, button_id, button_action);
+                if let Some(mqtt_publisher) = &mqtt_publisher {
+                    mqtt_publisher.publish_button_event(remote_id, button_id, button_action);
+                }
                 let button_key = format!("{}-{}-{}", remote_id, button_id, button_action);
-                let room_configuration = topology.get(&remote_id);
-                if let None = room_configuration {
+                let current_topology = topology.current();
+                let room_configuration = current_topology.get(&remote_id);
+                if room_configuration.is_none() {
                     info!(
                         "ignoring unconfigured remote {{id: {}: button_action: {}}}",
                         remote_id, button_action
                     );
                     continue;
                 }
-                let (_remote, room) = room_configuration.unwrap();
+                let (remote, room) = room_configuration.unwrap();
+                if !remote.has_button(&button_id) {
+                    warn!(
+                        remote_id=%remote_id,
+                        button_id=%button_id,
+                        "ignoring a {} event for remote {}, which doesn't have that button",
+                        button_id,
+                        remote_id
+                    );
+                    continue;
+                }
                 debug!(
                     remote_id=%remote_id,
                     button_id=%button_id,
@@ -102,7 +278,7 @@ async fn watch_caseta_events() -> Result<()> {
                     room.name
                 );
 
-                match remote_watchers.entry(remote_id) {
+                match remote_watchers.lock().unwrap().entry(remote_id) {
                     Entry::Occupied(mut entry) => {
                         let remote_watcher = entry.get();
                         let remote_history = remote_watcher.remote_history.clone();
@@ -151,6 +327,18 @@ async fn watch_caseta_events() -> Result<()> {
                     }
                 }
             }
+            Ok(Some(
+                message @ (Message::DeviceUpdate { .. }
+                | Message::OutputUpdate { .. }
+                | Message::OccupancyUpdate { .. }
+                | Message::System { .. }
+                | Message::Monitoring { .. }),
+            )) => {
+                debug!(message_contents=%message, "observed a hub event: {}", message)
+            }
+            Ok(Some(Message::Error(integration_error))) => {
+                warn!(integration_error=%integration_error, "the caseta hub reported an error: {}", integration_error)
+            }
             Ok(Some(unexpected_contents)) => {
                 warn!(message_contents=%unexpected_contents, "got an unexpected message type: {}", unexpected_contents)
             }
@@ -168,32 +356,3 @@ async fn watch_caseta_events() -> Result<()> {
         }
     }
 }
-
-fn build_topology(
-    caseta_remote_configuration: RemoteConfiguration,
-    home_configuration: HomeConfiguration,
-) -> Topology {
-    let mut remotes_by_remote_id: HashMap<RemoteId, CasetaRemote> = HashMap::new();
-    for remote in caseta_remote_configuration.remotes.iter() {
-        match remote {
-            CasetaRemote::TwoButtonPico { id, .. } => {
-                remotes_by_remote_id.insert(*id, remote.clone());
-            }
-            CasetaRemote::FiveButtonPico { id, .. } => {
-                remotes_by_remote_id.insert(*id, remote.clone());
-            }
-        }
-    }
-
-    let mut topology: Topology = HashMap::new();
-    for room in home_configuration.rooms.iter() {
-        for remote_id in room.remotes.iter() {
-            let remote = remotes_by_remote_id
-                .get(&remote_id)
-                .expect(format!("no remote with id {} in our configuration", *remote_id).as_str());
-            topology.insert(*remote_id, (remote.clone(), room.clone()));
-        }
-    }
-
-    topology
-}
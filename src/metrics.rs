@@ -0,0 +1,284 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use serde::Serialize;
+
+use crate::client::dispatcher::DeviceAction;
+use crate::config::caseta_remote::{ButtonAction, ButtonId, RemoteId};
+
+pub const CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+// presses are classified somewhere between the double click window and the long press timeout
+const PRESS_CLASSIFICATION_BUCKETS: [f64; 9] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 5.0, 7.5];
+
+/// The prometheus metrics for the whole process, served at `/metrics`.
+///
+/// A press takes `caseta_press_classification_seconds` to become a [`DeviceAction`], then
+/// `dispatcher_duration_seconds` to become light.
+pub struct Metrics {
+    registry: Registry,
+    button_events: IntCounterVec,
+    device_actions: IntCounterVec,
+    press_classification: HistogramVec,
+    dispatches: IntCounterVec,
+    dispatch_duration: HistogramVec,
+    hue_request_duration: HistogramVec,
+    room_state_cache_lookups: IntCounterVec,
+    caseta_reconnects: IntCounter,
+    caseta_failed_connection_attempts: IntCounter,
+    caseta_missed_keep_alives: IntCounter,
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let metrics = Metrics {
+            button_events: IntCounterVec::new(
+                Opts::new(
+                    "caseta_button_events_total",
+                    "button events reported by the caseta hub",
+                ),
+                &["remote_id", "button_id", "button_action"],
+            )
+            .unwrap(),
+            device_actions: IntCounterVec::new(
+                Opts::new(
+                    "caseta_device_actions_total",
+                    "button presses classified as single, double and long presses",
+                ),
+                &["remote_id", "button_id", "device_action"],
+            )
+            .unwrap(),
+            press_classification: HistogramVec::new(
+                HistogramOpts::new(
+                    "caseta_press_classification_seconds",
+                    "time from a button's first press until it's classified",
+                )
+                .buckets(PRESS_CLASSIFICATION_BUCKETS.to_vec()),
+                &["device_action"],
+            )
+            .unwrap(),
+            dispatches: IntCounterVec::new(
+                Opts::new(
+                    "dispatcher_actions_total",
+                    "messages the dispatcher handled, by handler and outcome",
+                ),
+                &["handler", "outcome"],
+            )
+            .unwrap(),
+            dispatch_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "dispatcher_duration_seconds",
+                    "time the dispatcher takes to carry out a message",
+                ),
+                &["handler"],
+            )
+            .unwrap(),
+            hue_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "hue_request_duration_seconds",
+                    "hue bridge requests, including retries",
+                ),
+                &["endpoint", "status"],
+            )
+            .unwrap(),
+            room_state_cache_lookups: IntCounterVec::new(
+                Opts::new(
+                    "room_state_cache_lookups_total",
+                    "room state cache lookups, by hit or miss",
+                ),
+                &["result"],
+            )
+            .unwrap(),
+            caseta_reconnects: IntCounter::new(
+                "caseta_reconnects_total",
+                "connections to the caseta hub after the first one",
+            )
+            .unwrap(),
+            caseta_failed_connection_attempts: IntCounter::new(
+                "caseta_failed_connection_attempts_total",
+                "attempts to connect to the caseta hub that failed",
+            )
+            .unwrap(),
+            caseta_missed_keep_alives: IntCounter::new(
+                "caseta_missed_keep_alives_total",
+                "keep alives the caseta hub didn't answer in time",
+            )
+            .unwrap(),
+            registry,
+        };
+        metrics.register();
+        metrics
+    }
+
+    fn register(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(self.button_events.clone()),
+            Box::new(self.device_actions.clone()),
+            Box::new(self.press_classification.clone()),
+            Box::new(self.dispatches.clone()),
+            Box::new(self.dispatch_duration.clone()),
+            Box::new(self.hue_request_duration.clone()),
+            Box::new(self.room_state_cache_lookups.clone()),
+            Box::new(self.caseta_reconnects.clone()),
+            Box::new(self.caseta_failed_connection_attempts.clone()),
+            Box::new(self.caseta_missed_keep_alives.clone()),
+        ];
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .expect("metric names should be unique");
+        }
+    }
+
+    /// every metric in the prometheus text format
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("the text encoder shouldn't fail");
+        String::from_utf8(buffer).expect("the text encoder writes utf-8")
+    }
+
+    pub fn record_button_event(
+        &self,
+        remote_id: RemoteId,
+        button_id: ButtonId,
+        button_action: ButtonAction,
+    ) {
+        self.button_events
+            .with_label_values(&[
+                &remote_id.to_string(),
+                &label(button_id),
+                &label(button_action),
+            ])
+            .inc();
+    }
+
+    pub fn record_device_action(
+        &self,
+        remote_id: RemoteId,
+        button_id: ButtonId,
+        device_action: DeviceAction,
+        since_first_press: Duration,
+    ) {
+        let device_action = label(device_action);
+        self.device_actions
+            .with_label_values(&[&remote_id.to_string(), &label(button_id), &device_action])
+            .inc();
+        self.press_classification
+            .with_label_values(&[&device_action])
+            .observe(since_first_press.as_secs_f64());
+    }
+
+    pub fn record_dispatch(&self, handler: &str, succeeded: bool, duration: Duration) {
+        let outcome = match succeeded {
+            true => "success",
+            false => "error",
+        };
+        self.dispatches.with_label_values(&[handler, outcome]).inc();
+        self.dispatch_duration
+            .with_label_values(&[handler])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// `status` is the http status code, or `error` when there wasn't a response
+    pub fn record_hue_request(&self, endpoint: &str, status: &str, duration: Duration) {
+        self.hue_request_duration
+            .with_label_values(&[endpoint, status])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn record_room_state_cache_lookup(&self, hit: bool) {
+        let result = match hit {
+            true => "hit",
+            false => "miss",
+        };
+        self.room_state_cache_lookups
+            .with_label_values(&[result])
+            .inc();
+    }
+
+    pub fn record_caseta_reconnect(&self) {
+        self.caseta_reconnects.inc();
+    }
+
+    pub fn record_caseta_failed_connection_attempt(&self) {
+        self.caseta_failed_connection_attempts.inc();
+    }
+
+    pub fn record_caseta_missed_keep_alive(&self) {
+        self.caseta_missed_keep_alives.inc();
+    }
+}
+
+// the snake_case names from the configuration files and mqtt payloads, like `power_on` and
+// `double_press_complete`
+fn label<T: Serialize>(value: T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .expect("metric labels should serialize to strings")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use spectral::prelude::*;
+
+    use crate::client::dispatcher::DeviceAction;
+    use crate::config::caseta_remote::{ButtonAction, ButtonId};
+    use crate::metrics::metrics;
+
+    #[test]
+    fn it_encodes_recorded_metrics_in_the_text_format() {
+        // the metrics are shared by every test, so this sticks to labels nothing else uses
+        metrics().record_hue_request("test_endpoint", "503", Duration::from_millis(300));
+        metrics().record_dispatch("test_handler", false, Duration::from_millis(20));
+
+        let encoded = metrics().encode();
+
+        assert_that(&encoded).contains(
+            r#"hue_request_duration_seconds_bucket{endpoint="test_endpoint",status="503",le="0.5"} 1"#,
+        );
+        assert_that(&encoded).contains(
+            r#"hue_request_duration_seconds_bucket{endpoint="test_endpoint",status="503",le="0.25"} 0"#,
+        );
+        assert_that(&encoded)
+            .contains(r#"dispatcher_actions_total{handler="test_handler",outcome="error"} 1"#);
+        assert_that(&encoded).contains("# TYPE caseta_reconnects_total counter");
+    }
+
+    #[test]
+    fn it_labels_presses_with_their_configuration_names() {
+        // a remote id no other test uses
+        metrics().record_button_event(201, ButtonId::PowerOn, ButtonAction::Press);
+        metrics().record_device_action(
+            201,
+            ButtonId::PowerOn,
+            DeviceAction::DoublePressComplete,
+            Duration::from_millis(400),
+        );
+
+        let encoded = metrics().encode();
+
+        assert_that(&encoded).contains(
+            r#"caseta_button_events_total{button_action="press",button_id="power_on",remote_id="201"} 1"#,
+        );
+        assert_that(&encoded).contains(
+            r#"caseta_device_actions_total{button_id="power_on",device_action="double_press_complete",remote_id="201"} 1"#,
+        );
+        assert_that(&encoded).contains(
+            r#"caseta_press_classification_seconds_count{device_action="double_press_complete"}"#,
+        );
+    }
+}